@@ -0,0 +1,159 @@
+use std::{
+    fs::OpenOptions,
+    io::{self, Write},
+    net::SocketAddr,
+    path::Path,
+    sync::Mutex,
+    time::SystemTime,
+};
+
+use crate::{date::format_clf_time, request::Request, status::StatusCode};
+
+/// Logs one line per response in the NCSA Common Log Format:
+///
+/// `host - - [time] "METHOD path version" status bytes request-id`
+///
+/// `bytes` counts everything written for the response, headers included.
+/// The trailing request id is not part of the standard format, but log
+/// parsers generally ignore extra fields.
+pub struct AccessLog {
+    writer: Mutex<Box<dyn Write + Send>>,
+}
+
+impl AccessLog {
+    pub fn new(writer: Box<dyn Write + Send>) -> AccessLog {
+        AccessLog {
+            writer: Mutex::new(writer),
+        }
+    }
+
+    pub fn stdout() -> AccessLog {
+        AccessLog::new(Box::new(io::stdout()))
+    }
+
+    /// Append to the file at `path`, creating it if needed
+    pub fn to_file(path: &Path) -> io::Result<AccessLog> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(AccessLog::new(Box::new(file)))
+    }
+
+    /// Log a response of `bytes` bytes. `request` is `None` when the
+    /// request could not be parsed.
+    pub fn log(
+        &self,
+        peer: Option<SocketAddr>,
+        request: Option<&Request>,
+        status: StatusCode,
+        bytes: usize,
+    ) {
+        let line = format_entry(peer, request, status, bytes, SystemTime::now());
+        // Losing a log line is better than failing the request over it
+        let mut writer = self.writer.lock().unwrap();
+        let _ = writeln!(writer, "{}", line).and_then(|_| writer.flush());
+    }
+}
+
+/// Format a single log line, without its trailing newline. Unknown fields
+/// are written as `-`, as is a zero byte count.
+pub fn format_entry(
+    peer: Option<SocketAddr>,
+    request: Option<&Request>,
+    status: StatusCode,
+    bytes: usize,
+    time: SystemTime,
+) -> String {
+    let host = peer.map_or_else(|| "-".to_string(), |addr| addr.ip().to_string());
+    let request_line = request.map_or_else(
+        || "-".to_string(),
+        |req| format!("{} {} {}", req.method, req.target, req.version),
+    );
+    let request_id = request.map_or("-", |req| req.request_id.as_str());
+    let bytes = match bytes {
+        0 => "-".to_string(),
+        n => n.to_string(),
+    };
+
+    format!(
+        "{} - - [{}] \"{}\" {} {} {}",
+        host,
+        format_clf_time(time),
+        request_line,
+        status.as_u16(),
+        bytes,
+        request_id
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    use super::*;
+    use crate::request::parse_request_head;
+
+    /// 10 Oct 2000 13:55:36 UTC
+    const TIME: u64 = 971_186_136;
+
+    /// A writer whose output can still be read after it's handed off
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn request() -> Request {
+        let head = b"GET /index.html?lang=en HTTP/1.1\r\nHost: a\r\nX-Request-Id: abc-1\r\n\r\n";
+        parse_request_head(head, 100).unwrap()
+    }
+
+    #[test]
+    fn formats_an_entry_in_common_log_format() {
+        let peer = "192.0.2.7:51234".parse().ok();
+        let time = UNIX_EPOCH + Duration::from_secs(TIME);
+        assert_eq!(
+            format_entry(peer, Some(&request()), StatusCode::OK, 2326, time),
+            "192.0.2.7 - - [10/Oct/2000:13:55:36 +0000] \
+             \"GET /index.html?lang=en HTTP/1.1\" 200 2326 abc-1"
+        );
+    }
+
+    #[test]
+    fn unknown_fields_are_dashes() {
+        let time = UNIX_EPOCH + Duration::from_secs(TIME);
+        assert_eq!(
+            format_entry(None, None, StatusCode::BAD_REQUEST, 0, time),
+            "- - - [10/Oct/2000:13:55:36 +0000] \"-\" 400 - -"
+        );
+    }
+
+    #[test]
+    fn logs_one_line_per_response() {
+        let out = Shared::default();
+        let log = AccessLog::new(Box::new(out.clone()));
+        let peer = "[::1]:8000".parse().ok();
+        log.log(peer, Some(&request()), StatusCode::NOT_FOUND, 10);
+        log.log(peer, None, StatusCode::REQUEST_TIMEOUT, 0);
+
+        let written = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("::1 - - ["), "{}", lines[0]);
+        assert!(
+            lines[0].ends_with("] \"GET /index.html?lang=en HTTP/1.1\" 404 10 abc-1"),
+            "{}",
+            lines[0]
+        );
+        assert!(lines[1].ends_with("] \"-\" 408 - -"), "{}", lines[1]);
+        assert!(written.ends_with('\n'));
+    }
+}
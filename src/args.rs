@@ -0,0 +1,298 @@
+use std::{error::Error, fmt, str::FromStr, time::Duration};
+
+use crate::{
+    auth::BasicAuth, config::ServerConfig, cors::CorsConfig, log, log::Level, pool::RejectionPolicy,
+};
+
+const AUTH_REALM: &str = "webserver";
+
+pub const USAGE: &str = "\
+Usage: webserver [OPTIONS]
+
+Options override the config file, which the RUST_WS_* environment variables
+override in turn.
+
+General:
+  --config PATH               Load settings from a TOML config file
+  --help                      Print this help and exit
+
+Listening:
+  --addr ADDR                 Address to listen on; repeat for several
+  --port PORT                 Port to listen on
+  --unix-socket PATH          Listen on a Unix socket instead of TCP
+  --tls-cert PATH             PEM certificate chain to serve HTTPS with
+  --tls-key PATH              PEM private key for --tls-cert
+  --allow-plain-http          Serve plain HTTP on the TLS port too
+  --tcp-nodelay               Send small writes without waiting to fill a packet
+  --no-reuse-address          Don't set SO_REUSEADDR on listeners
+
+Workers:
+  --threads N                 Worker threads serving connections
+  --overflow POLICY           When the queue is full: block, reject or caller-runs
+  --worker-idle-timeout SECS  Let workers idle this long exit
+  --handler-timeout SECS      Answer handlers that run this long with a 503
+  --header-timeout SECS       Give clients this long to send a request head; 0 for no limit
+  --rate-limit N              Requests per second allowed from each client IP
+
+Static files:
+  --static-dir DIR            Serve files from DIR
+  --vhost HOST=DIR            Serve requests for HOST from DIR
+  --list-directories          List directories that have no index.html
+  --spa-fallback              Answer unknown extensionless paths with the root index.html
+  --no-directory-redirect     Don't add a trailing slash to directory paths
+  --file-cache-size BYTES     Most file contents kept in memory
+
+Responses:
+  --header 'NAME: VALUE'      Add a header to every response; repeat for several
+  --server-header VALUE       Send VALUE as the Server header
+  --no-server-header          Send no Server header
+  --cors-origin ORIGIN        Let pages from ORIGIN read responses; repeat for several
+
+Access:
+  --basic-auth USER:PASSWORD  Require these credentials for protected paths
+  --protect PATH              Protect PATH with --basic-auth; repeat for several
+  --admin-shutdown            Serve POST /admin/shutdown, which stops the server
+
+Debugging:
+  --dump-requests             Log each request, credentials redacted, at trace level
+  --dump-body-limit BYTES     Most bytes of each body to dump
+";
+
+/// What the command line asked for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// Start the server
+    Serve,
+    /// Print [`USAGE`] and exit
+    Help,
+}
+
+/// An argument that could not be parsed, with a message saying what was
+/// wrong with it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArgError(pub String);
+
+impl fmt::Display for ArgError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for ArgError {}
+
+/// Apply the command line options in `args` to `config`, which should
+/// already hold any settings from the config file and environment. An
+/// unknown option or a missing or invalid value is an error.
+///
+/// `--config` is skipped, as the file has to be loaded before the options
+/// can override it.
+pub fn parse_args(args: &[String], config: &mut ServerConfig) -> Result<Command, ArgError> {
+    let mut args = args.iter();
+    let mut addrs = Vec::new();
+    let mut protected = Vec::new();
+    let mut cors_origins = Vec::new();
+
+    while let Some(arg) = args.next() {
+        let mut value = |what: &str| {
+            args.next()
+                .map(String::as_str)
+                .ok_or_else(|| ArgError(format!("{} needs {}", arg, what)))
+        };
+        match arg.as_str() {
+            "--help" | "-h" => return Ok(Command::Help),
+            "--config" => {
+                value("a path")?;
+            }
+            "--addr" => addrs.push(value("an address")?.to_string()),
+            "--port" => config.port = parse(arg, value("a port number")?)?,
+            "--threads" => {
+                config.threads = match parse(arg, value("a number of threads")?)? {
+                    0 => return Err(ArgError("--threads needs at least one thread".into())),
+                    threads => threads,
+                }
+            }
+            "--static-dir" => config.static_dir = Some(value("a directory")?.into()),
+            "--vhost" => match value("HOST=DIR")?.split_once('=') {
+                Some((host, dir)) => config.virtual_hosts.push((host.to_string(), dir.into())),
+                None => return Err(ArgError("--vhost needs HOST=DIR".into())),
+            },
+            "--list-directories" => config.directory_listing = true,
+            "--spa-fallback" => config.spa_fallback = true,
+            "--no-directory-redirect" => config.redirect_directories = false,
+            "--admin-shutdown" => config.admin_shutdown = true,
+            "--tcp-nodelay" => config.tcp_nodelay = true,
+            "--no-reuse-address" => config.reuse_address = false,
+            "--overflow" => {
+                config.rejection_policy = value("block, reject or caller-runs")?
+                    .parse::<RejectionPolicy>()
+                    .map_err(|_| ArgError("--overflow needs block, reject or caller-runs".into()))?
+            }
+            "--file-cache-size" => {
+                config.file_cache_size = parse(arg, value("a number of bytes")?)?
+            }
+            "--tls-cert" => config.tls_cert = Some(value("a path")?.into()),
+            "--tls-key" => config.tls_key = Some(value("a path")?.into()),
+            "--allow-plain-http" => config.tls_allow_plain = true,
+            "--unix-socket" => config.unix_socket = Some(value("a path")?.into()),
+            "--basic-auth" => match value("user:password")?.split_once(':') {
+                Some((username, password)) => {
+                    config.basic_auth = Some(BasicAuth::new(AUTH_REALM, username, password))
+                }
+                None => return Err(ArgError("--basic-auth needs user:password".into())),
+            },
+            "--protect" => protected.push(value("a path")?.to_string()),
+            "--cors-origin" => cors_origins.push(value("an origin")?.to_string()),
+            "--header" => match value("Name: value")?.split_once(':') {
+                Some((name, value)) => config
+                    .default_headers
+                    .push((name.trim().to_string(), value.trim().to_string())),
+                None => return Err(ArgError("--header needs Name: value".into())),
+            },
+            "--server-header" => config.server_header = Some(value("a value")?.to_string()),
+            "--no-server-header" => config.server_header = None,
+            "--dump-requests" => config.dump_requests = true,
+            "--dump-body-limit" => {
+                config.dump_body_limit = parse(arg, value("a number of bytes")?)?
+            }
+            "--handler-timeout" => match parse(arg, value("a number of seconds")?)? {
+                0 => {
+                    return Err(ArgError(
+                        "--handler-timeout needs a positive number of seconds".into(),
+                    ))
+                }
+                secs => config.handler_timeout = Some(Duration::from_secs(secs)),
+            },
+            "--header-timeout" => match parse(arg, value("a number of seconds")?)? {
+                0 => config.header_timeout = None,
+                secs => config.header_timeout = Some(Duration::from_secs(secs)),
+            },
+            "--worker-idle-timeout" => {
+                let secs = parse(arg, value("a number of seconds")?)?;
+                config.worker_idle_timeout = Some(Duration::from_secs(secs));
+            }
+            "--rate-limit" => match parse::<f64>(arg, value("a positive number")?)? {
+                rate if rate > 0.0 => config.rate_limit = Some(rate),
+                _ => return Err(ArgError("--rate-limit needs a positive number".into())),
+            },
+            _ => return Err(ArgError(format!("unknown option {:?}", arg))),
+        }
+    }
+
+    if !addrs.is_empty() {
+        config.bind_addrs = addrs;
+    }
+    match config.basic_auth.as_mut() {
+        Some(auth) => auth.protected = protected,
+        None if !protected.is_empty() => {
+            log!(Level::Warn, "Ignoring --protect without --basic-auth")
+        }
+        None => {}
+    }
+    if !cors_origins.is_empty() {
+        config.cors = Some(CorsConfig::new(cors_origins));
+    }
+
+    Ok(Command::Serve)
+}
+
+fn parse<T: FromStr>(flag: &str, value: &str) -> Result<T, ArgError> {
+    value
+        .parse()
+        .map_err(|_| ArgError(format!("invalid value {:?} for {}", value, flag)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn parsed(argv: &[&str]) -> Result<(Command, ServerConfig), ArgError> {
+        let mut config = ServerConfig::default();
+        let command = parse_args(&args(argv), &mut config)?;
+        Ok((command, config))
+    }
+
+    #[test]
+    fn a_sample_command_line_fills_in_the_config() {
+        let (command, config) = parsed(&[
+            "--config",
+            "server.toml",
+            "--addr",
+            "127.0.0.1",
+            "--addr",
+            "::1",
+            "--port",
+            "8080",
+            "--threads",
+            "8",
+            "--static-dir",
+            "public",
+            "--header",
+            "X-Frame-Options: DENY",
+            "--no-server-header",
+            "--basic-auth",
+            "admin:s3cret:x",
+            "--protect",
+            "/admin",
+            "--header-timeout",
+            "0",
+        ])
+        .unwrap();
+
+        assert_eq!(command, Command::Serve);
+        assert_eq!(config.bind_addrs, ["127.0.0.1", "::1"]);
+        assert_eq!(config.port, 8080);
+        assert_eq!(config.threads, 8);
+        assert_eq!(config.static_dir, Some(PathBuf::from("public")));
+        assert_eq!(
+            config.default_headers,
+            [("X-Frame-Options".to_string(), "DENY".to_string())]
+        );
+        assert_eq!(config.server_header, None);
+        assert_eq!(config.header_timeout, None);
+        let auth = config.basic_auth.unwrap();
+        assert_eq!(
+            (auth.username.as_str(), auth.password.as_str()),
+            ("admin", "s3cret:x")
+        );
+        assert_eq!(auth.protected, ["/admin"]);
+    }
+
+    #[test]
+    fn no_arguments_leave_the_config_as_it_was() {
+        let (command, config) = parsed(&[]).unwrap();
+        assert_eq!(command, Command::Serve);
+        assert_eq!(config.bind_addrs, ServerConfig::default().bind_addrs);
+        assert_eq!(config.port, ServerConfig::default().port);
+    }
+
+    #[test]
+    fn help_stops_parsing() {
+        assert_eq!(parsed(&["--help", "--bogus"]).unwrap().0, Command::Help);
+        assert_eq!(parsed(&["-h"]).unwrap().0, Command::Help);
+    }
+
+    #[test]
+    fn invalid_arguments_say_what_is_wrong() {
+        let error = |argv: &[&str]| match parsed(argv) {
+            Err(error) => error.to_string(),
+            Ok((command, _)) => panic!("{:?} parsed as {:?}", argv, command),
+        };
+        assert_eq!(error(&["--bogus"]), "unknown option \"--bogus\"");
+        assert_eq!(error(&["--port"]), "--port needs a port number");
+        assert_eq!(
+            error(&["--port", "http"]),
+            "invalid value \"http\" for --port"
+        );
+        assert_eq!(
+            error(&["--threads", "0"]),
+            "--threads needs at least one thread"
+        );
+        assert_eq!(error(&["--vhost", "example.com"]), "--vhost needs HOST=DIR");
+    }
+}
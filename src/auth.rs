@@ -0,0 +1,227 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    middleware::Middleware, pages::error_response, request::Request, response::Response,
+    status::StatusCode,
+};
+
+/// HTTP Basic authentication for requests under a set of path prefixes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BasicAuth {
+    pub realm: String,
+    pub username: String,
+    pub password: String,
+    /// Paths needing credentials, each covering everything below it
+    pub protected: Vec<String>,
+}
+
+impl BasicAuth {
+    /// Credentials that protect nothing until paths are added with
+    /// [`BasicAuth::protect`]
+    pub fn new(realm: &str, username: &str, password: &str) -> BasicAuth {
+        BasicAuth {
+            realm: realm.to_string(),
+            username: username.to_string(),
+            password: password.to_string(),
+            protected: Vec::new(),
+        }
+    }
+
+    pub fn protect(mut self, prefix: &str) -> BasicAuth {
+        self.protected.push(prefix.to_string());
+        self
+    }
+
+    /// Whether `path` is at or below one of the protected prefixes. Whole
+    /// segments are compared, so `/admin` covers `/admin/users` but not
+    /// `/administrator`.
+    pub fn protects(&self, path: &str) -> bool {
+        self.protected.iter().any(|prefix| {
+            let prefix = prefix.trim_end_matches('/');
+            match path.strip_prefix(prefix) {
+                Some(rest) => prefix.is_empty() || rest.is_empty() || rest.starts_with('/'),
+                None => false,
+            }
+        })
+    }
+
+    /// Whether an `Authorization` header holds these credentials
+    pub fn authorized(&self, authorization: Option<&str>) -> bool {
+        let credentials = authorization
+            .and_then(|header| header.trim().strip_prefix("Basic "))
+            .and_then(|encoded| base64_decode(encoded.trim()));
+        let expected = format!("{}:{}", self.username, self.password);
+
+        match credentials {
+            Some(credentials) => constant_time_eq(&credentials, expected.as_bytes()),
+            None => false,
+        }
+    }
+
+    /// A `401` asking for credentials, if `request` needs them and doesn't
+    /// have them, with the page for it from `error_pages`
+    pub fn check(&self, request: &Request, error_pages: &Path) -> Option<Response> {
+        if !self.protects(&request.path) || self.authorized(request.headers.get("Authorization")) {
+            return None;
+        }
+
+        let challenge = format!("Basic realm=\"{}\"", self.realm.replace('"', "'"));
+        let response = error_response(error_pages, StatusCode::UNAUTHORIZED);
+        Some(response.header("WWW-Authenticate", &challenge))
+    }
+}
+
+/// Middleware asking for [`BasicAuth`] credentials on protected paths
+pub struct Authenticate {
+    pub auth: BasicAuth,
+    /// Where the `401` page is read from
+    pub error_pages: PathBuf,
+}
+
+impl Middleware for Authenticate {
+    fn handle(&self, req: Request, next: &dyn Fn(Request) -> Response) -> Response {
+        match self.auth.check(&req, &self.error_pages) {
+            Some(challenge) => challenge,
+            None => next(req),
+        }
+    }
+}
+
+/// Compare without returning early, so the time taken doesn't give away how
+/// much of a guess was right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    let len = a.len().max(b.len());
+    let mut difference = a.len() ^ b.len();
+    for i in 0..len {
+        let x = a.get(i).copied().unwrap_or(0);
+        let y = b.get(i).copied().unwrap_or(0);
+        difference |= usize::from(x ^ y);
+    }
+    difference == 0
+}
+
+/// Decode standard, padded base64. Returns `None` for anything malformed.
+pub fn base64_decode(input: &str) -> Option<Vec<u8>> {
+    let input = input.as_bytes();
+    if !input.len().is_multiple_of(4) {
+        return None;
+    }
+
+    let mut output = Vec::with_capacity(input.len() / 4 * 3);
+    for (i, chunk) in input.chunks(4).enumerate() {
+        let last = i == input.len() / 4 - 1;
+        let padding = chunk.iter().rev().take_while(|&&b| b == b'=').count();
+        if padding > 2 || (padding > 0 && !last) {
+            return None;
+        }
+
+        let mut group = 0u32;
+        for &byte in &chunk[..4 - padding] {
+            group = group << 6 | u32::from(base64_value(byte)?);
+        }
+        group <<= 6 * padding as u32;
+
+        let bytes = group.to_be_bytes();
+        output.extend_from_slice(&bytes[1..4 - padding]);
+    }
+
+    Some(output)
+}
+
+fn base64_value(byte: u8) -> Option<u8> {
+    match byte {
+        b'A'..=b'Z' => Some(byte - b'A'),
+        b'a'..=b'z' => Some(byte - b'a' + 26),
+        b'0'..=b'9' => Some(byte - b'0' + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::parse_request_head;
+
+    /// "Aladdin:open sesame", the example from RFC 7617
+    const ALADDIN: &str = "QWxhZGRpbjpvcGVuIHNlc2FtZQ==";
+
+    /// Somewhere without page files, so the built-in pages are used
+    fn no_pages() -> &'static Path {
+        Path::new("no-such-directory")
+    }
+
+    fn auth() -> BasicAuth {
+        BasicAuth::new("admin \"area\"", "Aladdin", "open sesame").protect("/admin")
+    }
+
+    fn request(path: &str, authorization: Option<&str>) -> Request {
+        let header = authorization
+            .map(|value| format!("Authorization: {}\r\n", value))
+            .unwrap_or_default();
+        let head = format!("GET {} HTTP/1.1\r\nHost: a\r\n{}\r\n", path, header);
+        parse_request_head(head.as_bytes(), 100).unwrap()
+    }
+
+    #[test]
+    fn base64_decodes_with_and_without_padding() {
+        assert_eq!(base64_decode("").unwrap(), b"");
+        assert_eq!(base64_decode("Zg==").unwrap(), b"f");
+        assert_eq!(base64_decode("Zm8=").unwrap(), b"fo");
+        assert_eq!(base64_decode("Zm9v").unwrap(), b"foo");
+        assert_eq!(base64_decode("+/+/").unwrap(), [0xfb, 0xff, 0xbf]);
+        assert_eq!(base64_decode(ALADDIN).unwrap(), b"Aladdin:open sesame");
+    }
+
+    #[test]
+    fn malformed_base64_is_rejected() {
+        assert_eq!(base64_decode("Zm9"), None);
+        assert_eq!(base64_decode("Zm9v!A=="), None);
+        assert_eq!(base64_decode("Z==="), None);
+        assert_eq!(base64_decode("Zg==Zm9v"), None);
+    }
+
+    #[test]
+    fn valid_credentials_are_let_through() {
+        let auth = auth();
+        let basic = format!("Basic {}", ALADDIN);
+        assert!(auth.authorized(Some(&basic)));
+        assert!(auth
+            .check(&request("/admin/users", Some(&basic)), no_pages())
+            .is_none());
+    }
+
+    #[test]
+    fn a_wrong_password_gets_a_401() {
+        // "Aladdin:open sesame!"
+        let basic = "Basic QWxhZGRpbjpvcGVuIHNlc2FtZSE=";
+        let response = auth()
+            .check(&request("/admin", Some(basic)), no_pages())
+            .unwrap();
+        assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            response.headers.get("WWW-Authenticate"),
+            Some("Basic realm=\"admin 'area'\"")
+        );
+    }
+
+    #[test]
+    fn a_missing_header_gets_a_401_only_on_protected_paths() {
+        let auth = auth();
+        let response = auth.check(&request("/admin", None), no_pages()).unwrap();
+        assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+        assert!(auth
+            .check(&request("/administrator", None), no_pages())
+            .is_none());
+        assert!(auth.check(&request("/", None), no_pages()).is_none());
+    }
+
+    #[test]
+    fn credentials_must_match_exactly() {
+        assert!(constant_time_eq(b"user:pass", b"user:pass"));
+        assert!(!constant_time_eq(b"user:pass", b"user:pas"));
+        assert!(!constant_time_eq(b"user:pass", b"user:pasS"));
+        assert!(!auth().authorized(Some("Bearer token")));
+    }
+}
@@ -0,0 +1,127 @@
+use std::io::Write;
+
+use flate2::{write::GzEncoder, Compression};
+
+use crate::{middleware::Middleware, request::Request, response::Response};
+
+/// Compress `body` with gzip if `accept_encoding`, the request's
+/// `Accept-Encoding` header, allows it. Returns the body to send and the
+/// `Content-Encoding` it was sent with, if any.
+pub fn maybe_compress(
+    accept_encoding: Option<&str>,
+    body: Vec<u8>,
+) -> (Vec<u8>, Option<&'static str>) {
+    if !accept_encoding.is_some_and(accepts_gzip) {
+        return (body, None);
+    }
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    // Writing to a Vec shouldn't fail, but sending the raw body is always safe
+    match encoder.write_all(&body).and_then(|_| encoder.finish()) {
+        Ok(compressed) => (compressed, Some("gzip")),
+        Err(_) => (body, None),
+    }
+}
+
+/// Whether an `Accept-Encoding` value lists gzip (or `*`) without `q=0`
+pub fn accepts_gzip(accept_encoding: &str) -> bool {
+    accept_encoding.split(',').any(|coding| {
+        let mut parts = coding.split(';').map(str::trim);
+        let name = parts.next().unwrap_or("");
+        let acceptable =
+            name.eq_ignore_ascii_case("gzip") || name.eq_ignore_ascii_case("x-gzip") || name == "*";
+
+        acceptable
+            && parts
+                .filter_map(|param| param.strip_prefix("q="))
+                .all(|q| q.parse::<f32>().is_ok_and(|q| q > 0.0))
+    })
+}
+
+/// Middleware that gzips responses of at least `min_size` bytes for clients
+/// that accept it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Compress {
+    pub min_size: usize,
+}
+
+impl Middleware for Compress {
+    fn handle(&self, req: Request, next: &dyn Fn(Request) -> Response) -> Response {
+        let accept_encoding = req.headers.get("Accept-Encoding").map(String::from);
+        next(req).compress(accept_encoding.as_deref(), self.min_size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+
+    use super::*;
+    use crate::status::StatusCode;
+
+    fn gunzip(body: &[u8]) -> Vec<u8> {
+        let mut decoded = Vec::new();
+        GzDecoder::new(body).read_to_end(&mut decoded).unwrap();
+        decoded
+    }
+
+    fn page() -> Vec<u8> {
+        "<p>hello</p>".repeat(200).into_bytes()
+    }
+
+    #[test]
+    fn a_gzip_client_gets_a_compressed_body() {
+        let (body, encoding) = maybe_compress(Some("gzip, deflate"), page());
+        assert_eq!(encoding, Some("gzip"));
+        assert!(body.len() < page().len());
+        assert_eq!(gunzip(&body), page());
+    }
+
+    #[test]
+    fn other_clients_get_the_raw_body() {
+        for accept_encoding in [None, Some("br, deflate"), Some("gzip;q=0"), Some("")] {
+            assert_eq!(
+                maybe_compress(accept_encoding, page()),
+                (page(), None),
+                "{:?}",
+                accept_encoding
+            );
+        }
+    }
+
+    #[test]
+    fn gzip_is_accepted_by_name_wildcard_or_weight() {
+        assert!(accepts_gzip("GZIP"));
+        assert!(accepts_gzip("x-gzip"));
+        assert!(accepts_gzip("*"));
+        assert!(accepts_gzip("br;q=1.0, gzip;q=0.5"));
+        assert!(!accepts_gzip("gzip;q=0.0"));
+        assert!(!accepts_gzip("identity"));
+    }
+
+    #[test]
+    fn compressed_responses_are_sent_with_their_new_length() {
+        let mut response = Response::new(StatusCode::OK)
+            .body(page())
+            .compress(Some("gzip"), 100);
+        assert_eq!(response.headers.get("Content-Encoding"), Some("gzip"));
+        assert_eq!(response.headers.get("Vary"), Some("Accept-Encoding"));
+        let compressed_len = response.body.len();
+
+        let mut out = Vec::new();
+        response.write_to(&mut out).unwrap();
+        let written = String::from_utf8_lossy(&out);
+        assert!(written.contains(&format!("\r\nContent-Length: {}\r\n", compressed_len)));
+    }
+
+    #[test]
+    fn bodies_under_the_minimum_size_are_left_alone() {
+        let response = Response::new(StatusCode::OK)
+            .body("tiny")
+            .compress(Some("gzip"), 100);
+        assert_eq!(response.body, b"tiny");
+        assert!(!response.headers.contains("Content-Encoding"));
+    }
+}
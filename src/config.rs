@@ -0,0 +1,621 @@
+use std::{
+    convert::TryFrom,
+    env,
+    error::Error,
+    fmt, fs, io,
+    net::{SocketAddr, TcpListener, ToSocketAddrs},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{
+    auth::BasicAuth,
+    cors::CorsConfig,
+    log,
+    log::Level,
+    pool::{RejectionPolicy, DEFAULT_QUEUE_BOUND},
+    request::Limits,
+    socket::bind_tcp,
+    toml::{self, Entry, TomlError, Value},
+};
+
+pub const DEFAULT_BIND_ADDR: &str = "127.0.0.1";
+pub const DEFAULT_PORT: u16 = 7878;
+pub const DEFAULT_WORKERS: u16 = 5;
+pub const DEFAULT_MAX_HEADER_SIZE: usize = 8 * 1024;
+pub const DEFAULT_MAX_HEADERS: usize = 100;
+pub const DEFAULT_MAX_BODY_SIZE: usize = 1024 * 1024;
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+pub const DEFAULT_KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(5);
+pub const DEFAULT_HEADER_TIMEOUT: Duration = Duration::from_secs(10);
+pub const DEFAULT_MAX_REQUESTS_PER_CONNECTION: usize = 100;
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+pub const DEFAULT_COMPRESSION_MIN_SIZE: usize = 1024;
+pub const DEFAULT_MAX_CONNECTIONS: usize = 256;
+pub const DEFAULT_RATE_LIMIT_BURST: u32 = 20;
+pub const DEFAULT_MIN_WORKERS: u16 = 1;
+pub const DEFAULT_FILE_CACHE_SIZE: usize = 16 * 1024 * 1024;
+pub const DEFAULT_DUMP_BODY_LIMIT: usize = 1024;
+/// What the `Server` header says unless configured otherwise
+pub const SERVER_NAME: &str = concat!("rust-webserver/", env!("CARGO_PKG_VERSION"));
+
+const ADDR_ENV_VAR: &str = "RUST_WS_ADDR";
+const PORT_ENV_VAR: &str = "RUST_WS_PORT";
+
+pub struct ServerConfig {
+    /// Addresses to listen on, all with the same `port`. `::` or `[::]`
+    /// covers IPv6, and IPv4 too on dual-stack hosts.
+    pub bind_addrs: Vec<String>,
+    pub port: u16,
+    /// Set `SO_REUSEADDR` on listeners, so a restarted server can bind its
+    /// port while connections from the last run are still in `TIME_WAIT`
+    pub reuse_address: bool,
+    /// Set `TCP_NODELAY` on accepted connections. Small writes are sent
+    /// straight away instead of being held back to fill a packet, which
+    /// cuts latency at the cost of sending more, smaller packets.
+    pub tcp_nodelay: bool,
+    /// Worker threads serving connections
+    pub threads: u16,
+    /// Least severe messages logged. `RUST_WS_LOG` overrides it, and Info
+    /// is used when both are unset.
+    pub log_level: Option<Level>,
+    /// Log each request as it was received at `Level::Trace`, with
+    /// credentials redacted
+    pub dump_requests: bool,
+    /// Most bytes of a request body included in its dump
+    pub dump_body_limit: usize,
+    /// Largest request line plus header block accepted, in bytes
+    pub max_header_size: usize,
+    /// Most header lines accepted in one request
+    pub max_headers: usize,
+    /// Largest request body accepted, in bytes
+    pub max_body_size: usize,
+    /// Directory holding pages for error statuses, such as `404.html`.
+    /// Statuses without one get a built-in page.
+    pub error_pages: PathBuf,
+    /// Directory to serve files from. Static serving is off when unset.
+    pub static_dir: Option<PathBuf>,
+    /// Sites with static directories of their own, by host name. Requests
+    /// for any other host are served from `static_dir`.
+    pub virtual_hosts: Vec<(String, PathBuf)>,
+    /// List the contents of static directories that have no `index.html`.
+    /// Off by default, as a listing can expose files nothing links to.
+    pub directory_listing: bool,
+    /// Answer static paths that don't exist and have no file extension with
+    /// the root `index.html`, so a single-page app can route them itself
+    pub spa_fallback: bool,
+    /// Redirect static directory paths without a trailing slash to ones
+    /// with it, so relative links in their `index.html` resolve inside the
+    /// directory
+    pub redirect_directories: bool,
+    /// Most bytes of static file contents kept in memory. Nothing is cached
+    /// when 0.
+    pub file_cache_size: usize,
+    /// How long a read from a client may block before the connection is
+    /// given up on
+    pub read_timeout: Duration,
+    /// How long a write to a client may block
+    pub write_timeout: Duration,
+    /// How long a client may take to send a whole request head, however
+    /// slowly it trickles in. Clients that take longer are sent a `408`.
+    /// Only the read timeout applies when unset.
+    pub header_timeout: Option<Duration>,
+    /// How long a handler may run before its client is sent a `503` and
+    /// hung up on. Handlers run as long as they like when unset.
+    pub handler_timeout: Option<Duration>,
+    /// How long a persistent connection may sit idle between requests
+    pub keep_alive_timeout: Duration,
+    /// Requests served on one connection before it is closed
+    pub max_requests_per_connection: usize,
+    /// Connections that may wait for a free worker before
+    /// `rejection_policy` applies to new ones
+    pub queue_bound: usize,
+    /// What happens to a connection that finds the queue full
+    pub rejection_policy: RejectionPolicy,
+    /// How long in-flight connections get to finish when shutting down
+    pub shutdown_timeout: Duration,
+    /// File to append the access log to. The log goes to stdout when unset.
+    pub access_log: Option<PathBuf>,
+    /// Smallest response body, in bytes, worth gzip compressing
+    pub compression_min_size: usize,
+    /// PEM certificate chain to serve HTTPS with. TLS is only used when
+    /// both this and `tls_key` are set.
+    pub tls_cert: Option<PathBuf>,
+    /// PEM private key for `tls_cert`
+    pub tls_key: Option<PathBuf>,
+    /// Serve plain HTTP on the TLS listeners too, telling each connection's
+    /// protocol apart by its first byte
+    pub tls_allow_plain: bool,
+    /// Unix socket to listen on instead of TCP. Only used on Unix.
+    pub unix_socket: Option<PathBuf>,
+    /// Connections served at once. Any more are answered with a 503 and
+    /// closed straight away.
+    pub max_connections: usize,
+    /// Requests per second allowed from each client IP. There is no limit
+    /// when unset.
+    pub rate_limit: Option<f64>,
+    /// Requests a client may make in a burst before `rate_limit` applies
+    pub rate_limit_burst: u32,
+    /// Credentials required for protected paths
+    pub basic_auth: Option<BasicAuth>,
+    /// Cross-origin access for browsers. Only same-origin pages can read
+    /// responses when unset.
+    pub cors: Option<CorsConfig>,
+    /// Serve `POST /admin/shutdown`, which stops the server. Protect it with
+    /// `basic_auth` on anything but a trusted network.
+    pub admin_shutdown: bool,
+    /// Headers added to every response that doesn't already set them, such
+    /// as `X-Content-Type-Options` or a `Content-Security-Policy`
+    pub default_headers: Vec<(String, String)>,
+    /// `Server` header sent with every response that doesn't set its own.
+    /// None is sent when unset.
+    pub server_header: Option<String>,
+    /// How long a worker may sit idle before it exits. Workers never exit
+    /// on their own when unset.
+    pub worker_idle_timeout: Option<Duration>,
+    /// Workers kept however long they are idle
+    pub min_workers: u16,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind_addrs: vec![DEFAULT_BIND_ADDR.to_string()],
+            port: DEFAULT_PORT,
+            reuse_address: true,
+            tcp_nodelay: false,
+            threads: DEFAULT_WORKERS,
+            log_level: None,
+            dump_requests: false,
+            dump_body_limit: DEFAULT_DUMP_BODY_LIMIT,
+            max_header_size: DEFAULT_MAX_HEADER_SIZE,
+            max_headers: DEFAULT_MAX_HEADERS,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            error_pages: PathBuf::from("."),
+            static_dir: None,
+            virtual_hosts: Vec::new(),
+            directory_listing: false,
+            spa_fallback: false,
+            redirect_directories: true,
+            file_cache_size: DEFAULT_FILE_CACHE_SIZE,
+            read_timeout: DEFAULT_TIMEOUT,
+            write_timeout: DEFAULT_TIMEOUT,
+            header_timeout: Some(DEFAULT_HEADER_TIMEOUT),
+            handler_timeout: None,
+            keep_alive_timeout: DEFAULT_KEEP_ALIVE_TIMEOUT,
+            max_requests_per_connection: DEFAULT_MAX_REQUESTS_PER_CONNECTION,
+            queue_bound: DEFAULT_QUEUE_BOUND,
+            rejection_policy: RejectionPolicy::Reject,
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+            access_log: None,
+            compression_min_size: DEFAULT_COMPRESSION_MIN_SIZE,
+            tls_cert: None,
+            tls_key: None,
+            tls_allow_plain: false,
+            unix_socket: None,
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            rate_limit: None,
+            rate_limit_burst: DEFAULT_RATE_LIMIT_BURST,
+            basic_auth: None,
+            cors: None,
+            admin_shutdown: false,
+            default_headers: Vec::new(),
+            server_header: Some(SERVER_NAME.to_string()),
+            worker_idle_timeout: None,
+            min_workers: DEFAULT_MIN_WORKERS,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// The size limits requests are read with
+    pub fn limits(&self) -> Limits {
+        Limits {
+            max_header_size: self.max_header_size,
+            max_headers: self.max_headers,
+            max_body_size: self.max_body_size,
+            header_timeout: self.header_timeout,
+            read_timeout: Some(self.read_timeout),
+        }
+    }
+
+    /// Build a config from `RUST_WS_ADDR` (a comma separated list of
+    /// addresses) and `RUST_WS_PORT`, falling back to the defaults for
+    /// anything unset or unparseable
+    pub fn from_env() -> ServerConfig {
+        let mut config = ServerConfig::default();
+        config.apply_env();
+        config
+    }
+
+    /// Load a TOML config file, using the defaults for anything it leaves
+    /// out. Settings are grouped into tables:
+    ///
+    /// ```toml
+    /// [server]
+    /// bind = ["127.0.0.1", "::1"]
+    /// port = 8080
+    /// threads = 8
+    /// error_pages = "errors"
+    ///
+    /// [static]
+    /// root = "public"
+    /// directory_listing = true
+    /// spa_fallback = false
+    ///
+    /// [tls]
+    /// cert = "cert.pem"
+    /// key = "key.pem"
+    /// allow_plain = false
+    ///
+    /// [log]
+    /// level = "debug"
+    /// access_log = "access.log"
+    /// ```
+    ///
+    /// Unknown settings are an error, so a misspelt one isn't silently
+    /// ignored.
+    pub fn from_file(path: &Path) -> Result<ServerConfig, ConfigError> {
+        let text = fs::read_to_string(path).map_err(ConfigError::Io)?;
+        ServerConfig::from_toml(&text)
+    }
+
+    /// Parse the contents of a config file, as [`ServerConfig::from_file`]
+    /// does
+    pub fn from_toml(text: &str) -> Result<ServerConfig, ConfigError> {
+        let mut config = ServerConfig::default();
+        for entry in toml::parse(text).map_err(ConfigError::Syntax)? {
+            config.set(&entry)?;
+        }
+        Ok(config)
+    }
+
+    fn set(&mut self, entry: &Entry) -> Result<(), ConfigError> {
+        match entry.key.as_str() {
+            "server.bind" => {
+                self.bind_addrs = match &entry.value {
+                    Value::String(addr) => vec![addr.clone()],
+                    _ => strings(entry)?,
+                }
+            }
+            "server.port" => self.port = integer(entry)?,
+            "server.threads" => self.threads = integer(entry)?,
+            "server.error_pages" => self.error_pages = string(entry)?.into(),
+            "static.root" => self.static_dir = Some(string(entry)?.into()),
+            "static.directory_listing" => self.directory_listing = boolean(entry)?,
+            "static.spa_fallback" => self.spa_fallback = boolean(entry)?,
+            "tls.cert" => self.tls_cert = Some(string(entry)?.into()),
+            "tls.key" => self.tls_key = Some(string(entry)?.into()),
+            "tls.allow_plain" => self.tls_allow_plain = boolean(entry)?,
+            "log.level" => {
+                let level = string(entry)?.parse().map_err(|e| invalid(entry, e))?;
+                self.log_level = Some(level);
+            }
+            "log.access_log" => self.access_log = Some(string(entry)?.into()),
+            "log.dump_requests" => self.dump_requests = boolean(entry)?,
+            "log.dump_body_limit" => self.dump_body_limit = integer(entry)?,
+            _ => return Err(invalid(entry, "unknown setting")),
+        }
+        Ok(())
+    }
+
+    /// Override settings with `RUST_WS_ADDR` (a comma separated list of
+    /// addresses) and `RUST_WS_PORT`, where they are set and parse
+    pub fn apply_env(&mut self) {
+        if let Ok(addrs) = env::var(ADDR_ENV_VAR) {
+            self.bind_addrs = addrs
+                .split(',')
+                .map(str::trim)
+                .filter(|addr| !addr.is_empty())
+                .map(String::from)
+                .collect();
+        }
+
+        if let Ok(port) = env::var(PORT_ENV_VAR) {
+            match port.parse() {
+                Ok(port) => self.port = port,
+                Err(e) => log!(
+                    Level::Warn,
+                    "Ignoring invalid {} {:?} ({}), using {}",
+                    PORT_ENV_VAR,
+                    port,
+                    e,
+                    self.port
+                ),
+            }
+        }
+    }
+
+    /// Resolve each configured address, with the port, into a socket
+    /// address
+    pub fn socket_addrs(&self) -> Vec<io::Result<SocketAddr>> {
+        self.bind_addrs
+            .iter()
+            .map(|addr| resolve(addr, self.port))
+            .collect()
+    }
+
+    /// Bind a listener on every configured address. Addresses that fail to
+    /// resolve or bind are logged and skipped; it is only an error if none
+    /// of them could be bound.
+    pub fn bind(&self) -> io::Result<Vec<TcpListener>> {
+        let mut listeners = Vec::new();
+        for addr in self.socket_addrs() {
+            let bound = addr.and_then(|addr| {
+                bind_tcp(addr, self.reuse_address).map_err(|e| {
+                    io::Error::new(e.kind(), format!("failed to bind {}: {}", addr, e))
+                })
+            });
+            match bound {
+                Ok(listener) => listeners.push(listener),
+                Err(e) => log!(Level::Warn, "Skipping listen address: {}", e),
+            }
+        }
+
+        if listeners.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrNotAvailable,
+                "none of the configured addresses could be bound",
+            ));
+        }
+        Ok(listeners)
+    }
+}
+
+/// Resolve `addr`, which may be a bracketed IPv6 address, with `port`
+fn resolve(addr: &str, port: u16) -> io::Result<SocketAddr> {
+    let host = addr.trim_start_matches('[').trim_end_matches(']');
+    let mut addrs = (host, port)
+        .to_socket_addrs()
+        .map_err(|e| io::Error::new(e.kind(), format!("failed to resolve {}: {}", addr, e)))?;
+    addrs.next().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{}:{} did not resolve to an address", addr, port),
+        )
+    })
+}
+
+/// Why a config file could not be loaded
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The file could not be read
+    Io(io::Error),
+    /// The file is not valid TOML
+    Syntax(TomlError),
+    /// A setting is unknown, or its value is of the wrong kind or out of
+    /// range
+    Invalid {
+        line: usize,
+        key: String,
+        message: String,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "unable to read config file: {}", e),
+            ConfigError::Syntax(e) => write!(f, "invalid config file: {}", e),
+            ConfigError::Invalid { line, key, message } => {
+                write!(
+                    f,
+                    "invalid config file: line {}: {}: {}",
+                    line, key, message
+                )
+            }
+        }
+    }
+}
+
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ConfigError::Io(e) => Some(e),
+            ConfigError::Syntax(e) => Some(e),
+            ConfigError::Invalid { .. } => None,
+        }
+    }
+}
+
+fn invalid(entry: &Entry, message: impl Into<String>) -> ConfigError {
+    ConfigError::Invalid {
+        line: entry.line,
+        key: entry.key.clone(),
+        message: message.into(),
+    }
+}
+
+fn mismatch(entry: &Entry, expected: &str) -> ConfigError {
+    invalid(
+        entry,
+        format!("expected {}, found {}", expected, entry.value.kind()),
+    )
+}
+
+fn string(entry: &Entry) -> Result<String, ConfigError> {
+    match &entry.value {
+        Value::String(value) => Ok(value.clone()),
+        _ => Err(mismatch(entry, "a string")),
+    }
+}
+
+fn strings(entry: &Entry) -> Result<Vec<String>, ConfigError> {
+    match &entry.value {
+        Value::Array(items) => items
+            .iter()
+            .map(|item| match item {
+                Value::String(value) => Ok(value.clone()),
+                _ => Err(mismatch(entry, "an array of strings")),
+            })
+            .collect(),
+        _ => Err(mismatch(entry, "an array of strings")),
+    }
+}
+
+fn boolean(entry: &Entry) -> Result<bool, ConfigError> {
+    match entry.value {
+        Value::Boolean(value) => Ok(value),
+        _ => Err(mismatch(entry, "a boolean")),
+    }
+}
+
+fn integer<T: TryFrom<i64>>(entry: &Entry) -> Result<T, ConfigError> {
+    match entry.value {
+        Value::Integer(value) => {
+            T::try_from(value).map_err(|_| invalid(entry, format!("{} is out of range", value)))
+        }
+        _ => Err(mismatch(entry, "an integer")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = r#"
+# A sample config
+[server]
+bind = ["127.0.0.1", "::1"]
+port = 8443
+threads = 8
+error_pages = "/srv/errors"
+
+[static]
+root = "/srv/www"
+directory_listing = true
+
+[tls]
+cert = "/etc/ws/cert.pem"
+key = "/etc/ws/key.pem"
+
+[log]
+level = "debug"
+access_log = "/var/log/ws/access.log"
+"#;
+
+    fn invalid_message(text: &str) -> String {
+        match ServerConfig::from_toml(text) {
+            Err(e @ ConfigError::Invalid { .. }) => e.to_string(),
+            Err(e) => panic!("expected an invalid setting, got {}", e),
+            Ok(_) => panic!("expected an invalid setting"),
+        }
+    }
+
+    #[test]
+    fn a_sample_file_maps_onto_the_config() {
+        let path = env::temp_dir().join(format!("webserver-config-{}.toml", std::process::id()));
+        fs::write(&path, SAMPLE).unwrap();
+        let config = ServerConfig::from_file(&path);
+        fs::remove_file(&path).unwrap();
+        let config = config.unwrap();
+
+        assert_eq!(config.bind_addrs, ["127.0.0.1", "::1"]);
+        assert_eq!(config.port, 8443);
+        assert_eq!(config.threads, 8);
+        assert_eq!(config.error_pages, PathBuf::from("/srv/errors"));
+        assert_eq!(config.static_dir, Some(PathBuf::from("/srv/www")));
+        assert!(config.directory_listing);
+        assert!(!config.spa_fallback);
+        assert_eq!(config.tls_cert, Some(PathBuf::from("/etc/ws/cert.pem")));
+        assert_eq!(config.tls_key, Some(PathBuf::from("/etc/ws/key.pem")));
+        assert_eq!(config.log_level, Some(Level::Debug));
+        assert_eq!(
+            config.access_log,
+            Some(PathBuf::from("/var/log/ws/access.log"))
+        );
+    }
+
+    #[test]
+    fn settings_left_out_keep_their_defaults() {
+        let config = ServerConfig::from_toml("[server]\nbind = \"0.0.0.0\"\n").unwrap();
+        assert_eq!(config.bind_addrs, ["0.0.0.0"]);
+        assert_eq!(config.port, DEFAULT_PORT);
+        assert_eq!(config.threads, DEFAULT_WORKERS);
+        assert_eq!(config.static_dir, None);
+    }
+
+    #[test]
+    fn a_missing_file_is_an_io_error() {
+        let error = ServerConfig::from_file(Path::new("/nonexistent/config.toml")).err();
+        assert!(matches!(error, Some(ConfigError::Io(_))));
+        assert!(error
+            .unwrap()
+            .to_string()
+            .starts_with("unable to read config file: "));
+    }
+
+    #[test]
+    fn a_syntax_error_names_its_line() {
+        let error = ServerConfig::from_toml("[server]\nport = \"8080\nthreads = 2\n").err();
+        assert!(matches!(error, Some(ConfigError::Syntax(_))));
+        assert_eq!(
+            error.unwrap().to_string(),
+            "invalid config file: line 2: unterminated string"
+        );
+    }
+
+    #[test]
+    fn bad_settings_name_their_line_and_key() {
+        assert_eq!(
+            invalid_message("[server]\nprot = 80\n"),
+            "invalid config file: line 2: server.prot: unknown setting"
+        );
+        assert_eq!(
+            invalid_message("[server]\nport = \"80\"\n"),
+            "invalid config file: line 2: server.port: expected an integer, found a string"
+        );
+        assert_eq!(
+            invalid_message("[server]\nport = 70000\n"),
+            "invalid config file: line 2: server.port: 70000 is out of range"
+        );
+        assert_eq!(
+            invalid_message("[server]\nbind = [\"a\", 1]\n"),
+            "invalid config file: line 2: server.bind: expected an array of strings, found an array"
+        );
+        assert_eq!(
+            invalid_message("[static]\ndirectory_listing = 1\n"),
+            "invalid config file: line 2: static.directory_listing: expected a boolean, found an integer"
+        );
+        assert!(invalid_message("[log]\nlevel = \"loud\"\n")
+            .starts_with("invalid config file: line 2: log.level: "));
+    }
+
+    #[test]
+    fn the_environment_sets_the_listen_addresses() {
+        env::set_var(ADDR_ENV_VAR, "127.0.0.1, [::1]");
+        env::set_var(PORT_ENV_VAR, "8089");
+        let config = ServerConfig::from_env();
+        env::set_var(PORT_ENV_VAR, "not a port");
+        let unparseable = ServerConfig::from_env();
+        env::remove_var(ADDR_ENV_VAR);
+        env::remove_var(PORT_ENV_VAR);
+
+        assert_eq!(config.bind_addrs, ["127.0.0.1", "[::1]"]);
+        let addrs: Vec<SocketAddr> = config
+            .socket_addrs()
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            addrs,
+            [
+                "127.0.0.1:8089".parse::<SocketAddr>().unwrap(),
+                "[::1]:8089".parse().unwrap()
+            ]
+        );
+        assert_eq!(unparseable.port, DEFAULT_PORT);
+    }
+
+    #[test]
+    fn binding_a_port_in_use_is_an_error() {
+        let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = ServerConfig {
+            bind_addrs: vec!["127.0.0.1".to_string()],
+            port: taken.local_addr().unwrap().port(),
+            ..ServerConfig::default()
+        };
+        let error = config.bind().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::AddrNotAvailable);
+    }
+}
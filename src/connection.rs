@@ -0,0 +1,1179 @@
+use std::{
+    io::{self, Read, Write},
+    net::{Shutdown, SocketAddr, TcpStream},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+
+use crate::{
+    access_log::AccessLog,
+    config::ServerConfig,
+    error::ServerError,
+    log,
+    log::Level,
+    metrics::Metrics,
+    pages::error_response,
+    rate_limit::RateLimiter,
+    request::{wants_keep_alive, Method, ParseError, Request, Version, REQUEST_ID_HEADER},
+    response::Response,
+    shutdown::ShutdownSignal,
+    status::StatusCode,
+    tls::TlsStream,
+    vhost::VirtualHosts,
+};
+
+/// State shared by every connection
+pub struct Context {
+    pub config: ServerConfig,
+    /// The router for each site, wrapped in the server's middleware
+    pub sites: VirtualHosts,
+    pub access_log: AccessLog,
+    pub metrics: Arc<Metrics>,
+    pub connections: ConnectionLimiter,
+    pub rate_limiter: Option<RateLimiter>,
+    /// Triggered when the server should stop accepting connections
+    pub shutdown: Arc<ShutdownSignal>,
+}
+
+/// Caps how many connections are open at once
+#[derive(Debug)]
+pub struct ConnectionLimiter {
+    active: Arc<AtomicUsize>,
+    max: usize,
+}
+
+/// A connection's place under a [`ConnectionLimiter`], given back when
+/// dropped. Dropping happens during unwinding too, so a panicking handler
+/// doesn't leak its place.
+#[derive(Debug)]
+pub struct ConnectionPermit {
+    active: Arc<AtomicUsize>,
+}
+
+impl ConnectionLimiter {
+    pub fn new(max: usize) -> ConnectionLimiter {
+        ConnectionLimiter {
+            active: Arc::new(AtomicUsize::new(0)),
+            max,
+        }
+    }
+
+    /// Take a place for a new connection, or `None` if `max` are already
+    /// open
+    pub fn try_acquire(&self) -> Option<ConnectionPermit> {
+        let acquired = self
+            .active
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |active| {
+                if active < self.max {
+                    Some(active + 1)
+                } else {
+                    None
+                }
+            });
+
+        acquired.ok().map(|_| ConnectionPermit {
+            active: Arc::clone(&self.active),
+        })
+    }
+
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        self.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// A client connection requests can be served over, whether plain TCP, TLS
+/// or an in-memory stream in tests. Streams that have no timeouts can leave
+/// the timeout methods as no-ops.
+pub trait Connection: Read + Write {
+    fn set_read_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn set_write_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Tell the client the connection is being closed. Nothing needs saying
+    /// for plain TCP.
+    fn close(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// A second handle on the socket, for hanging up on the client from
+    /// another thread. Streams with no socket to share return `None`.
+    fn hangup(&self) -> Option<Hangup> {
+        None
+    }
+}
+
+/// Lets a watchdog thread answer a client and close its connection while a
+/// handler is still running on it
+pub struct Hangup {
+    /// Where to write a response, if the connection can be written to from
+    /// another thread. A TLS session can't be.
+    writer: Option<Box<dyn Write + Send>>,
+    shutdown: Box<dyn FnOnce() -> io::Result<()> + Send>,
+}
+
+impl Hangup {
+    /// Send `response` if possible, then shut the socket so the handler's
+    /// own writes fail. Returns how many bytes were sent.
+    fn hang_up(self, response: &mut Response) -> usize {
+        let written = match self.writer {
+            Some(mut writer) => response.write_to(&mut writer).unwrap_or(0),
+            None => 0,
+        };
+        if let Err(e) = (self.shutdown)() {
+            log!(Level::Debug, "Failed to shut down connection: {}", e);
+        }
+        written
+    }
+}
+
+impl Connection for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+
+    fn hangup(&self) -> Option<Hangup> {
+        let writer = self.try_clone().ok()?;
+        let socket = self.try_clone().ok()?;
+        Some(Hangup {
+            writer: Some(Box::new(writer)),
+            shutdown: Box::new(move || socket.shutdown(Shutdown::Both)),
+        })
+    }
+}
+
+#[cfg(unix)]
+impl Connection for UnixStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_write_timeout(self, timeout)
+    }
+
+    fn hangup(&self) -> Option<Hangup> {
+        let writer = self.try_clone().ok()?;
+        let socket = self.try_clone().ok()?;
+        Some(Hangup {
+            writer: Some(Box::new(writer)),
+            shutdown: Box::new(move || socket.shutdown(Shutdown::Both)),
+        })
+    }
+}
+
+impl Connection for TlsStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.sock.set_read_timeout(timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.sock.set_write_timeout(timeout)
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.conn.send_close_notify();
+        self.flush()
+    }
+
+    fn hangup(&self) -> Option<Hangup> {
+        let socket = self.sock.try_clone().ok()?;
+        Some(Hangup {
+            writer: None,
+            shutdown: Box::new(move || socket.shutdown(Shutdown::Both)),
+        })
+    }
+}
+
+/// Serve requests on `stream` until the client closes it, asks for it to be
+/// closed, or the per-connection limits are reached. `peer` is only used for
+/// logging.
+pub fn handle_connection(
+    stream: &mut impl Connection,
+    peer: Option<SocketAddr>,
+    context: &Context,
+) -> Result<(), ServerError> {
+    let _active = context.metrics.track_connection();
+    match serve_connection(stream, peer, context) {
+        // Clients are free to leave mid-response, so it's no error
+        Err(e) if is_disconnect(&e) => {
+            log!(Level::Debug, "Client disconnected: {}", e);
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+fn serve_connection(
+    stream: &mut impl Connection,
+    peer: Option<SocketAddr>,
+    context: &Context,
+) -> Result<(), ServerError> {
+    let config = &context.config;
+    stream.set_write_timeout(Some(config.write_timeout))?;
+
+    let max_requests = config.max_requests_per_connection.max(1);
+    // Bytes of the next request already read off the stream, which have to
+    // be put back in front of it: a pipelined request read along with the
+    // one before, or the byte read while waiting
+    let mut pending = Vec::new();
+    for served in 0..max_requests {
+        if served > 0 && pending.is_empty() {
+            match wait_for_request(stream, config.keep_alive_timeout)? {
+                Some(byte) => pending.push(byte),
+                None => break,
+            }
+        }
+
+        stream.set_read_timeout(Some(config.read_timeout))?;
+        let remaining = max_requests - served - 1;
+        let keep_alive = serve_request(stream, &mut pending, peer, context, remaining)?;
+        if !keep_alive {
+            break;
+        }
+    }
+
+    stream.close()?;
+    Ok(())
+}
+
+/// Whether `e` only means the client hung up
+fn is_disconnect(e: &ServerError) -> bool {
+    match e {
+        ServerError::Io(e) => matches!(
+            e.kind(),
+            io::ErrorKind::BrokenPipe
+                | io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+        ),
+        _ => false,
+    }
+}
+
+/// Wait up to `idle_timeout` for the next request on a persistent
+/// connection, returning its first byte. Returns `None` if the client closed
+/// the connection or went quiet.
+fn wait_for_request(
+    stream: &mut impl Connection,
+    idle_timeout: Duration,
+) -> Result<Option<u8>, ServerError> {
+    stream.set_read_timeout(Some(idle_timeout))?;
+    let mut byte = [0; 1];
+    match stream.read(&mut byte) {
+        Ok(0) => Ok(None),
+        Ok(_) => Ok(Some(byte[0])),
+        Err(e) => match ServerError::from(e) {
+            ServerError::Timeout => Ok(None),
+            e => Err(e),
+        },
+    }
+}
+
+/// Read and respond to a single request, returning whether the connection
+/// should be kept open for another. `pending` is any of the request that was
+/// already read off the stream, and is left holding whatever was read past
+/// its end. `remaining` is how many more requests the connection may serve
+/// after this one.
+///
+/// Pipelined requests are answered one at a time, so responses go out in
+/// the order the requests came in.
+fn serve_request(
+    stream: &mut impl Connection,
+    pending: &mut Vec<u8>,
+    peer: Option<SocketAddr>,
+    context: &Context,
+    remaining: usize,
+) -> Result<bool, ServerError> {
+    let config = &context.config;
+    let prefix = std::mem::take(pending);
+    let mut prefixed = Prefixed {
+        prefix: &prefix,
+        stream,
+    };
+    let mut request = match Request::parse(&mut prefixed, peer, &config.limits()) {
+        Ok((request, mut rest)) => {
+            // What was read past the request came before anything still
+            // left of the prefix
+            rest.extend_from_slice(prefixed.prefix);
+            *pending = rest;
+            request
+        }
+        Err(e) => return reject(stream, peer, context, e),
+    };
+    if config.dump_requests && log::enabled(Level::Trace) {
+        log!(
+            Level::Trace,
+            "Request {}:\n{}",
+            request.request_id,
+            request.dump(config.dump_body_limit)
+        );
+    }
+    let refused = throttle(context, peer);
+    // The middleware gets a request of its own, but the body is only needed
+    // there
+    let body = std::mem::take(&mut request.body);
+    let handled = Request {
+        body,
+        ..request.clone()
+    };
+    let handle = move || context.sites.dispatch(handled);
+    let mut response = match (refused, config.handler_timeout) {
+        (Some(response), _) => response,
+        (None, Some(timeout)) => match stream.hangup() {
+            Some(hangup) => {
+                match handle_with_watchdog(handle, timeout, hangup, &request, peer, context) {
+                    Some(response) => response,
+                    // The client has been answered and the socket shut
+                    None => return Ok(false),
+                }
+            }
+            None => handle(),
+        },
+        (None, None) => handle(),
+    };
+    response.headers.set(REQUEST_ID_HEADER, &request.request_id);
+    add_configured_headers(&mut response, config);
+    response.version = Version::for_request(&request.version);
+    let keep_alive = keep_connection_open(&request, &response, remaining);
+    set_connection_headers(&mut response, keep_alive, config, remaining);
+
+    // HEAD gets the same headers as GET, including the length of the body it
+    // would have received
+    let written = if request.method == Method::Head {
+        response.write_head_to(stream)?
+    } else {
+        response.write_to(stream)?
+    };
+    stream.flush()?;
+    record_response(context, peer, Some(&request), &response, written);
+
+    Ok(keep_alive)
+}
+
+/// Run `handle` while a watchdog waits for it. If it takes longer than
+/// `timeout`, the watchdog answers with a `503` and hangs up, as the thread
+/// can't be stopped, and `None` is returned once `handle` does finish.
+fn handle_with_watchdog(
+    handle: impl FnOnce() -> Response,
+    timeout: Duration,
+    hangup: Hangup,
+    request: &Request,
+    peer: Option<SocketAddr>,
+    context: &Context,
+) -> Option<Response> {
+    let (done, finished) = mpsc::channel::<()>();
+    thread::scope(|scope| {
+        let watchdog = scope.spawn(move || {
+            if finished.recv_timeout(timeout) != Err(RecvTimeoutError::Timeout) {
+                return false;
+            }
+            log!(
+                Level::Warn,
+                "Handler for {} {} ran past {:?}, abandoning the connection",
+                request.method,
+                request.path,
+                timeout
+            );
+            let mut response =
+                error_response(&context.config.error_pages, StatusCode::SERVICE_UNAVAILABLE)
+                    .header("Connection", "close");
+            add_configured_headers(&mut response, &context.config);
+            let written = hangup.hang_up(&mut response);
+            record_response(context, peer, Some(request), &response, written);
+            true
+        });
+
+        let response = handle();
+        drop(done);
+        let abandoned = watchdog.join().unwrap_or(false);
+        if abandoned {
+            None
+        } else {
+            Some(response)
+        }
+    })
+}
+
+/// Whether the connection stays open after `response` is sent for
+/// `request`: the client must want it kept open, the handler must not have
+/// asked for it to be closed, the connection must have requests left, and
+/// the request must not have been framed two ways at once.
+pub fn keep_connection_open(request: &Request, response: &Response, remaining: usize) -> bool {
+    let handler_closes = response
+        .headers
+        .get_all("Connection")
+        .iter()
+        .any(|value| value.eq_ignore_ascii_case("close"));
+
+    remaining > 0
+        && !handler_closes
+        && !request.has_conflicting_framing()
+        && wants_keep_alive(&request.version, &request.headers)
+}
+
+/// Tell the client whether the connection will be reused, and if so for how
+/// long and how many more requests
+fn set_connection_headers(
+    response: &mut Response,
+    keep_alive: bool,
+    config: &ServerConfig,
+    remaining: usize,
+) {
+    if keep_alive {
+        response.headers.set("Connection", "keep-alive");
+        let hint = format!(
+            "timeout={}, max={}",
+            config.keep_alive_timeout.as_secs(),
+            remaining
+        );
+        response.headers.set("Keep-Alive", &hint);
+    } else {
+        response.headers.set("Connection", "close");
+        response.headers.remove("Keep-Alive");
+    }
+}
+
+/// Add the headers every response gets unless it has its own
+fn add_configured_headers(response: &mut Response, config: &ServerConfig) {
+    response.add_default_headers(&config.default_headers);
+    if let Some(server) = &config.server_header {
+        if !response.headers.contains("Server") {
+            response.headers.insert("Server", server);
+        }
+    }
+}
+
+/// A stream with bytes already read from it put back in front
+struct Prefixed<'a, C> {
+    prefix: &'a [u8],
+    stream: &'a mut C,
+}
+
+impl<C: Read> Read for Prefixed<'_, C> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.prefix.is_empty() {
+            self.stream.read(buf)
+        } else {
+            self.prefix.read(buf)
+        }
+    }
+}
+
+impl<C: Write> Write for Prefixed<'_, C> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl<C: Connection> Connection for Prefixed<'_, C> {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream.set_read_timeout(timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream.set_write_timeout(timeout)
+    }
+}
+
+/// A `429` if the client is over its rate limit
+fn throttle(context: &Context, peer: Option<SocketAddr>) -> Option<Response> {
+    let limiter = context.rate_limiter.as_ref()?;
+    let retry_after = limiter.check(peer?.ip()).err()?;
+
+    // Retry-After only has whole seconds, so round up
+    let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    Some(
+        error_response(&context.config.error_pages, StatusCode::TOO_MANY_REQUESTS)
+            .header("Retry-After", &seconds.to_string()),
+    )
+}
+
+/// Respond to a request that could not be read, if the client can still be
+/// told anything. The connection is not reused afterwards.
+fn reject(
+    stream: &mut impl Connection,
+    peer: Option<SocketAddr>,
+    context: &Context,
+    e: ServerError,
+) -> Result<bool, ServerError> {
+    let status = match e {
+        ServerError::Parse(ParseError::HeadersTooLarge)
+        | ServerError::Parse(ParseError::TooManyHeaders) => {
+            StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE
+        }
+        ServerError::Parse(ParseError::BodyTooLarge) => StatusCode::PAYLOAD_TOO_LARGE,
+        ServerError::Parse(ParseError::UnsupportedVersion(_)) => {
+            StatusCode::HTTP_VERSION_NOT_SUPPORTED
+        }
+        ServerError::Parse(e) => {
+            log!(Level::Info, "Failed to parse request: {}", e);
+            StatusCode::BAD_REQUEST
+        }
+        ServerError::Timeout => {
+            // The client may well be gone, so failing to tell it is fine
+            let mut response =
+                error_response(&context.config.error_pages, StatusCode::REQUEST_TIMEOUT)
+                    .header("Connection", "close");
+            add_configured_headers(&mut response, &context.config);
+            let written = response.write_to(stream).unwrap_or(0);
+            record_response(context, peer, None, &response, written);
+            return Err(ServerError::Timeout);
+        }
+        // Nobody is left to answer, e.g. a port scan or a browser's
+        // speculative connection
+        ServerError::ConnectionClosed => return Ok(false),
+        e => return Err(e),
+    };
+
+    let mut response =
+        error_response(&context.config.error_pages, status).header("Connection", "close");
+    add_configured_headers(&mut response, &context.config);
+    let written = response.write_to(stream)?;
+    stream.flush()?;
+    record_response(context, peer, None, &response, written);
+    Ok(false)
+}
+
+fn record_response(
+    context: &Context,
+    peer: Option<SocketAddr>,
+    request: Option<&Request>,
+    response: &Response,
+    written: usize,
+) {
+    context.metrics.record_response(response.status);
+    context
+        .access_log
+        .log(peer, request, response.status, written);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{TcpListener, TcpStream},
+        time::Instant,
+    };
+
+    use super::*;
+    use crate::{
+        config::SERVER_NAME, middleware::MiddlewareStack, request::parse_request_head,
+        router::Router, testing::Duplex,
+    };
+
+    /// A context serving `GET /` with a small page, and nothing else
+    fn context(config: ServerConfig) -> Context {
+        let mut router = Router::new();
+        router.add_route(
+            Method::Get,
+            "/",
+            Box::new(|_| Response::new(StatusCode::OK).body("hello")),
+        );
+        Context {
+            config,
+            sites: VirtualHosts::new(MiddlewareStack::new(router)),
+            access_log: AccessLog::new(Box::new(io::sink())),
+            metrics: Arc::new(Metrics::new()),
+            connections: ConnectionLimiter::new(1),
+            rate_limiter: None,
+            shutdown: Arc::new(ShutdownSignal::new()),
+        }
+    }
+
+    /// Serve everything in `input` as one connection, returning what was
+    /// written back
+    fn serve(context: &Context, input: &str) -> String {
+        let mut stream = Duplex::new(input);
+        handle_connection(&mut stream, None, context).unwrap();
+        stream.output()
+    }
+
+    /// Both ends of a loopback TCP connection, client first
+    fn tcp_pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        (client, server)
+    }
+
+    #[test]
+    fn a_silent_client_times_out_with_a_408() {
+        let context = context(ServerConfig {
+            read_timeout: Duration::from_millis(100),
+            header_timeout: None,
+            ..ServerConfig::default()
+        });
+        let (mut client, mut server) = tcp_pair();
+
+        let started = Instant::now();
+        let result = handle_connection(&mut server, None, &context);
+        let elapsed = started.elapsed();
+        assert!(matches!(result, Err(ServerError::Timeout)));
+        assert!(elapsed >= Duration::from_millis(100), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_secs(5), "{:?}", elapsed);
+
+        drop(server);
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(
+            response.starts_with("HTTP/1.1 408 Request Timeout\r\n"),
+            "{}",
+            response
+        );
+    }
+
+    /// Read one response with a `Content-Length` off `stream`
+    fn read_response(stream: &mut TcpStream) -> String {
+        let mut response = Vec::new();
+        let mut byte = [0; 1];
+        while !response.ends_with(b"\r\n\r\n") {
+            stream.read_exact(&mut byte).unwrap();
+            response.push(byte[0]);
+        }
+        let head = String::from_utf8(response.clone()).unwrap();
+        let length: usize = head
+            .lines()
+            .find_map(|line| line.strip_prefix("Content-Length: "))
+            .map_or(0, |length| length.parse().unwrap());
+        let mut body = vec![0; length];
+        stream.read_exact(&mut body).unwrap();
+        response.extend_from_slice(&body);
+        String::from_utf8(response).unwrap()
+    }
+
+    #[test]
+    fn two_requests_are_served_on_one_connection() {
+        let context = context(ServerConfig::default());
+        let (mut client, mut server) = tcp_pair();
+
+        thread::scope(|scope| {
+            let serving = scope.spawn(|| handle_connection(&mut server, None, &context));
+
+            client
+                .write_all(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n")
+                .unwrap();
+            let first = read_response(&mut client);
+            assert!(first.starts_with("HTTP/1.1 200 OK\r\n"), "{}", first);
+            assert!(first.contains("Connection: keep-alive\r\n"), "{}", first);
+            assert!(first.ends_with("hello"), "{}", first);
+
+            client
+                .write_all(b"GET / HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n")
+                .unwrap();
+            let second = read_response(&mut client);
+            assert!(second.starts_with("HTTP/1.1 200 OK\r\n"), "{}", second);
+            assert!(second.contains("Connection: close\r\n"), "{}", second);
+
+            assert!(serving.join().unwrap().is_ok());
+        });
+        assert_eq!(context.metrics.requests(), 2);
+    }
+
+    #[test]
+    fn a_connection_is_closed_after_its_last_allowed_request() {
+        let context = context(ServerConfig {
+            max_requests_per_connection: 2,
+            ..ServerConfig::default()
+        });
+        let (mut client, mut server) = tcp_pair();
+
+        thread::scope(|scope| {
+            let serving = scope.spawn(|| handle_connection(&mut server, None, &context));
+            client
+                .write_all(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n")
+                .unwrap();
+            let first = read_response(&mut client);
+            assert!(
+                first.contains("Keep-Alive: timeout=5, max=1\r\n"),
+                "{}",
+                first
+            );
+            client
+                .write_all(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n")
+                .unwrap();
+            let second = read_response(&mut client);
+            assert!(second.contains("Connection: close\r\n"), "{}", second);
+            assert!(serving.join().unwrap().is_ok());
+        });
+    }
+
+    #[test]
+    fn an_idle_connection_is_closed_after_the_keep_alive_timeout() {
+        let context = context(ServerConfig {
+            keep_alive_timeout: Duration::from_millis(100),
+            ..ServerConfig::default()
+        });
+        let (mut client, mut server) = tcp_pair();
+
+        thread::scope(|scope| {
+            let serving = scope.spawn(|| handle_connection(&mut server, None, &context));
+            client
+                .write_all(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n")
+                .unwrap();
+            read_response(&mut client);
+            // Quietly closed, with no 408 for a client that sent nothing
+            assert!(serving.join().unwrap().is_ok());
+        });
+        let mut rest = Vec::new();
+        drop(server);
+        client.read_to_end(&mut rest).unwrap();
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn a_client_request_id_is_echoed() {
+        let context = context(ServerConfig::default());
+        let response = serve(
+            &context,
+            "GET / HTTP/1.1\r\nHost: a\r\nX-Request-Id: trace-42\r\nConnection: close\r\n\r\n",
+        );
+        assert!(
+            response.contains("\r\nX-Request-Id: trace-42\r\n"),
+            "{}",
+            response
+        );
+    }
+
+    #[test]
+    fn a_request_without_an_id_is_given_one() {
+        let context = context(ServerConfig::default());
+        let response = serve(
+            &context,
+            "GET / HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+        );
+        let id = response
+            .lines()
+            .find_map(|line| line.strip_prefix("X-Request-Id: "))
+            .unwrap();
+        assert!(!id.is_empty());
+    }
+
+    #[test]
+    fn a_request_in_memory_gets_the_whole_response() {
+        let context = context(ServerConfig::default());
+        let response = serve(
+            &context,
+            "GET / HTTP/1.1\r\nHost: a\r\nX-Request-Id: r1\r\nConnection: close\r\n\r\n",
+        );
+        // The date is the only part that changes from run to run
+        let (head, date) = response.split_once("Date: ").unwrap();
+        let (date, rest) = date.split_once("\r\n").unwrap();
+        assert!(date.ends_with(" GMT"), "{}", date);
+        assert_eq!(
+            format!("{}{}", head, rest),
+            format!(
+                "HTTP/1.1 200 OK\r\nX-Request-Id: r1\r\nServer: {}\r\n\
+                 Connection: close\r\nContent-Length: 5\r\n\r\nhello",
+                SERVER_NAME
+            )
+        );
+    }
+
+    #[test]
+    fn the_limiter_refuses_connections_over_its_max() {
+        let limiter = ConnectionLimiter::new(2);
+        let first = limiter.try_acquire().unwrap();
+        let second = limiter.try_acquire().unwrap();
+        assert!(limiter.try_acquire().is_none());
+        assert_eq!(limiter.active(), 2);
+
+        drop(first);
+        let third = limiter.try_acquire().unwrap();
+        drop((second, third));
+        assert_eq!(limiter.active(), 0);
+    }
+
+    #[test]
+    fn a_permit_is_given_back_when_its_holder_panics() {
+        let limiter = ConnectionLimiter::new(1);
+        let result = thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    let _permit = limiter.try_acquire().unwrap();
+                    panic!("handler failed");
+                })
+                .join()
+        });
+        assert!(result.is_err());
+        assert_eq!(limiter.active(), 0);
+        assert!(limiter.try_acquire().is_some());
+    }
+
+    #[test]
+    fn a_client_over_its_rate_limit_gets_a_429() {
+        let context = Context {
+            rate_limiter: Some(RateLimiter::new(0.5, 1)),
+            ..context(ServerConfig::default())
+        };
+        let peer = "192.0.2.1:4000".parse().ok();
+        let request = "GET / HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n";
+
+        let mut stream = Duplex::new(request);
+        handle_connection(&mut stream, peer, &context).unwrap();
+        assert!(stream.output().starts_with("HTTP/1.1 200 OK\r\n"));
+
+        let mut stream = Duplex::new(request);
+        handle_connection(&mut stream, peer, &context).unwrap();
+        let response = stream.output();
+        assert!(
+            response.starts_with("HTTP/1.1 429 Too Many Requests\r\n"),
+            "{}",
+            response
+        );
+        assert!(response.contains("\r\nRetry-After: 2\r\n"), "{}", response);
+    }
+
+    #[test]
+    fn an_http_1_0_request_gets_a_1_0_response_and_is_closed() {
+        let context = context(ServerConfig::default());
+        // The second request is never read
+        let response = serve(&context, "GET / HTTP/1.0\r\n\r\nGET / HTTP/1.0\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.0 200 OK\r\n"), "{}", response);
+        assert!(
+            response.contains("\r\nConnection: close\r\n"),
+            "{}",
+            response
+        );
+        assert_eq!(response.matches("HTTP/1.0 200 OK").count(), 1);
+    }
+
+    #[test]
+    fn an_http_1_0_client_can_ask_for_keep_alive() {
+        let context = context(ServerConfig::default());
+        let response = serve(
+            &context,
+            "GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\nGET / HTTP/1.0\r\n\r\n",
+        );
+        assert_eq!(response.matches("HTTP/1.0 200 OK").count(), 2);
+        assert!(
+            response.contains("\r\nConnection: keep-alive\r\n"),
+            "{}",
+            response
+        );
+    }
+
+    fn parsed(head: &str) -> Request {
+        parse_request_head(head.as_bytes(), 100).unwrap()
+    }
+
+    #[test]
+    fn a_connection_is_kept_open_only_when_everyone_agrees() {
+        let ok = Response::new(StatusCode::OK);
+        let keep = parsed("GET / HTTP/1.1\r\nHost: a\r\n\r\n");
+        assert!(keep_connection_open(&keep, &ok, 1));
+        // Out of requests
+        assert!(!keep_connection_open(&keep, &ok, 0));
+        // The client is done
+        let close = parsed("GET / HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n");
+        assert!(!keep_connection_open(&close, &ok, 1));
+        // The handler is done
+        let closing = Response::new(StatusCode::OK).header("Connection", "close");
+        assert!(!keep_connection_open(&keep, &closing, 1));
+        // Framed by both Transfer-Encoding and Content-Length
+        let smuggling = parsed(
+            "POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\
+             Content-Length: 5\r\n\r\n",
+        );
+        assert!(!keep_connection_open(&smuggling, &ok, 1));
+    }
+
+    #[test]
+    fn a_request_with_both_lengths_closes_the_connection() {
+        let context = context(ServerConfig::default());
+        let output = serve(
+            &context,
+            "GET / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\
+             Content-Length: 3\r\n\r\n0\r\n\r\nGET / HTTP/1.1\r\nHost: a\r\n\r\n",
+        );
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"), "{}", output);
+        assert!(output.contains("\r\nConnection: close\r\n"), "{}", output);
+        // The request after it is never read
+        assert_eq!(output.matches("HTTP/1.1 ").count(), 1, "{}", output);
+    }
+
+    #[test]
+    fn a_transfer_encoding_not_ending_in_chunked_is_a_400() {
+        let context = context(ServerConfig::default());
+        let output = serve(
+            &context,
+            "GET / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked, gzip\r\n\
+             Content-Length: 5\r\n\r\nhello",
+        );
+        assert!(
+            output.starts_with("HTTP/1.1 400 Bad Request\r\n"),
+            "{}",
+            output
+        );
+        assert!(output.contains("\r\nConnection: close\r\n"), "{}", output);
+    }
+
+    #[test]
+    fn connection_headers_match_the_decision() {
+        let config = ServerConfig {
+            keep_alive_timeout: Duration::from_secs(5),
+            ..ServerConfig::default()
+        };
+        let mut response = Response::new(StatusCode::OK);
+        set_connection_headers(&mut response, true, &config, 3);
+        assert_eq!(response.headers.get("Connection"), Some("keep-alive"));
+        assert_eq!(response.headers.get("Keep-Alive"), Some("timeout=5, max=3"));
+
+        set_connection_headers(&mut response, false, &config, 3);
+        assert_eq!(response.headers.get("Connection"), Some("close"));
+        assert_eq!(response.headers.get("Keep-Alive"), None);
+    }
+
+    #[test]
+    fn configured_headers_are_added_to_every_response() {
+        let context = context(ServerConfig {
+            default_headers: vec![(
+                "Content-Security-Policy".to_string(),
+                "default-src 'self'".to_string(),
+            )],
+            ..ServerConfig::default()
+        });
+        for path in ["/", "/missing"] {
+            let response = serve(
+                &context,
+                &format!(
+                    "GET {} HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+                    path
+                ),
+            );
+            assert!(
+                response.contains("\r\nContent-Security-Policy: default-src 'self'\r\n"),
+                "{}",
+                response
+            );
+        }
+    }
+
+    #[test]
+    fn a_missing_page_is_a_404_but_malformed_input_is_a_400() {
+        let context = context(ServerConfig::default());
+        let response = serve(
+            &context,
+            "GET /missing HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+        );
+        assert!(
+            response.starts_with("HTTP/1.1 404 Not Found\r\n"),
+            "{}",
+            response
+        );
+
+        for input in [
+            "\x16\x03\x01\x02\x00\x01\x00\x01\x7c\x03\x03\r\n\r\n",
+            "GET /\r\nHost: a\r\n\r\n",
+            "GET / HTTP/1.1\r\nno colon here\r\n\r\n",
+            "GET / HTTP/1.1\r\nHost: a\r\n",
+        ] {
+            let response = serve(&context, input);
+            assert!(
+                response.starts_with("HTTP/1.1 400 Bad Request\r\n"),
+                "{:?}: {}",
+                input,
+                response
+            );
+        }
+    }
+
+    #[test]
+    fn too_many_headers_get_a_431() {
+        let context = context(ServerConfig {
+            max_headers: 3,
+            ..ServerConfig::default()
+        });
+        let response = serve(
+            &context,
+            "GET / HTTP/1.1\r\nHost: a\r\nA: 1\r\nConnection: close\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+
+        let response = serve(
+            &context,
+            "GET / HTTP/1.1\r\nHost: a\r\nA: 1\r\nB: 2\r\nConnection: close\r\n\r\n",
+        );
+        assert!(
+            response.starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"),
+            "{}",
+            response
+        );
+    }
+
+    #[test]
+    fn a_client_that_closes_straight_away_gets_no_response() {
+        let context = context(ServerConfig::default());
+        let mut stream = Duplex::new("");
+        handle_connection(&mut stream, None, &context).unwrap();
+        assert!(stream.output.is_empty());
+    }
+
+    #[test]
+    fn the_server_header_is_sent_unless_suppressed() {
+        let request = "GET / HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n";
+        let response = serve(&context(ServerConfig::default()), request);
+        assert!(
+            response.contains(&format!("\r\nServer: {}\r\n", SERVER_NAME)),
+            "{}",
+            response
+        );
+        assert!(SERVER_NAME.ends_with(env!("CARGO_PKG_VERSION")));
+
+        let suppressed = context(ServerConfig {
+            server_header: None,
+            ..ServerConfig::default()
+        });
+        let response = serve(&suppressed, request);
+        assert!(!response.contains("\r\nServer:"), "{}", response);
+    }
+
+    #[test]
+    fn a_handler_past_its_deadline_gets_the_connection_closed() {
+        let mut router = Router::new();
+        router.add_route(
+            Method::Get,
+            "/slow",
+            Box::new(|_| {
+                thread::sleep(Duration::from_millis(500));
+                Response::new(StatusCode::OK).body("too late")
+            }),
+        );
+        let context = Context {
+            sites: VirtualHosts::new(MiddlewareStack::new(router)),
+            ..context(ServerConfig {
+                handler_timeout: Some(Duration::from_millis(50)),
+                ..ServerConfig::default()
+            })
+        };
+        let (mut client, mut server) = tcp_pair();
+
+        thread::scope(|scope| {
+            let serving = scope.spawn(|| handle_connection(&mut server, None, &context));
+            let started = Instant::now();
+            client
+                .write_all(b"GET /slow HTTP/1.1\r\nHost: a\r\n\r\n")
+                .unwrap();
+            let mut response = String::new();
+            client.read_to_string(&mut response).unwrap();
+            let elapsed = started.elapsed();
+
+            assert!(
+                response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"),
+                "{}",
+                response
+            );
+            assert!(response.contains("\r\nConnection: close\r\n"));
+            assert!(!response.contains("too late"));
+            // Closed by the watchdog, not once the handler was done
+            assert!(elapsed < Duration::from_millis(400), "{:?}", elapsed);
+            assert!(serving.join().unwrap().is_ok());
+        });
+    }
+
+    /// A client that sends a request, then is gone before the response
+    struct Departed {
+        input: io::Cursor<Vec<u8>>,
+        error: io::ErrorKind,
+    }
+
+    impl Read for Departed {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Departed {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(self.error.into())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Err(self.error.into())
+        }
+    }
+
+    impl Connection for Departed {}
+
+    #[test]
+    fn a_client_gone_mid_response_is_no_error() {
+        let context = context(ServerConfig::default());
+        for error in [io::ErrorKind::BrokenPipe, io::ErrorKind::ConnectionReset] {
+            let mut stream = Departed {
+                input: io::Cursor::new(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n".to_vec()),
+                error,
+            };
+            assert!(handle_connection(&mut stream, None, &context).is_ok());
+        }
+
+        // Other write errors are still reported
+        let mut stream = Departed {
+            input: io::Cursor::new(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n".to_vec()),
+            error: io::ErrorKind::PermissionDenied,
+        };
+        assert!(handle_connection(&mut stream, None, &context).is_err());
+    }
+
+    #[test]
+    fn pipelined_requests_are_answered_in_order() {
+        let mut router = Router::new();
+        router.add_route(
+            Method::Get,
+            "/:n",
+            Box::new(|req| {
+                Response::new(StatusCode::OK).body(format!("reply {}", req.params["n"]))
+            }),
+        );
+        let context = Context {
+            sites: VirtualHosts::new(MiddlewareStack::new(router)),
+            ..context(ServerConfig::default())
+        };
+        let response = serve(
+            &context,
+            "GET /1 HTTP/1.1\r\nHost: a\r\n\r\nGET /2 HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+        );
+
+        assert_eq!(response.matches("HTTP/1.1 200 OK\r\n").count(), 2);
+        let first = response.find("reply 1").unwrap();
+        let second = response.find("reply 2").unwrap();
+        assert!(first < second, "{}", response);
+        assert!(response.ends_with("\r\n\r\nreply 2"), "{}", response);
+    }
+
+    #[test]
+    fn an_http_1_1_request_without_a_host_is_a_400() {
+        let context = context(ServerConfig::default());
+        let response = serve(&context, "GET / HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(
+            response.starts_with("HTTP/1.1 400 Bad Request\r\n"),
+            "{}",
+            response
+        );
+        // HTTP/1.0 had no Host header to require
+        let response = serve(&context, "GET / HTTP/1.0\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.0 200 OK\r\n"), "{}", response);
+    }
+}
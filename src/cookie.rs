@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+
+/// Parse a `Cookie` header into its name/value pairs. Values may contain
+/// `=`; only the first one separates the name. Pairs without a name or
+/// without any `=` are skipped.
+pub fn parse_cookies(header: &str) -> HashMap<String, String> {
+    header
+        .split(';')
+        .filter_map(|pair| {
+            let (name, value) = pair.trim().split_once('=')?;
+            let name = name.trim();
+            if name.is_empty() {
+                return None;
+            }
+            Some((name.to_string(), value.trim().to_string()))
+        })
+        .collect()
+}
+
+/// The `SameSite` attribute of a cookie
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl SameSite {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        }
+    }
+}
+
+/// Attributes for a `Set-Cookie` header. The default sets none of them,
+/// giving a session cookie scoped to the request's path.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CookieOptions {
+    pub path: Option<String>,
+    /// Seconds until the cookie expires. Zero removes it straight away.
+    pub max_age: Option<u64>,
+    pub http_only: bool,
+    pub secure: bool,
+    pub same_site: Option<SameSite>,
+}
+
+/// Serialize a cookie as the value of a `Set-Cookie` header
+pub fn format_set_cookie(name: &str, value: &str, options: &CookieOptions) -> String {
+    let mut cookie = format!("{}={}", name, value);
+    if let Some(path) = &options.path {
+        cookie.push_str(&format!("; Path={}", path));
+    }
+    if let Some(max_age) = options.max_age {
+        cookie.push_str(&format!("; Max-Age={}", max_age));
+    }
+    if options.http_only {
+        cookie.push_str("; HttpOnly");
+    }
+    if options.secure {
+        cookie.push_str("; Secure");
+    }
+    if let Some(same_site) = options.same_site {
+        cookie.push_str(&format!("; SameSite={}", same_site.as_str()));
+    }
+    cookie
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cookies(header: &str) -> Vec<(String, String)> {
+        let mut cookies: Vec<_> = parse_cookies(header).into_iter().collect();
+        cookies.sort();
+        cookies
+    }
+
+    fn pair(name: &str, value: &str) -> (String, String) {
+        (name.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn parses_a_single_cookie() {
+        assert_eq!(cookies("session=abc123"), [pair("session", "abc123")]);
+    }
+
+    #[test]
+    fn parses_several_cookies() {
+        assert_eq!(
+            cookies("a=1; b=2;c=3 ;  d = 4"),
+            [
+                pair("a", "1"),
+                pair("b", "2"),
+                pair("c", "3"),
+                pair("d", "4")
+            ]
+        );
+    }
+
+    #[test]
+    fn a_value_may_contain_equals_signs() {
+        assert_eq!(
+            cookies("token=a=b==; x=1"),
+            [pair("token", "a=b=="), pair("x", "1")]
+        );
+    }
+
+    #[test]
+    fn empty_and_malformed_pairs_are_skipped() {
+        assert_eq!(
+            cookies(";; flag; =orphan; empty=; ok=1;"),
+            [pair("empty", ""), pair("ok", "1")]
+        );
+        assert!(cookies("").is_empty());
+    }
+
+    #[test]
+    fn a_cookie_without_attributes_is_just_the_pair() {
+        assert_eq!(
+            format_set_cookie("theme", "dark", &CookieOptions::default()),
+            "theme=dark"
+        );
+    }
+
+    #[test]
+    fn attributes_follow_the_pair() {
+        let options = CookieOptions {
+            path: Some("/".to_string()),
+            max_age: Some(3600),
+            http_only: true,
+            secure: true,
+            same_site: Some(SameSite::Lax),
+        };
+        assert_eq!(
+            format_set_cookie("session", "abc", &options),
+            "session=abc; Path=/; Max-Age=3600; HttpOnly; Secure; SameSite=Lax"
+        );
+    }
+}
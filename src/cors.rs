@@ -0,0 +1,190 @@
+use crate::{
+    middleware::Middleware,
+    request::{Method, Request},
+    response::Response,
+    status::StatusCode,
+};
+
+const DEFAULT_METHODS: [&str; 3] = ["GET", "HEAD", "POST"];
+const DEFAULT_MAX_AGE: u64 = 600;
+
+/// Cross-origin resource sharing for browser clients on other origins
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorsConfig {
+    /// Origins allowed to make requests, e.g. `https://example.com`. `*`
+    /// allows any origin.
+    pub allowed_origins: Vec<String>,
+    /// Methods a preflight may ask for
+    pub allowed_methods: Vec<String>,
+    /// Request headers a preflight may ask for
+    pub allowed_headers: Vec<String>,
+    /// How long, in seconds, browsers may cache a preflight's answer
+    pub max_age: Option<u64>,
+}
+
+impl CorsConfig {
+    /// Allow `origins` to use the simple methods, with no extra request
+    /// headers
+    pub fn new(origins: Vec<String>) -> CorsConfig {
+        CorsConfig {
+            allowed_origins: origins,
+            allowed_methods: DEFAULT_METHODS.iter().map(|m| m.to_string()).collect(),
+            allowed_headers: Vec::new(),
+            max_age: Some(DEFAULT_MAX_AGE),
+        }
+    }
+
+    /// Whether requests from `origin` are allowed. Origins are compared
+    /// exactly, except for letter case.
+    pub fn allows_origin(&self, origin: &str) -> bool {
+        self.allowed_origins
+            .iter()
+            .any(|allowed| allowed == "*" || allowed.eq_ignore_ascii_case(origin))
+    }
+
+    /// A `204` answering `request` if it is a preflight. The answer only
+    /// carries `Access-Control-Allow-*` headers when the origin is allowed,
+    /// so the browser blocks the real request otherwise.
+    pub fn preflight(&self, request: &Request) -> Option<Response> {
+        let origin = request.headers.get("Origin")?;
+        if request.method != Method::Options
+            || !request.headers.contains("Access-Control-Request-Method")
+        {
+            return None;
+        }
+
+        let mut response = Response::new(StatusCode::NO_CONTENT).header("Vary", "Origin");
+        if !self.allows_origin(origin) {
+            return Some(response);
+        }
+
+        response = response
+            .header("Access-Control-Allow-Origin", origin)
+            .header(
+                "Access-Control-Allow-Methods",
+                &self.allowed_methods.join(", "),
+            );
+        if !self.allowed_headers.is_empty() {
+            response = response.header(
+                "Access-Control-Allow-Headers",
+                &self.allowed_headers.join(", "),
+            );
+        }
+        if let Some(max_age) = self.max_age {
+            response = response.header("Access-Control-Max-Age", &max_age.to_string());
+        }
+        Some(response)
+    }
+
+    /// Let the browser hand `response` to the page if `request` came from an
+    /// allowed origin
+    pub fn apply(&self, request: &Request, response: &mut Response) {
+        self.apply_origin(request.headers.get("Origin"), response);
+    }
+
+    fn apply_origin(&self, origin: Option<&str>, response: &mut Response) {
+        let origin = match origin {
+            Some(origin) => origin,
+            None => return,
+        };
+
+        // The answer depends on the origin, so caches must key on it
+        response.vary("Origin");
+        if self.allows_origin(origin) {
+            response.headers.set("Access-Control-Allow-Origin", origin);
+        }
+    }
+}
+
+impl Middleware for CorsConfig {
+    /// Answer preflights, and let pages on allowed origins read every other
+    /// response. Preflights never carry credentials, so this goes outside
+    /// any auth.
+    fn handle(&self, req: Request, next: &dyn Fn(Request) -> Response) -> Response {
+        if let Some(preflight) = self.preflight(&req) {
+            return preflight;
+        }
+        let origin = req.headers.get("Origin").map(String::from);
+        let mut response = next(req);
+        self.apply_origin(origin.as_deref(), &mut response);
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::parse_request_head;
+
+    const ALLOWED: &str = "https://app.example.com";
+
+    fn cors() -> CorsConfig {
+        CorsConfig {
+            allowed_headers: vec!["Content-Type".to_string()],
+            ..CorsConfig::new(vec![ALLOWED.to_string()])
+        }
+    }
+
+    fn request(method: &str, headers: &str) -> Request {
+        let head = format!("{} /api HTTP/1.1\r\nHost: a\r\n{}\r\n", method, headers);
+        parse_request_head(head.as_bytes(), 100).unwrap()
+    }
+
+    fn handled(request: Request) -> Response {
+        cors().handle(request, &|_| Response::new(StatusCode::OK).body("data"))
+    }
+
+    #[test]
+    fn a_preflight_gets_the_allowed_methods_and_headers() {
+        let response = handled(request(
+            "OPTIONS",
+            "Origin: https://app.example.com\r\n\
+             Access-Control-Request-Method: POST\r\n\
+             Access-Control-Request-Headers: content-type\r\n",
+        ));
+        assert_eq!(response.status, StatusCode::NO_CONTENT);
+        let header = |name| response.headers.get(name);
+        assert_eq!(header("Access-Control-Allow-Origin"), Some(ALLOWED));
+        assert_eq!(
+            header("Access-Control-Allow-Methods"),
+            Some("GET, HEAD, POST")
+        );
+        assert_eq!(header("Access-Control-Allow-Headers"), Some("Content-Type"));
+        assert_eq!(header("Access-Control-Max-Age"), Some("600"));
+        assert_eq!(header("Vary"), Some("Origin"));
+    }
+
+    #[test]
+    fn only_an_allowed_origin_is_echoed_on_a_get() {
+        let response = handled(request("GET", "Origin: https://app.example.com\r\n"));
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(
+            response.headers.get("Access-Control-Allow-Origin"),
+            Some(ALLOWED)
+        );
+
+        let response = handled(request("GET", "Origin: https://evil.example\r\n"));
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.headers.get("Access-Control-Allow-Origin"), None);
+        assert_eq!(response.headers.get("Vary"), Some("Origin"));
+    }
+
+    #[test]
+    fn a_disallowed_preflight_gets_no_cors_headers() {
+        let response = handled(request(
+            "OPTIONS",
+            "Origin: https://evil.example\r\nAccess-Control-Request-Method: POST\r\n",
+        ));
+        assert_eq!(response.status, StatusCode::NO_CONTENT);
+        assert!(!response
+            .headers
+            .iter()
+            .any(|(name, _)| name.starts_with("Access-Control-")));
+    }
+
+    #[test]
+    fn an_options_request_without_a_method_is_not_a_preflight() {
+        let request = request("OPTIONS", "Origin: https://app.example.com\r\n");
+        assert!(cors().preflight(&request).is_none());
+    }
+}
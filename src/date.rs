@@ -0,0 +1,247 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// A UTC calendar date and time, to the second
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: i64,
+    /// 1 to 12
+    pub month: u32,
+    /// 1 to 31
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+}
+
+impl DateTime {
+    pub fn from_system_time(time: SystemTime) -> DateTime {
+        let secs = match time.duration_since(UNIX_EPOCH) {
+            Ok(since) => since.as_secs() as i64,
+            Err(before) => -(before.duration().as_secs() as i64),
+        };
+        DateTime::from_unix(secs)
+    }
+
+    /// Convert seconds since the Unix epoch, using Howard Hinnant's
+    /// `civil_from_days` algorithm for the date
+    pub fn from_unix(secs: i64) -> DateTime {
+        let days = secs.div_euclid(86_400);
+        let time_of_day = secs.rem_euclid(86_400) as u32;
+
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let day_of_era = z.rem_euclid(146_097);
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let mp = (5 * day_of_year + 2) / 153;
+        let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        DateTime {
+            year,
+            month,
+            day,
+            hour: time_of_day / 3600,
+            minute: time_of_day % 3600 / 60,
+            second: time_of_day % 60,
+        }
+    }
+
+    /// Seconds since the Unix epoch, the inverse of [`DateTime::from_unix`]
+    /// using `days_from_civil`
+    pub fn to_unix(&self) -> i64 {
+        let year = if self.month <= 2 {
+            self.year - 1
+        } else {
+            self.year
+        };
+        let month = i64::from(self.month);
+        let era = year.div_euclid(400);
+        let year_of_era = year.rem_euclid(400);
+        let mp = if month > 2 { month - 3 } else { month + 9 };
+        let day_of_year = (153 * mp + 2) / 5 + i64::from(self.day) - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * 146_097 + day_of_era - 719_468;
+
+        days * 86_400
+            + i64::from(self.hour) * 3600
+            + i64::from(self.minute) * 60
+            + i64::from(self.second)
+    }
+
+    pub fn to_system_time(&self) -> SystemTime {
+        let secs = self.to_unix();
+        if secs >= 0 {
+            UNIX_EPOCH + Duration::from_secs(secs as u64)
+        } else {
+            UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs())
+        }
+    }
+
+    fn month_name(&self) -> &'static str {
+        MONTHS[(self.month - 1) as usize]
+    }
+
+    fn weekday_name(&self) -> &'static str {
+        // The epoch was a Thursday
+        let days = self.to_unix().div_euclid(86_400);
+        WEEKDAYS[(days + 4).rem_euclid(7) as usize]
+    }
+}
+
+/// Format a time as the Common Log Format uses it, e.g.
+/// `10/Oct/2000:13:55:36 +0000`. Times are always given in UTC.
+pub fn format_clf_time(time: SystemTime) -> String {
+    let dt = DateTime::from_system_time(time);
+    format!(
+        "{:02}/{}/{:04}:{:02}:{:02}:{:02} +0000",
+        dt.day,
+        dt.month_name(),
+        dt.year,
+        dt.hour,
+        dt.minute,
+        dt.second
+    )
+}
+
+/// Format a time in the RFC 3339 form, e.g. `2000-10-10T13:55:36Z`
+pub fn format_rfc3339(time: SystemTime) -> String {
+    let dt = DateTime::from_system_time(time);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        dt.year, dt.month, dt.day, dt.hour, dt.minute, dt.second
+    )
+}
+
+/// Format a time as an HTTP date (the RFC 1123 form), e.g.
+/// `Sun, 06 Nov 1994 08:49:37 GMT`
+pub fn format_http_date(time: SystemTime) -> String {
+    let dt = DateTime::from_system_time(time);
+    format!(
+        "{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+        dt.weekday_name(),
+        dt.day,
+        dt.month_name(),
+        dt.year,
+        dt.hour,
+        dt.minute,
+        dt.second
+    )
+}
+
+/// Parse an HTTP date in the RFC 1123 form. The weekday is not checked
+/// against the date. Returns `None` for anything else, including the
+/// obsolete RFC 850 and asctime forms.
+pub fn parse_http_date(date: &str) -> Option<SystemTime> {
+    let mut parts = date.split_whitespace();
+    let weekday = parts.next()?.strip_suffix(',')?;
+    if !WEEKDAYS.contains(&weekday) {
+        return None;
+    }
+    let day = parts.next()?.parse().ok()?;
+    let month_name = parts.next()?;
+    let month = MONTHS.iter().position(|&m| m == month_name)? as u32 + 1;
+    let year = parts.next()?.parse().ok()?;
+
+    let mut time = parts.next()?.split(':').map(|n| n.parse::<u32>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+    if time.next().is_some() || parts.next()? != "GMT" || parts.next().is_some() {
+        return None;
+    }
+    if !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    let dt = DateTime {
+        year,
+        month,
+        day,
+        hour,
+        minute,
+        second,
+    };
+    Some(dt.to_system_time())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sun, 06 Nov 1994 08:49:37 GMT, RFC 7231's example date
+    const EXAMPLE: u64 = 784_111_777;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn formats_an_http_date() {
+        assert_eq!(
+            format_http_date(at(EXAMPLE)),
+            "Sun, 06 Nov 1994 08:49:37 GMT"
+        );
+        assert_eq!(
+            format_http_date(UNIX_EPOCH),
+            "Thu, 01 Jan 1970 00:00:00 GMT"
+        );
+        assert_eq!(
+            format_http_date(at(951_782_400)),
+            "Tue, 29 Feb 2000 00:00:00 GMT"
+        );
+    }
+
+    #[test]
+    fn http_dates_round_trip() {
+        for secs in [0, EXAMPLE, 951_782_400, 1_700_000_000, 4_102_444_799] {
+            let formatted = format_http_date(at(secs));
+            assert_eq!(parse_http_date(&formatted), Some(at(secs)), "{}", formatted);
+        }
+    }
+
+    #[test]
+    fn other_date_forms_are_not_parsed() {
+        for date in [
+            "Sunday, 06-Nov-94 08:49:37 GMT",
+            "Sun Nov  6 08:49:37 1994",
+            "Sun, 06 Nov 1994 08:49:37 UTC",
+            "Sun, 32 Nov 1994 08:49:37 GMT",
+            "Sun, 06 Nov 1994 24:00:00 GMT",
+            "Sun, 06 Nov 1994 08:49 GMT",
+            "",
+        ] {
+            assert_eq!(parse_http_date(date), None, "{:?}", date);
+        }
+    }
+
+    #[test]
+    fn civil_dates_convert_both_ways() {
+        let dt = DateTime::from_unix(EXAMPLE as i64);
+        assert_eq!(
+            dt,
+            DateTime {
+                year: 1994,
+                month: 11,
+                day: 6,
+                hour: 8,
+                minute: 49,
+                second: 37
+            }
+        );
+        assert_eq!(dt.to_unix(), EXAMPLE as i64);
+        assert_eq!(DateTime::from_unix(-1).to_unix(), -1);
+        assert_eq!(DateTime::from_unix(-1).year, 1969);
+    }
+
+    #[test]
+    fn formats_the_log_and_rfc_3339_forms() {
+        assert_eq!(format_clf_time(at(EXAMPLE)), "06/Nov/1994:08:49:37 +0000");
+        assert_eq!(format_rfc3339(at(EXAMPLE)), "1994-11-06T08:49:37Z");
+    }
+}
@@ -0,0 +1,60 @@
+use std::{error::Error, fmt, io, path::PathBuf};
+
+use crate::request::ParseError;
+
+#[derive(Debug)]
+pub enum ServerError {
+    Io(io::Error),
+    Parse(ParseError),
+    /// A file the server needed to respond with does not exist
+    NotFound(PathBuf),
+    /// A requested path is outside of the directory being served from
+    Forbidden(String),
+    /// The client did not send its request within the read or header timeout
+    Timeout,
+    /// The client closed the connection without sending anything
+    ConnectionClosed,
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServerError::Io(e) => write!(f, "I/O error: {}", e),
+            ServerError::Parse(e) => write!(f, "bad request: {}", e),
+            ServerError::NotFound(path) => write!(f, "{} not found", path.display()),
+            ServerError::Forbidden(path) => write!(f, "access to {} is forbidden", path),
+            ServerError::Timeout => write!(f, "timed out waiting for the client"),
+            ServerError::ConnectionClosed => write!(f, "connection closed before a request"),
+        }
+    }
+}
+
+impl Error for ServerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ServerError::Io(e) => Some(e),
+            ServerError::Parse(e) => Some(e),
+            ServerError::NotFound(_)
+            | ServerError::Forbidden(_)
+            | ServerError::Timeout
+            | ServerError::ConnectionClosed => None,
+        }
+    }
+}
+
+impl From<io::Error> for ServerError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            // Which of these a timed out socket read reports is platform
+            // dependent
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => ServerError::Timeout,
+            _ => ServerError::Io(e),
+        }
+    }
+}
+
+impl From<ParseError> for ServerError {
+    fn from(e: ParseError) -> Self {
+        ServerError::Parse(e)
+    }
+}
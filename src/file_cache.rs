@@ -0,0 +1,169 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        RwLock,
+    },
+    time::SystemTime,
+};
+
+/// Contents of static files kept in memory, shared by every worker.
+///
+/// An entry is only used while the file's modification time and size on
+/// disk still match it, so edited files are reloaded on their next request.
+pub struct FileCache {
+    files: RwLock<HashMap<PathBuf, CachedFile>>,
+    /// Most bytes of file contents held at once
+    max_size: usize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+struct CachedFile {
+    contents: Vec<u8>,
+    modified: Option<SystemTime>,
+}
+
+impl FileCache {
+    /// A cache holding at most `max_size` bytes. A size of 0 caches nothing.
+    pub fn new(max_size: usize) -> FileCache {
+        FileCache {
+            files: RwLock::new(HashMap::new()),
+            max_size,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// The contents of the file at `path`, whose current metadata is
+    /// `metadata`, from memory if they are still fresh or else from disk
+    pub fn read(&self, path: &Path, metadata: &fs::Metadata) -> io::Result<Vec<u8>> {
+        let modified = metadata.modified().ok();
+        let fresh = |cached: &CachedFile| {
+            modified.is_some()
+                && cached.modified == modified
+                && cached.contents.len() as u64 == metadata.len()
+        };
+
+        if let Some(cached) = self.files.read().unwrap().get(path) {
+            if fresh(cached) {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(cached.contents.clone());
+            }
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let contents = fs::read(path)?;
+        // Without a modification time there is no telling when the entry
+        // goes stale
+        if modified.is_some() && contents.len() <= self.max_size {
+            self.insert(path, contents.clone(), modified);
+        }
+        Ok(contents)
+    }
+
+    /// Requests answered from memory
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Requests that had to read the file from disk
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    /// Bytes of file contents currently held
+    pub fn size(&self) -> usize {
+        total_size(&self.files.read().unwrap())
+    }
+
+    fn insert(&self, path: &Path, contents: Vec<u8>, modified: Option<SystemTime>) {
+        let mut files = self.files.write().unwrap();
+        files.remove(path);
+
+        // Make room by dropping whatever comes first; which files are hot
+        // isn't tracked
+        let mut size = total_size(&files);
+        while size + contents.len() > self.max_size {
+            let victim = match files.keys().next() {
+                Some(victim) => victim.clone(),
+                None => break,
+            };
+            if let Some(evicted) = files.remove(&victim) {
+                size -= evicted.contents.len();
+            }
+        }
+
+        files.insert(path.to_path_buf(), CachedFile { contents, modified });
+    }
+}
+
+fn total_size(files: &HashMap<PathBuf, CachedFile>) -> usize {
+    files.values().map(|file| file.contents.len()).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, time::Duration};
+
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn read(cache: &FileCache, path: &Path) -> Vec<u8> {
+        cache.read(path, &fs::metadata(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn a_second_read_hits_the_cache() {
+        let dir = TempDir::new();
+        let file = dir.file("page.html", "<p>hot</p>");
+        let cache = FileCache::new(1024);
+
+        assert_eq!(read(&cache, &file), b"<p>hot</p>");
+        assert_eq!((cache.hits(), cache.misses()), (0, 1));
+        assert_eq!(read(&cache, &file), b"<p>hot</p>");
+        assert_eq!((cache.hits(), cache.misses()), (1, 1));
+        assert_eq!(cache.size(), 10);
+    }
+
+    #[test]
+    fn a_changed_modification_time_reloads_the_file() {
+        let dir = TempDir::new();
+        let file = dir.file("page.html", "old");
+        let cache = FileCache::new(1024);
+        read(&cache, &file);
+
+        // Same size, so only the modification time gives the change away
+        fs::write(&file, "new").unwrap();
+        let modified = fs::metadata(&file).unwrap().modified().unwrap();
+        File::options()
+            .write(true)
+            .open(&file)
+            .unwrap()
+            .set_modified(modified + Duration::from_secs(10))
+            .unwrap();
+
+        assert_eq!(read(&cache, &file), b"new");
+        assert_eq!(cache.misses(), 2);
+        assert_eq!(read(&cache, &file), b"new");
+        assert_eq!(cache.hits(), 1);
+    }
+
+    #[test]
+    fn the_cache_stays_within_its_size() {
+        let dir = TempDir::new();
+        let small = dir.file("small.txt", "12345");
+        let other = dir.file("other.txt", "67890");
+        let large = dir.file("large.txt", "x".repeat(20));
+        let cache = FileCache::new(8);
+
+        read(&cache, &small);
+        read(&cache, &other);
+        assert_eq!(cache.size(), 5);
+        // Too large to cache at all, but still read
+        assert_eq!(read(&cache, &large).len(), 20);
+        assert_eq!(cache.size(), 5);
+    }
+}
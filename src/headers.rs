@@ -0,0 +1,275 @@
+use std::{borrow::Cow, collections::HashMap, str};
+
+use crate::request::ParseError;
+
+/// A set of HTTP headers with case-insensitive names.
+///
+/// A name may carry several values (e.g. repeated `Set-Cookie` lines), which
+/// are kept in the order they were inserted. Iteration yields headers in the
+/// order their names were first seen, using the casing of that first
+/// insertion.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Headers {
+    map: HashMap<String, (String, Vec<String>)>,
+    order: Vec<String>,
+}
+
+impl Headers {
+    pub fn new() -> Headers {
+        Headers::default()
+    }
+
+    /// Add a value for `name`, keeping any values already present. Control
+    /// characters other than tab are dropped from both, as a CR or LF would
+    /// let the value end its header line and start another.
+    pub fn insert(&mut self, name: &str, value: &str) {
+        let (name, value) = (strip_controls(name), strip_controls(value));
+        let key = name.to_ascii_lowercase();
+        match self.map.get_mut(&key) {
+            Some((_, values)) => values.push(value.to_string()),
+            None => {
+                self.order.push(key.clone());
+                self.map
+                    .insert(key, (name.into_owned(), vec![value.into_owned()]));
+            }
+        }
+    }
+
+    /// Replace all values for `name` with `value`
+    pub fn set(&mut self, name: &str, value: &str) {
+        self.remove(name);
+        self.insert(name, value);
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Vec<String>> {
+        let key = name.to_ascii_lowercase();
+        let (_, values) = self.map.remove(&key)?;
+        self.order.retain(|k| *k != key);
+        Some(values)
+    }
+
+    /// The first value for `name`
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.get_all(name).first().map(String::as_str)
+    }
+
+    /// Every value for `name`, in insertion order
+    pub fn get_all(&self, name: &str) -> &[String] {
+        self.map
+            .get(&name.to_ascii_lowercase())
+            .map(|(_, values)| values.as_slice())
+            .unwrap_or(&[])
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.map.contains_key(&name.to_ascii_lowercase())
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.values().map(|(_, values)| values.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Each (name, value) pair, one per value
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.order.iter().flat_map(move |key| {
+            let (name, values) = &self.map[key];
+            values
+                .iter()
+                .map(move |value| (name.as_str(), value.as_str()))
+        })
+    }
+}
+
+/// `text` without any control characters but tab
+fn strip_controls(text: &str) -> Cow<'_, str> {
+    let is_control = |c: char| c.is_ascii_control() && c != '\t';
+    if text.contains(is_control) {
+        Cow::Owned(text.chars().filter(|&c| !is_control(c)).collect())
+    } else {
+        Cow::Borrowed(text)
+    }
+}
+
+/// Parse a header block, starting at the first header line and ending at
+/// the blank line that separates headers from the body. Fails with
+/// `TooManyHeaders` as soon as a line past the first `max_headers` is seen.
+pub fn parse_headers(buffer: &[u8], max_headers: usize) -> Result<Headers, ParseError> {
+    let end = find_header_end(buffer).ok_or(ParseError::IncompleteHeaders)?;
+    let block = str::from_utf8(&buffer[..end]).map_err(|_| ParseError::InvalidEncoding)?;
+
+    let mut headers = Headers::new();
+    for (count, line) in block.split('\n').enumerate() {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        if line.is_empty() {
+            break;
+        }
+        if count == max_headers {
+            return Err(ParseError::TooManyHeaders);
+        }
+
+        let (name, value) = match line.find(':') {
+            Some(colon) => (&line[..colon], &line[colon + 1..]),
+            None => return Err(ParseError::MalformedHeader(line.to_string())),
+        };
+
+        // Whitespace before the colon or a folded continuation line is not
+        // allowed (RFC 7230 section 3.2.4)
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(ParseError::MalformedHeader(line.to_string()));
+        }
+
+        headers.insert(name, value.trim());
+    }
+
+    Ok(headers)
+}
+
+/// The offset just past the blank line ending a header block, if present.
+/// A header block with no headers at all is just the blank line.
+pub fn find_header_end(buffer: &[u8]) -> Option<usize> {
+    if buffer.starts_with(b"\r\n") {
+        return Some(2);
+    }
+    if buffer.starts_with(b"\n") {
+        return Some(1);
+    }
+
+    find_blank_line(buffer)
+}
+
+/// The offset just past the first blank line inside `buffer`, ended by
+/// either CRLF or a bare LF. Unlike [`find_header_end`], a line break at the
+/// very start doesn't count, so this can search part of a header block.
+pub fn find_blank_line(buffer: &[u8]) -> Option<usize> {
+    let crlf = buffer
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .map(|pos| pos + 4);
+    let lf = buffer
+        .windows(2)
+        .position(|window| window == b"\n\n")
+        .map(|pos| pos + 2);
+    crlf.into_iter().chain(lf).min()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn control_characters_cannot_inject_headers() {
+        let mut headers = Headers::new();
+        headers.insert("X-Custom", "a\r\nInjected: yes");
+        headers.insert("X-Bad\rName", "b\0");
+        headers.insert("X-Tab", "c\td");
+        assert_eq!(headers.get("X-Custom"), Some("aInjected: yes"));
+        assert_eq!(headers.get("X-BadName"), Some("b"));
+        assert_eq!(headers.get("X-Tab"), Some("c\td"));
+        assert_eq!(headers.len(), 3);
+    }
+
+    #[test]
+    fn parses_a_header_block() {
+        let block = b"Host: example.com\r\nAccept:  text/html \r\nContent-Length: 0\r\n\r\nbody";
+        let headers = parse_headers(block, 100).unwrap();
+        assert_eq!(headers.len(), 3);
+        assert_eq!(headers.get("Host"), Some("example.com"));
+        assert_eq!(headers.get("Accept"), Some("text/html"));
+        let names: Vec<&str> = headers.iter().map(|(name, _)| name).collect();
+        assert_eq!(names, ["Host", "Accept", "Content-Length"]);
+    }
+
+    #[test]
+    fn lookups_ignore_case() {
+        let headers = parse_headers(b"Content-Type: text/plain\r\n\r\n", 100).unwrap();
+        assert_eq!(headers.get("content-type"), Some("text/plain"));
+        assert_eq!(headers.get("CONTENT-TYPE"), Some("text/plain"));
+        assert!(headers.contains("cOnTeNt-TyPe"));
+        assert_eq!(headers.get("Content-Length"), None);
+    }
+
+    #[test]
+    fn repeated_headers_keep_every_value() {
+        let block = b"Set-Cookie: a=1\r\nset-cookie: b=2\r\nSET-COOKIE: c=3\r\n\r\n";
+        let headers = parse_headers(block, 100).unwrap();
+        assert_eq!(headers.get_all("Set-Cookie"), ["a=1", "b=2", "c=3"]);
+        assert_eq!(headers.get("Set-Cookie"), Some("a=1"));
+        let pairs: Vec<(&str, &str)> = headers.iter().collect();
+        assert_eq!(
+            pairs,
+            [
+                ("Set-Cookie", "a=1"),
+                ("Set-Cookie", "b=2"),
+                ("Set-Cookie", "c=3")
+            ]
+        );
+    }
+
+    #[test]
+    fn a_header_line_without_a_colon_is_malformed() {
+        assert_eq!(
+            parse_headers(b"Host: a\r\nNoColonHere\r\n\r\n", 100),
+            Err(ParseError::MalformedHeader("NoColonHere".to_string()))
+        );
+        assert_eq!(
+            parse_headers(b"Host : a\r\n\r\n", 100),
+            Err(ParseError::MalformedHeader("Host : a".to_string()))
+        );
+        assert_eq!(
+            parse_headers(b": a\r\n\r\n", 100),
+            Err(ParseError::MalformedHeader(": a".to_string()))
+        );
+        assert_eq!(
+            parse_headers(b"Host: a\r\n", 100),
+            Err(ParseError::IncompleteHeaders)
+        );
+    }
+
+    #[test]
+    fn set_replaces_and_remove_drops_every_value() {
+        let mut headers = Headers::new();
+        headers.insert("Vary", "Accept");
+        headers.insert("vary", "Origin");
+        headers.set("VARY", "Accept-Encoding");
+        assert_eq!(headers.get_all("Vary"), ["Accept-Encoding"]);
+        assert_eq!(
+            headers.remove("vary"),
+            Some(vec!["Accept-Encoding".to_string()])
+        );
+        assert!(headers.is_empty());
+        assert_eq!(headers.remove("Vary"), None);
+    }
+
+    #[test]
+    fn the_first_blank_line_ends_the_block() {
+        assert_eq!(find_header_end(b"\r\nbody"), Some(2));
+        assert_eq!(find_header_end(b"A: 1\r\n\r\nB: 2\r\n\r\n"), Some(8));
+        assert_eq!(find_header_end(b"A: 1\n\nbody\r\n\r\n"), Some(6));
+        assert_eq!(find_blank_line(b"\nHost: a\r\n"), None);
+        assert_eq!(find_blank_line(b"\r\n\r\n"), Some(4));
+    }
+
+    fn header_lines(count: usize) -> String {
+        let mut block: String = (0..count).map(|i| format!("X-{}: {}\r\n", i, i)).collect();
+        block.push_str("\r\n");
+        block
+    }
+
+    #[test]
+    fn exactly_max_headers_are_accepted() {
+        let headers = parse_headers(header_lines(100).as_bytes(), 100).unwrap();
+        assert_eq!(headers.len(), 100);
+    }
+
+    #[test]
+    fn one_header_over_the_limit_is_rejected() {
+        assert!(matches!(
+            parse_headers(header_lines(101).as_bytes(), 100),
+            Err(ParseError::TooManyHeaders)
+        ));
+    }
+}
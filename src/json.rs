@@ -0,0 +1,648 @@
+use std::{collections::HashMap, convert::TryFrom, error::Error, fmt, str};
+
+/// Deepest nesting of arrays and objects `parse` accepts, so a hostile body
+/// can't overflow the stack
+const MAX_DEPTH: usize = 128;
+
+/// A JSON value. Object members keep the order they were written in.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// The member `key` of an object, or `None` if this isn't an object or
+    /// has no such member
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    /// Parse the member `key` of an object into a `T`. A missing member is
+    /// parsed as `null`, so it only succeeds for types such as `Option`.
+    pub fn field<T: FromJson>(&self, key: &str) -> Result<T, JsonError> {
+        match self {
+            Json::Object(_) => T::from_json(self.get(key).unwrap_or(&Json::Null))
+                .map_err(|e| JsonError::Type(format!("{} in field {:?}", e.expected(), key))),
+            _ => Err(JsonError::Type("an object".to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Json {
+    /// Serialize the value as compact JSON
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => f.write_str("null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            // JSON has no NaN or infinities
+            Json::Number(n) if !n.is_finite() => f.write_str("null"),
+            Json::Number(n) => write!(f, "{}", n),
+            Json::String(s) => write_string(f, s),
+            Json::Array(items) => {
+                f.write_str("[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                f.write_str("]")
+            }
+            Json::Object(members) => {
+                f.write_str("{")?;
+                for (i, (name, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write_string(f, name)?;
+                    write!(f, ":{}", value)?;
+                }
+                f.write_str("}")
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    f.write_str("\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if c < ' ' => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    f.write_str("\"")
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JsonError {
+    /// The text is not valid JSON. Holds the byte offset parsing gave up at.
+    Syntax(usize),
+    /// The JSON is valid but not of the shape wanted. Holds what was
+    /// expected instead.
+    Type(String),
+}
+
+impl JsonError {
+    fn expected(&self) -> &str {
+        match self {
+            JsonError::Type(expected) => expected,
+            JsonError::Syntax(_) => "valid JSON",
+        }
+    }
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JsonError::Syntax(offset) => write!(f, "invalid JSON at byte {}", offset),
+            JsonError::Type(expected) => write!(f, "expected {}", expected),
+        }
+    }
+}
+
+impl Error for JsonError {}
+
+/// Types that can be serialized as JSON, for [`Response::json`].
+///
+/// [`Response::json`]: crate::response::Response::json
+pub trait ToJson {
+    fn to_json(&self) -> Json;
+}
+
+/// Types that can be built from parsed JSON, for [`from_body`]
+pub trait FromJson: Sized {
+    fn from_json(json: &Json) -> Result<Self, JsonError>;
+}
+
+impl ToJson for Json {
+    fn to_json(&self) -> Json {
+        self.clone()
+    }
+}
+
+impl FromJson for Json {
+    fn from_json(json: &Json) -> Result<Json, JsonError> {
+        Ok(json.clone())
+    }
+}
+
+impl ToJson for bool {
+    fn to_json(&self) -> Json {
+        Json::Bool(*self)
+    }
+}
+
+impl FromJson for bool {
+    fn from_json(json: &Json) -> Result<bool, JsonError> {
+        match json {
+            Json::Bool(b) => Ok(*b),
+            _ => Err(JsonError::Type("a boolean".to_string())),
+        }
+    }
+}
+
+impl ToJson for f64 {
+    fn to_json(&self) -> Json {
+        Json::Number(*self)
+    }
+}
+
+impl FromJson for f64 {
+    fn from_json(json: &Json) -> Result<f64, JsonError> {
+        match json {
+            Json::Number(n) => Ok(*n),
+            _ => Err(JsonError::Type("a number".to_string())),
+        }
+    }
+}
+
+/// Integers are carried as `f64`, so ones past 2^53 lose precision
+macro_rules! integer_json {
+    ($($int:ty),*) => {$(
+        impl ToJson for $int {
+            fn to_json(&self) -> Json {
+                Json::Number(*self as f64)
+            }
+        }
+
+        impl FromJson for $int {
+            fn from_json(json: &Json) -> Result<$int, JsonError> {
+                let expected = || JsonError::Type(format!("a {} integer", stringify!($int)));
+                match json {
+                    Json::Number(n) if n.fract() == 0.0 => {
+                        <$int>::try_from(*n as i128).map_err(|_| expected())
+                    }
+                    _ => Err(expected()),
+                }
+            }
+        }
+    )*};
+}
+
+integer_json!(i32, i64, u16, u32, u64, usize);
+
+impl ToJson for str {
+    fn to_json(&self) -> Json {
+        Json::String(self.to_string())
+    }
+}
+
+impl ToJson for String {
+    fn to_json(&self) -> Json {
+        Json::String(self.clone())
+    }
+}
+
+impl FromJson for String {
+    fn from_json(json: &Json) -> Result<String, JsonError> {
+        match json {
+            Json::String(s) => Ok(s.clone()),
+            _ => Err(JsonError::Type("a string".to_string())),
+        }
+    }
+}
+
+impl<T: ToJson> ToJson for Option<T> {
+    fn to_json(&self) -> Json {
+        self.as_ref().map_or(Json::Null, ToJson::to_json)
+    }
+}
+
+impl<T: FromJson> FromJson for Option<T> {
+    fn from_json(json: &Json) -> Result<Option<T>, JsonError> {
+        match json {
+            Json::Null => Ok(None),
+            json => T::from_json(json).map(Some),
+        }
+    }
+}
+
+impl<T: ToJson> ToJson for [T] {
+    fn to_json(&self) -> Json {
+        Json::Array(self.iter().map(ToJson::to_json).collect())
+    }
+}
+
+impl<T: ToJson> ToJson for Vec<T> {
+    fn to_json(&self) -> Json {
+        self.as_slice().to_json()
+    }
+}
+
+impl<T: FromJson> FromJson for Vec<T> {
+    fn from_json(json: &Json) -> Result<Vec<T>, JsonError> {
+        match json {
+            Json::Array(items) => items.iter().map(T::from_json).collect(),
+            _ => Err(JsonError::Type("an array".to_string())),
+        }
+    }
+}
+
+impl<T: ToJson> ToJson for HashMap<String, T> {
+    /// Members are sorted by name, so the output doesn't change from run to
+    /// run
+    fn to_json(&self) -> Json {
+        let mut members: Vec<_> = self
+            .iter()
+            .map(|(name, value)| (name.clone(), value.to_json()))
+            .collect();
+        members.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Json::Object(members)
+    }
+}
+
+impl<T: FromJson> FromJson for HashMap<String, T> {
+    fn from_json(json: &Json) -> Result<HashMap<String, T>, JsonError> {
+        match json {
+            Json::Object(members) => members
+                .iter()
+                .map(|(name, value)| Ok((name.clone(), T::from_json(value)?)))
+                .collect(),
+            _ => Err(JsonError::Type("an object".to_string())),
+        }
+    }
+}
+
+impl<T: ToJson + ?Sized> ToJson for &T {
+    fn to_json(&self) -> Json {
+        (**self).to_json()
+    }
+}
+
+/// Parse a request body as JSON into a `T`
+pub fn from_body<T: FromJson>(body: &[u8]) -> Result<T, JsonError> {
+    let text = str::from_utf8(body).map_err(|e| JsonError::Syntax(e.valid_up_to()))?;
+    T::from_json(&parse(text)?)
+}
+
+/// Parse a JSON document. Whitespace may surround the value, but nothing
+/// else may follow it.
+pub fn parse(text: &str) -> Result<Json, JsonError> {
+    let mut parser = Parser {
+        bytes: text.as_bytes(),
+        pos: 0,
+    };
+    let value = parser.value(0)?;
+    parser.skip_whitespace();
+    if parser.pos != parser.bytes.len() {
+        return Err(parser.error());
+    }
+    Ok(value)
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self) -> JsonError {
+        JsonError::Syntax(self.pos)
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), JsonError> {
+        if self.peek() != Some(byte) {
+            return Err(self.error());
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn literal(&mut self, word: &str, value: Json) -> Result<Json, JsonError> {
+        if !self.bytes[self.pos..].starts_with(word.as_bytes()) {
+            return Err(self.error());
+        }
+        self.pos += word.len();
+        Ok(value)
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Json, JsonError> {
+        if depth >= MAX_DEPTH {
+            return Err(self.error());
+        }
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'n') => self.literal("null", Json::Null),
+            Some(b't') => self.literal("true", Json::Bool(true)),
+            Some(b'f') => self.literal("false", Json::Bool(false)),
+            Some(b'"') => self.string().map(Json::String),
+            Some(b'[') => self.array(depth),
+            Some(b'{') => self.object(depth),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            _ => Err(self.error()),
+        }
+    }
+
+    fn array(&mut self, depth: usize) -> Result<Json, JsonError> {
+        self.expect(b'[')?;
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(Json::Array(items));
+        }
+        loop {
+            items.push(self.value(depth + 1)?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(Json::Array(items));
+                }
+                _ => return Err(self.error()),
+            }
+        }
+    }
+
+    fn object(&mut self, depth: usize) -> Result<Json, JsonError> {
+        self.expect(b'{')?;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(Json::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            let name = self.string()?;
+            self.skip_whitespace();
+            self.expect(b':')?;
+            members.push((name, self.value(depth + 1)?));
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(Json::Object(members));
+                }
+                _ => return Err(self.error()),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, JsonError> {
+        let start = self.pos;
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+        // A leading zero can't be followed by more digits
+        match self.peek() {
+            Some(b'0') => self.pos += 1,
+            Some(b'1'..=b'9') => self.digits(),
+            _ => return Err(self.error()),
+        }
+        if self.peek() == Some(b'.') {
+            self.pos += 1;
+            self.require_digits()?;
+        }
+        if let Some(b'e' | b'E') = self.peek() {
+            self.pos += 1;
+            if let Some(b'+' | b'-') = self.peek() {
+                self.pos += 1;
+            }
+            self.require_digits()?;
+        }
+
+        // Only ASCII was consumed, and Rust accepts everything JSON does
+        str::from_utf8(&self.bytes[start..self.pos])
+            .ok()
+            .and_then(|number| number.parse().ok())
+            .map(Json::Number)
+            .ok_or(JsonError::Syntax(start))
+    }
+
+    fn digits(&mut self) {
+        while let Some(b'0'..=b'9') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn require_digits(&mut self) -> Result<(), JsonError> {
+        let start = self.pos;
+        self.digits();
+        if self.pos == start {
+            return Err(self.error());
+        }
+        Ok(())
+    }
+
+    fn string(&mut self) -> Result<String, JsonError> {
+        self.expect(b'"')?;
+        let mut s = String::new();
+        loop {
+            let start = self.pos;
+            while let Some(b) = self.peek() {
+                if b == b'"' || b == b'\\' || b < b' ' {
+                    break;
+                }
+                self.pos += 1;
+            }
+            // The input is a str and the run stops at ASCII, so this can't
+            // split a character
+            s.push_str(str::from_utf8(&self.bytes[start..self.pos]).unwrap());
+
+            match self.peek() {
+                Some(b'"') => {
+                    self.pos += 1;
+                    return Ok(s);
+                }
+                Some(b'\\') => {
+                    self.pos += 1;
+                    s.push(self.escape()?);
+                }
+                // Control characters must be escaped
+                _ => return Err(self.error()),
+            }
+        }
+    }
+
+    fn escape(&mut self) -> Result<char, JsonError> {
+        let escaped = self.peek().ok_or_else(|| self.error())?;
+        self.pos += 1;
+        Ok(match escaped {
+            b'"' => '"',
+            b'\\' => '\\',
+            b'/' => '/',
+            b'b' => '\u{8}',
+            b'f' => '\u{c}',
+            b'n' => '\n',
+            b'r' => '\r',
+            b't' => '\t',
+            b'u' => return self.unicode_escape(),
+            _ => return Err(self.error()),
+        })
+    }
+
+    /// A `\uXXXX` escape, or a pair of them for a character outside the
+    /// Basic Multilingual Plane
+    fn unicode_escape(&mut self) -> Result<char, JsonError> {
+        let high = self.hex4()?;
+        if !(0xD800..0xDC00).contains(&high) {
+            return char::from_u32(high).ok_or_else(|| self.error());
+        }
+        if !self.bytes[self.pos..].starts_with(b"\\u") {
+            return Err(self.error());
+        }
+        self.pos += 2;
+        let low = self.hex4()?;
+        if !(0xDC00..0xE000).contains(&low) {
+            return Err(self.error());
+        }
+        char::from_u32(0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00))
+            .ok_or_else(|| self.error())
+    }
+
+    fn hex4(&mut self) -> Result<u32, JsonError> {
+        let digits = self
+            .bytes
+            .get(self.pos..self.pos + 4)
+            .and_then(|digits| str::from_utf8(digits).ok())
+            .filter(|digits| digits.bytes().all(|b| b.is_ascii_hexdigit()))
+            .ok_or_else(|| self.error())?;
+        let value = u32::from_str_radix(digits, 16).map_err(|_| self.error())?;
+        self.pos += 4;
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{response::Response, status::StatusCode};
+
+    #[derive(Debug, PartialEq)]
+    struct User {
+        name: String,
+        age: u32,
+        admin: bool,
+        tags: Vec<String>,
+        email: Option<String>,
+    }
+
+    impl ToJson for User {
+        fn to_json(&self) -> Json {
+            Json::Object(vec![
+                ("name".to_string(), self.name.to_json()),
+                ("age".to_string(), self.age.to_json()),
+                ("admin".to_string(), self.admin.to_json()),
+                ("tags".to_string(), self.tags.to_json()),
+                ("email".to_string(), self.email.to_json()),
+            ])
+        }
+    }
+
+    impl FromJson for User {
+        fn from_json(json: &Json) -> Result<User, JsonError> {
+            Ok(User {
+                name: json.field("name")?,
+                age: json.field("age")?,
+                admin: json.field("admin")?,
+                tags: json.field("tags")?,
+                email: json.field("email")?,
+            })
+        }
+    }
+
+    fn user() -> User {
+        User {
+            name: "Ada \"the first\"".to_string(),
+            age: 36,
+            admin: true,
+            tags: vec!["math".to_string(), "engines".to_string()],
+            email: None,
+        }
+    }
+
+    #[test]
+    fn a_struct_serializes_as_a_json_response() {
+        let response = Response::json(&user());
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(
+            response.headers.get("Content-Type"),
+            Some("application/json; charset=utf-8")
+        );
+        assert_eq!(
+            String::from_utf8(response.body).unwrap(),
+            r#"{"name":"Ada \"the first\"","age":36,"admin":true,"tags":["math","engines"],"email":null}"#
+        );
+    }
+
+    #[test]
+    fn a_body_parses_back_into_the_struct() {
+        let body = user().to_json().to_string();
+        assert_eq!(from_body::<User>(body.as_bytes()), Ok(user()));
+
+        let body = br#" {"name": "Bob", "age": 7, "admin": false, "tags": [],
+                         "email": "bob@example.com"} "#;
+        let bob: User = from_body(body).unwrap();
+        assert_eq!(bob.email.as_deref(), Some("bob@example.com"));
+        assert_eq!(bob.age, 7);
+    }
+
+    #[test]
+    fn the_wrong_shape_names_the_field() {
+        let error = from_body::<User>(br#"{"name": "Bob", "age": "seven"}"#).unwrap_err();
+        assert!(matches!(&error, JsonError::Type(_)), "{}", error);
+        assert!(error.to_string().contains("\"age\""), "{}", error);
+    }
+
+    #[test]
+    fn invalid_json_is_a_syntax_error() {
+        for text in ["", "{", "[1,]", "{\"a\" 1}", "nul", "1 2", "\"\\x\""] {
+            assert!(
+                matches!(parse(text), Err(JsonError::Syntax(_))),
+                "{:?}",
+                text
+            );
+        }
+        assert_eq!(from_body::<Json>(b"\"\xff\""), Err(JsonError::Syntax(1)));
+    }
+
+    #[test]
+    fn strings_are_escaped_and_unescaped() {
+        let text = "tab\there\nquote\" slash\\ bell\u{7} é";
+        let json = Json::String(text.to_string()).to_string();
+        assert_eq!(json, "\"tab\\there\\nquote\\\" slash\\\\ bell\\u0007 é\"");
+        assert_eq!(parse(&json), Ok(Json::String(text.to_string())));
+        assert_eq!(
+            parse(r#""\u00e9\ud83d\ude00""#),
+            Ok(Json::String("é😀".to_string()))
+        );
+    }
+
+    #[test]
+    fn nesting_is_limited() {
+        let deep = "[".repeat(MAX_DEPTH + 1) + &"]".repeat(MAX_DEPTH + 1);
+        assert!(parse(&deep).is_err());
+        let deepest = "[".repeat(MAX_DEPTH) + &"]".repeat(MAX_DEPTH);
+        assert!(parse(&deepest).is_ok());
+    }
+}
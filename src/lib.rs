@@ -0,0 +1,35 @@
+pub mod access_log;
+pub mod args;
+pub mod auth;
+pub mod compression;
+pub mod config;
+pub mod connection;
+pub mod cookie;
+pub mod cors;
+pub mod date;
+pub mod error;
+pub mod file_cache;
+pub mod headers;
+pub mod json;
+pub mod log;
+pub mod metrics;
+pub mod middleware;
+pub mod multipart;
+pub mod pages;
+pub mod pool;
+pub mod range;
+pub mod rate_limit;
+pub mod request;
+pub mod response;
+pub mod router;
+pub mod server;
+pub mod shutdown;
+pub mod socket;
+pub mod static_files;
+pub mod status;
+#[cfg(test)]
+mod testing;
+pub mod tls;
+pub mod toml;
+pub mod url;
+pub mod vhost;
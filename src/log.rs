@@ -0,0 +1,175 @@
+use std::{
+    env, fmt,
+    io::{self, Write},
+    str::FromStr,
+    sync::atomic::{AtomicU8, Ordering},
+    time::SystemTime,
+};
+
+use crate::date::format_rfc3339;
+
+const LEVEL_ENV_VAR: &str = "RUST_WS_LOG";
+
+/// The least severe level logged, as a `Level` discriminant
+static MIN_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+/// How severe a log message is, from most to least
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    /// Noisy detail for debugging, such as whole requests
+    Trace,
+}
+
+impl Level {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+
+    fn from_u8(level: u8) -> Level {
+        match level {
+            0 => Level::Error,
+            1 => Level::Warn,
+            2 => Level::Info,
+            3 => Level::Debug,
+            _ => Level::Trace,
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    /// Parse a level name, ignoring case
+    fn from_str(level: &str) -> Result<Level, String> {
+        match level.to_ascii_lowercase().as_str() {
+            "error" => Ok(Level::Error),
+            "warn" | "warning" => Ok(Level::Warn),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            "trace" => Ok(Level::Trace),
+            _ => Err(format!("unknown log level {:?}", level)),
+        }
+    }
+}
+
+/// Only log messages at `level` or more severe
+pub fn set_min_level(level: Level) {
+    MIN_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn min_level() -> Level {
+    Level::from_u8(MIN_LEVEL.load(Ordering::Relaxed))
+}
+
+/// Set the minimum level from `RUST_WS_LOG`, keeping the current one if it
+/// is unset or not a level name
+pub fn init_from_env() {
+    if let Ok(level) = env::var(LEVEL_ENV_VAR) {
+        match level.parse() {
+            Ok(level) => set_min_level(level),
+            Err(e) => log(
+                Level::Warn,
+                format_args!("Ignoring {}: {}, using {}", LEVEL_ENV_VAR, e, min_level()),
+            ),
+        }
+    }
+}
+
+/// Whether messages at `level` are currently logged
+pub fn enabled(level: Level) -> bool {
+    level <= min_level()
+}
+
+/// Write `message` to stdout with a timestamp, if `level` is enabled
+pub fn log(level: Level, message: impl fmt::Display) {
+    // Holding the lock keeps lines from different threads whole
+    let _ = log_to(&mut io::stdout().lock(), level, &message, SystemTime::now());
+}
+
+/// [`log`] to `out`, as of `time`
+fn log_to(
+    out: &mut impl Write,
+    level: Level,
+    message: &dyn fmt::Display,
+    time: SystemTime,
+) -> io::Result<()> {
+    if !enabled(level) {
+        return Ok(());
+    }
+    writeln!(out, "{}", format_line(level, message, time))
+}
+
+/// Format a single log line, without its trailing newline
+pub fn format_line(level: Level, message: &dyn fmt::Display, time: SystemTime) -> String {
+    format!("{} {:<5} {}", format_rfc3339(time), level, message)
+}
+
+/// Log a `format!` style message at a level, e.g.
+/// `log!(Level::Debug, "joining worker {}", id)`
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($arg:tt)+) => {
+        $crate::log::log($level, format_args!($($arg)+))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::*;
+
+    #[test]
+    fn messages_below_the_minimum_level_are_suppressed() {
+        let time = UNIX_EPOCH + Duration::from_secs(971_186_136);
+        let previous = min_level();
+        set_min_level(Level::Warn);
+
+        let mut out = Vec::new();
+        for level in [
+            Level::Trace,
+            Level::Debug,
+            Level::Info,
+            Level::Warn,
+            Level::Error,
+        ] {
+            log_to(
+                &mut out,
+                level,
+                &format_args!("at {}", level.as_str()),
+                time,
+            )
+            .unwrap();
+        }
+        set_min_level(previous);
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "2000-10-10T13:55:36Z WARN  at WARN\n2000-10-10T13:55:36Z ERROR at ERROR\n"
+        );
+    }
+
+    #[test]
+    fn levels_parse_ignoring_case() {
+        assert_eq!("DEBUG".parse(), Ok(Level::Debug));
+        assert_eq!("warning".parse(), Ok(Level::Warn));
+        assert!("loud".parse::<Level>().is_err());
+        assert!(Level::Error < Level::Trace);
+    }
+}
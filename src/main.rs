@@ -1,169 +1,155 @@
 use std::{
-    collections::VecDeque,
-    fs,
-    io::{Read, Write},
-    mem,
-    net::{TcpListener, TcpStream},
-    rc::Rc,
-    sync::{mpsc, Arc, Mutex},
-    thread::{self, JoinHandle},
+    convert::TryFrom,
+    env,
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use webserver::{
+    args::{parse_args, Command, USAGE},
+    config::ServerConfig,
+    log,
+    log::Level,
+    server::run_server,
+    shutdown::ShutdownSignal,
 };
 
 extern crate signal_hook;
 
 fn main() {
-    let listener = TcpListener::bind("127.0.0.1:7878").unwrap();
-    let pool = ThreadPool::new(5);
-
-    reg_for_sigs(Box::new(|| {
-        pool.shutdown();
-    }));
-
-    for stream in listener.incoming() {
-        let stream = stream.unwrap();
-
-        pool.submit(|| {
-            handle_connection(stream);
-        });
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut config = load_config(&args);
+    if let Some(level) = config.log_level {
+        log::set_min_level(level);
     }
-}
-
-pub fn reg_for_sigs(func: Box<dyn Fn() -> () + Send + Sync>) {
-    unsafe { signal_hook::register(signal_hook::SIGINT, || on_sigint(func)) }
-        .and_then(|_| {
-            println!("Registered for SIGINT");
-            Ok(())
-        })
-        .or_else(|e| {
-            println!("Failed to register for SIGINT {:?}", e);
-            Err(e)
-        })
-        .ok();
-}
-
-fn on_sigint(func: Box<dyn Fn() -> ()>) {
-    println!("SIGINT caught - exiting");
-    func();
-    std::process::exit(128 + signal_hook::SIGINT);
-}
-
-fn handle_connection(mut stream: TcpStream) {
-    let get_req = b"GET / HTTP/1.1\r\n";
-    let mut buffer = [0; 1024];
-    let _ = stream.read(&mut buffer).unwrap();
-
-    let (status_line, contents_file) = if buffer.starts_with(get_req) {
-        ("HTTP/1.1 200 OK", "hello.html")
-    } else {
-        ("HTTP/1.1 404 NOT FOUND", "404.html")
-    };
-
-    let contents = fs::read_to_string(contents_file).unwrap();
-
-    let response = format!(
-        "{}\r\nContent-Length: {}\r\n\r\n{}",
-        status_line,
-        contents.len(),
-        contents
-    );
-
-    let _ = stream.write(response.as_bytes()).unwrap();
-    stream.flush().unwrap();
-
-    // println!(
-    //     "Request: {} {}",
-    //     String::from_utf8_lossy(&buffer[..]),
-    //     response.as_bytes().len()
-    // );
-}
-
-type Job = Box<dyn FnOnce() + Send + 'static>;
-
-enum Message {
-    NewJob(Job),
-    Terminate,
-}
-
-struct ThreadPool {
-    workers: Vec<Worker>,
-    sender: mpsc::Sender<Message>,
-}
-
-impl ThreadPool {
-    /// Create a new ThreadPool
-    ///
-    /// nthreads is the number of threads in the pool
-    /// # Panics
-    ///
-    pub fn new(nthreads: u16) -> ThreadPool {
-        assert!(nthreads > 0);
-
-        let (sender, receiver) = mpsc::channel();
-        let receiver = Arc::new(Mutex::new(receiver));
-        let mut workers = Vec::with_capacity(nthreads.into());
-        for id in 0..nthreads {
-            workers.push(Worker::new(id, Arc::clone(&receiver)));
+    // RUST_WS_LOG overrides the config file, as the other variables do
+    log::init_from_env();
+    match parse_args(&args, &mut config) {
+        Ok(Command::Serve) => {}
+        Ok(Command::Help) => {
+            print!("{}", USAGE);
+            return;
         }
-
-        ThreadPool {
-            workers,
-            sender: sender,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            std::process::exit(2);
         }
     }
 
-    pub fn submit<F>(&self, request: F)
-    where
-        F: FnOnce() + Send + 'static,
-    {
-        self.sender.send(Message::NewJob(Box::new(request)));
-    }
-
-    pub fn shutdown(&self) {
-        println!("sending terminate messgae to all workers");
-        for _ in 0..self.workers.len() {
-            self.sender.send(Message::Terminate).unwrap();
+    let server = match run_server(config) {
+        Ok(server) => server,
+        Err(e) => {
+            log!(Level::Error, "Unable to start server: {}", e);
+            std::process::exit(1);
         }
-
-        println!("joining all workers on shutdown");
-        self.workers.iter_mut().for_each(|worker| {
-            if let Some(handle) = worker.joinHandle.take() {
-                handle.join().unwrap();
-            }
-        });
+    };
+    let caught = reg_for_sigs();
+    watch_for_signals(Arc::clone(&caught), server.shutdown_signal());
+    // Returns once a signal or a handler asks for a stop, or every
+    // listener fails
+    server.wait();
+    if let Some((signal, _)) = caught_signal(&caught) {
+        std::process::exit(exit_code(signal));
     }
 }
 
-impl Drop for ThreadPool {
-    fn drop(&mut self) {
-        self.shutdown();
+/// Signals that shut the server down. SIGTERM is what container
+/// orchestrators send.
+const SHUTDOWN_SIGNALS: [(i32, &str); 2] = [
+    (signal_hook::SIGINT, "SIGINT"),
+    (signal_hook::SIGTERM, "SIGTERM"),
+];
+
+/// How often the signal watcher checks whether a signal has been caught
+const SIGNAL_POLL: Duration = Duration::from_millis(50);
+
+/// Register for the shutdown signals. Little is safe to do inside a signal
+/// handler, so the handlers only store the number of the signal caught in
+/// the returned flag, which holds 0 until one arrives.
+fn reg_for_sigs() -> Arc<AtomicUsize> {
+    let caught = Arc::new(AtomicUsize::new(0));
+
+    for &(signal, name) in SHUTDOWN_SIGNALS.iter() {
+        let value = usize::try_from(signal).unwrap_or_default();
+        signal_hook::flag::register_usize(signal, Arc::clone(&caught), value)
+            .map(|_| {
+                log!(Level::Debug, "Registered for {}", name);
+            })
+            .map_err(|e| {
+                log!(Level::Error, "Failed to register for {} {:?}", name, e);
+            })
+            .ok();
     }
+    caught
 }
 
-struct Worker {
-    id: u16,
-    joinHandle: Option<JoinHandle<()>>,
+/// The shutdown signal stored in `caught` by a handler, if any
+fn caught_signal(caught: &AtomicUsize) -> Option<(i32, &'static str)> {
+    let caught = caught.load(Ordering::SeqCst);
+    SHUTDOWN_SIGNALS
+        .iter()
+        .copied()
+        .find(|&(signal, _)| usize::try_from(signal) == Ok(caught))
 }
 
-impl Worker {
-    fn new(id: u16, receiver: Arc<Mutex<mpsc::Receiver<Message>>>) -> Worker {
-        let joinHandle = thread::spawn(move || loop {
-            let message = receiver.lock().unwrap().recv().unwrap();
+/// Watch `caught` from a thread of its own, passing a signal on to
+/// `shutdown`, which the accept loops poll
+fn watch_for_signals(caught: Arc<AtomicUsize>, shutdown: Arc<ShutdownSignal>) {
+    thread::spawn(move || {
+        while !shutdown.is_triggered() {
+            if let Some((_, name)) = caught_signal(&caught) {
+                log!(Level::Info, "{} caught", name);
+                shutdown.trigger();
+                return;
+            }
+            thread::sleep(SIGNAL_POLL);
+        }
+    });
+}
 
-            match message {
-                Message::NewJob(job) => {
-                    println!("executing job on worker {}", id);
-                    job();
-                }
+/// The conventional exit status for a process ended by `signal`
+fn exit_code(signal: i32) -> i32 {
+    128 + signal
+}
 
-                Message::Terminate => {
-                    println!("terminating worker {}", id);
-                    break;
-                }
-            }
-        });
+/// The config file named by `--config`, if there is one, overridden by the
+/// environment
+fn load_config(args: &[String]) -> ServerConfig {
+    let path = match args.iter().position(|arg| arg == "--config") {
+        // A missing path is reported along with any other bad arguments
+        Some(i) => match args.get(i + 1) {
+            Some(path) => path,
+            None => return ServerConfig::from_env(),
+        },
+        None => return ServerConfig::from_env(),
+    };
 
-        let joinHandle = Some(joinHandle);
+    let mut config = ServerConfig::from_file(Path::new(path)).unwrap_or_else(|e| {
+        log!(Level::Error, "Unable to load {}: {}", path, e);
+        std::process::exit(1);
+    });
+    config.apply_env();
+    config
+}
 
-        Worker { id, joinHandle }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn both_shutdown_signals_are_caught() {
+        let caught = reg_for_sigs();
+        for &(signal, name) in SHUTDOWN_SIGNALS.iter() {
+            caught.store(0, Ordering::SeqCst);
+            assert_eq!(unsafe { libc::raise(signal) }, 0);
+            assert_eq!(caught_signal(&caught), Some((signal, name)));
+        }
+        assert_eq!(exit_code(signal_hook::SIGINT), 130);
+        assert_eq!(exit_code(signal_hook::SIGTERM), 143);
     }
 }
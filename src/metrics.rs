@@ -0,0 +1,162 @@
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::{pool::PoolStats, status::StatusCode};
+
+/// Server-wide counters, rendered for Prometheus by [`Metrics::render`]
+#[derive(Debug, Default)]
+pub struct Metrics {
+    requests: AtomicU64,
+    /// Responses by status class, indexed by the first digit of the code
+    responses: [AtomicU64; 6],
+    active_connections: AtomicU64,
+}
+
+/// Keeps a connection counted as active until it is dropped
+pub struct ActiveConnection<'a> {
+    metrics: &'a Metrics,
+}
+
+impl Drop for ActiveConnection<'_> {
+    fn drop(&mut self) {
+        self.metrics
+            .active_connections
+            .fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics::default()
+    }
+
+    /// Count a connection as active for as long as the returned guard lives
+    pub fn track_connection(&self) -> ActiveConnection<'_> {
+        self.active_connections.fetch_add(1, Ordering::Relaxed);
+        ActiveConnection { metrics: self }
+    }
+
+    /// Count a request and the status it was answered with
+    pub fn record_response(&self, status: StatusCode) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        let class = usize::from(status.as_u16() / 100).min(self.responses.len() - 1);
+        self.responses[class].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn requests(&self) -> u64 {
+        self.requests.load(Ordering::Relaxed)
+    }
+
+    /// The metrics, plus those of the worker pool, in the Prometheus text
+    /// exposition format
+    pub fn render(&self, pool: &PoolStats) -> String {
+        let mut out = String::new();
+        metric(
+            &mut out,
+            "webserver_requests_total",
+            "counter",
+            "Requests answered, including ones that could not be parsed.",
+        );
+        let _ = writeln!(out, "webserver_requests_total {}", self.requests());
+
+        metric(
+            &mut out,
+            "webserver_responses_total",
+            "counter",
+            "Responses sent, by status class.",
+        );
+        for (class, count) in self.responses.iter().enumerate().skip(1) {
+            let _ = writeln!(
+                out,
+                "webserver_responses_total{{class=\"{}xx\"}} {}",
+                class,
+                count.load(Ordering::Relaxed)
+            );
+        }
+
+        metric(
+            &mut out,
+            "webserver_active_connections",
+            "gauge",
+            "Connections currently being served.",
+        );
+        let _ = writeln!(
+            out,
+            "webserver_active_connections {}",
+            self.active_connections.load(Ordering::Relaxed)
+        );
+
+        metric(
+            &mut out,
+            "webserver_pool_workers",
+            "gauge",
+            "Threads in the worker pool.",
+        );
+        let _ = writeln!(out, "webserver_pool_workers {}", pool.workers);
+        metric(
+            &mut out,
+            "webserver_pool_active_jobs",
+            "gauge",
+            "Jobs being run by a worker.",
+        );
+        let _ = writeln!(out, "webserver_pool_active_jobs {}", pool.active);
+        metric(
+            &mut out,
+            "webserver_pool_queue_depth",
+            "gauge",
+            "Jobs waiting for a free worker.",
+        );
+        let _ = writeln!(out, "webserver_pool_queue_depth {}", pool.queued);
+
+        out
+    }
+}
+
+fn metric(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POOL: PoolStats = PoolStats {
+        workers: 4,
+        active: 1,
+        completed: 10,
+        queued: 2,
+    };
+
+    #[test]
+    fn responses_are_counted_by_status_class() {
+        let metrics = Metrics::new();
+        metrics.record_response(StatusCode::OK);
+        metrics.record_response(StatusCode::NOT_FOUND);
+        metrics.record_response(StatusCode::NOT_FOUND);
+        assert_eq!(metrics.requests(), 3);
+
+        let out = metrics.render(&POOL);
+        assert!(out.contains("\nwebserver_requests_total 3\n"), "{}", out);
+        assert!(out.contains("\nwebserver_responses_total{class=\"2xx\"} 1\n"));
+        assert!(out.contains("\nwebserver_responses_total{class=\"4xx\"} 2\n"));
+        assert!(out.contains("\nwebserver_responses_total{class=\"5xx\"} 0\n"));
+        assert!(out.contains("\nwebserver_pool_workers 4\n"));
+        assert!(out.contains("\nwebserver_pool_queue_depth 2\n"));
+    }
+
+    #[test]
+    fn a_connection_is_active_until_its_guard_drops() {
+        let metrics = Metrics::new();
+        let connection = metrics.track_connection();
+        assert!(metrics
+            .render(&POOL)
+            .contains("\nwebserver_active_connections 1\n"));
+        drop(connection);
+        assert!(metrics
+            .render(&POOL)
+            .contains("\nwebserver_active_connections 0\n"));
+    }
+}
@@ -0,0 +1,114 @@
+use crate::{request::Request, response::Response, router::Router};
+
+/// Behaviour wrapped around every request, such as auth or compression.
+///
+/// A middleware gets the request before the router does and decides what
+/// to do with it: answer it itself, or pass it on with `next` and return
+/// what comes back, changed as it likes.
+pub trait Middleware: Send + Sync {
+    fn handle(&self, req: Request, next: &dyn Fn(Request) -> Response) -> Response;
+}
+
+/// A router with middleware wrapped around it
+pub struct MiddlewareStack {
+    router: Router,
+    /// Outermost first
+    layers: Vec<Box<dyn Middleware>>,
+}
+
+impl MiddlewareStack {
+    pub fn new(router: Router) -> MiddlewareStack {
+        MiddlewareStack {
+            router,
+            layers: Vec::new(),
+        }
+    }
+
+    /// Add `middleware` inside those already added, so it sees requests
+    /// after them and responses before them
+    pub fn push(&mut self, middleware: impl Middleware + 'static) {
+        self.layers.push(Box::new(middleware));
+    }
+
+    /// Pass `request` through each middleware in turn to the router
+    pub fn dispatch(&self, request: Request) -> Response {
+        self.run(&self.layers, request)
+    }
+
+    fn run(&self, layers: &[Box<dyn Middleware>], request: Request) -> Response {
+        match layers.split_first() {
+            Some((layer, inner)) => layer.handle(request, &|req| self.run(inner, req)),
+            None => self.router.dispatch(&request),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        request::{parse_request_head, Method},
+        status::StatusCode,
+    };
+
+    /// Adds its name to the `X-Trace` request and response headers
+    struct Trace(&'static str);
+
+    impl Middleware for Trace {
+        fn handle(&self, mut req: Request, next: &dyn Fn(Request) -> Response) -> Response {
+            req.headers.insert("X-Trace", self.0);
+            let mut response = next(req);
+            response.headers.insert("X-Trace", self.0);
+            response
+        }
+    }
+
+    /// Refuses everything itself
+    struct Deny;
+
+    impl Middleware for Deny {
+        fn handle(&self, _req: Request, _next: &dyn Fn(Request) -> Response) -> Response {
+            Response::new(StatusCode::FORBIDDEN)
+        }
+    }
+
+    fn stack() -> MiddlewareStack {
+        let mut router = Router::new();
+        // Echo the trace the request arrived with
+        router.add_route(
+            Method::Get,
+            "/",
+            Box::new(|req| {
+                Response::new(StatusCode::OK).body(req.headers.get_all("X-Trace").join(","))
+            }),
+        );
+        MiddlewareStack::new(router)
+    }
+
+    fn request() -> Request {
+        parse_request_head(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n", 100).unwrap()
+    }
+
+    #[test]
+    fn middleware_runs_in_the_order_it_was_pushed() {
+        let mut stack = stack();
+        stack.push(Trace("outer"));
+        stack.push(Trace("inner"));
+        let response = stack.dispatch(request());
+
+        assert_eq!(response.body, b"outer,inner");
+        assert_eq!(response.headers.get_all("X-Trace"), ["inner", "outer"]);
+    }
+
+    #[test]
+    fn middleware_can_answer_without_the_router() {
+        let mut stack = stack();
+        stack.push(Trace("outer"));
+        stack.push(Deny);
+        stack.push(Trace("never"));
+        let response = stack.dispatch(request());
+
+        assert_eq!(response.status, StatusCode::FORBIDDEN);
+        assert_eq!(response.headers.get_all("X-Trace"), ["outer"]);
+    }
+}
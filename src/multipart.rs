@@ -0,0 +1,217 @@
+use crate::{
+    headers::{find_header_end, parse_headers, Headers},
+    request::ParseError,
+};
+
+/// Most headers accepted on one part of a multipart body
+const MAX_PART_HEADERS: usize = 16;
+
+/// One part of a `multipart/form-data` body, such as a form field or an
+/// uploaded file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Part {
+    pub headers: Headers,
+    /// The form field the part is for, from `Content-Disposition`
+    pub name: Option<String>,
+    /// The uploaded file's name, from `Content-Disposition`. Only set for
+    /// file fields.
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+    pub content: Vec<u8>,
+}
+
+/// The boundary declared by a `multipart/*` `Content-Type` header, if it
+/// has one
+pub fn boundary(content_type: &str) -> Option<String> {
+    let (media_type, params) = content_type.split_once(';').unwrap_or((content_type, ""));
+    if !media_type
+        .trim()
+        .to_ascii_lowercase()
+        .starts_with("multipart/")
+    {
+        return None;
+    }
+    header_param(params, "boundary").filter(|boundary| !boundary.is_empty())
+}
+
+/// Split a `multipart/form-data` body into its parts. Anything before the
+/// first boundary or after the closing `--boundary--` is ignored, as RFC
+/// 2046 asks.
+pub fn parse_multipart(body: &[u8], boundary: &str) -> Result<Vec<Part>, ParseError> {
+    let delimiter = format!("--{}", boundary);
+    let delimiter = delimiter.as_bytes();
+    // Every delimiter after the first ends the line of the part before it
+    let next_delimiter = [b"\r\n", delimiter].concat();
+
+    let mut pos = if body.starts_with(delimiter) {
+        delimiter.len()
+    } else {
+        find(body, &next_delimiter).ok_or(ParseError::MissingBoundary)? + next_delimiter.len()
+    };
+
+    let mut parts = Vec::new();
+    loop {
+        let rest = &body[pos..];
+        if rest.starts_with(b"--") {
+            return Ok(parts);
+        }
+        // Whitespace may pad the rest of a delimiter line
+        let line_end = find(rest, b"\r\n").ok_or(ParseError::IncompleteMultipart)?;
+        if !rest[..line_end].iter().all(|b| *b == b' ' || *b == b'\t') {
+            return Err(ParseError::MissingBoundary);
+        }
+        let start = pos + line_end + 2;
+
+        let len = find(&body[start..], &next_delimiter).ok_or(ParseError::IncompleteMultipart)?;
+        parts.push(parse_part(&body[start..start + len])?);
+        pos = start + len + next_delimiter.len();
+    }
+}
+
+fn parse_part(part: &[u8]) -> Result<Part, ParseError> {
+    let head_len = find_header_end(part).ok_or(ParseError::IncompleteHeaders)?;
+    let headers = parse_headers(part, MAX_PART_HEADERS)?;
+
+    let disposition = headers.get("Content-Disposition").unwrap_or("");
+    let params = disposition.split_once(';').map_or("", |(_, params)| params);
+    Ok(Part {
+        name: header_param(params, "name"),
+        filename: header_param(params, "filename"),
+        content_type: headers.get("Content-Type").map(String::from),
+        content: part[head_len..].to_vec(),
+        headers,
+    })
+}
+
+/// The value of the parameter `name` in a `; key=value` list, unquoting it
+/// if it is a quoted string
+fn header_param(params: &str, name: &str) -> Option<String> {
+    let mut rest = params;
+    while let Some((key, after)) = rest.split_once('=') {
+        let key = key.trim_start_matches(|c: char| c == ';' || c.is_whitespace());
+        let after = after.trim_start();
+
+        let (value, remainder) = match after.strip_prefix('"') {
+            Some(quoted) => unquote(quoted),
+            None => {
+                let end = after.find(';').unwrap_or(after.len());
+                (after[..end].trim_end().to_string(), &after[end..])
+            }
+        };
+        if key.trim().eq_ignore_ascii_case(name) {
+            return Some(value);
+        }
+        rest = remainder;
+    }
+    None
+}
+
+/// Read a quoted string whose opening quote has been stripped, returning it
+/// unescaped along with what follows the closing quote
+fn unquote(quoted: &str) -> (String, &str) {
+    let mut value = String::new();
+    let mut chars = quoted.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return (value, &quoted[i + 1..]),
+            '\\' => value.extend(chars.next().map(|(_, escaped)| escaped)),
+            c => value.push(c),
+        }
+    }
+    (value, "")
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOUNDARY: &str = "----form42";
+
+    fn body(parts: &[&str], closed: bool) -> Vec<u8> {
+        let mut body = String::new();
+        for part in parts {
+            body.push_str(&format!("--{}\r\n{}\r\n", BOUNDARY, part));
+        }
+        if closed {
+            body.push_str(&format!("--{}--\r\n", BOUNDARY));
+        }
+        body.into_bytes()
+    }
+
+    #[test]
+    fn the_boundary_comes_from_the_content_type() {
+        assert_eq!(
+            boundary("multipart/form-data; boundary=----form42").as_deref(),
+            Some(BOUNDARY)
+        );
+        assert_eq!(
+            boundary("Multipart/Form-Data; charset=utf-8; boundary=\"a b\"").as_deref(),
+            Some("a b")
+        );
+        assert_eq!(boundary("text/plain; boundary=x"), None);
+        assert_eq!(boundary("multipart/form-data"), None);
+    }
+
+    #[test]
+    fn parses_a_single_text_field() {
+        let body = body(
+            &["Content-Disposition: form-data; name=\"title\"\r\n\r\nHello, world"],
+            true,
+        );
+        let parts = parse_multipart(&body, BOUNDARY).unwrap();
+        assert_eq!(parts.len(), 1);
+        assert_eq!(parts[0].name.as_deref(), Some("title"));
+        assert_eq!(parts[0].filename, None);
+        assert_eq!(parts[0].content_type, None);
+        assert_eq!(parts[0].content, b"Hello, world");
+    }
+
+    #[test]
+    fn parses_a_file_field_alongside_a_text_field() {
+        let body = body(
+            &[
+                "Content-Disposition: form-data; name=\"note\"\r\n\r\nsee attached",
+                "Content-Disposition: form-data; name=\"upload\"; filename=\"a \\\"b\\\".txt\"\r\n\
+                 Content-Type: text/plain\r\n\r\nline one\r\nline two\r\n",
+            ],
+            true,
+        );
+        let parts = parse_multipart(&body, BOUNDARY).unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].content, b"see attached");
+
+        let file = &parts[1];
+        assert_eq!(file.name.as_deref(), Some("upload"));
+        assert_eq!(file.filename.as_deref(), Some("a \"b\".txt"));
+        assert_eq!(file.content_type.as_deref(), Some("text/plain"));
+        // Line breaks in the content are kept, up to the one before the
+        // delimiter
+        assert_eq!(file.content, b"line one\r\nline two\r\n");
+    }
+
+    #[test]
+    fn a_missing_final_boundary_is_an_error() {
+        let body = body(
+            &["Content-Disposition: form-data; name=\"title\"\r\n\r\nHello"],
+            false,
+        );
+        assert_eq!(
+            parse_multipart(&body, BOUNDARY),
+            Err(ParseError::IncompleteMultipart)
+        );
+    }
+
+    #[test]
+    fn a_body_without_the_boundary_is_an_error() {
+        assert_eq!(
+            parse_multipart(b"just some text", BOUNDARY),
+            Err(ParseError::MissingBoundary)
+        );
+    }
+}
@@ -0,0 +1,98 @@
+use std::{fs, io, path::Path};
+
+use crate::{
+    error::ServerError, log, log::Level, response::Response, static_files::content_type_for,
+    status::StatusCode,
+};
+
+/// Log `e` and answer with the 500 page from `pages`
+pub fn internal_error(pages: &Path, e: ServerError) -> Response {
+    log!(Level::Error, "Error serving request: {}", e);
+    error_response(pages, StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// The 404 page from `pages`
+pub fn not_found(pages: &Path) -> Response {
+    error_response(pages, StatusCode::NOT_FOUND)
+}
+
+/// The page for an error status, read from e.g. `404.html` in the `pages`
+/// directory, or a built-in page if that can't be read
+pub fn error_response(pages: &Path, status: StatusCode) -> Response {
+    let file = pages.join(format!("{}.html", status.as_u16()));
+    file_response(status, &file).unwrap_or_else(|_| {
+        Response::new(status)
+            .header("Content-Type", "text/html; charset=utf-8")
+            .body(default_error_body(status))
+    })
+}
+
+/// A minimal HTML page naming `status`
+pub fn default_error_body(status: StatusCode) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n  <head>\n    <meta charset=\"utf-8\">\n    \
+         <title>{status}</title>\n  </head>\n  <body>\n    <h1>{status}</h1>\n  </body>\n</html>\n",
+        status = status
+    )
+}
+
+/// A response with the contents of `file`, which is relative to the
+/// working directory unless absolute
+pub fn file_response(status: StatusCode, file: &Path) -> Result<Response, ServerError> {
+    // Read as bytes so a page that isn't UTF-8 is still sent as it is
+    let contents = fs::read(file).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => ServerError::NotFound(file.into()),
+        _ => e.into(),
+    })?;
+
+    Ok(Response::new(status)
+        .header("Content-Type", &content_type_for(&file.to_string_lossy()))
+        .body(contents))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn a_missing_page_file_gets_a_well_formed_built_in_page() {
+        let pages = TempDir::new();
+        let mut response = not_found(pages.path());
+        let body = default_error_body(StatusCode::NOT_FOUND).into_bytes();
+        assert_eq!(response.status, StatusCode::NOT_FOUND);
+        assert_eq!(
+            response.headers.get("Content-Type"),
+            Some("text/html; charset=utf-8")
+        );
+        assert_eq!(response.body, body);
+
+        let mut written = Vec::new();
+        response.write_to(&mut written).unwrap();
+        let length = format!("Content-Length: {}\r\n", body.len());
+        assert!(String::from_utf8_lossy(&written).contains(&length));
+        assert!(written.ends_with(&body));
+    }
+
+    #[test]
+    fn a_page_file_in_the_pages_directory_is_served() {
+        let pages = TempDir::new();
+        pages.file("403.html", b"<p>No entry</p>");
+        let response = error_response(pages.path(), StatusCode::FORBIDDEN);
+        assert_eq!(response.status, StatusCode::FORBIDDEN);
+        assert_eq!(response.body, b"<p>No entry</p>");
+        assert_eq!(
+            error_response(pages.path(), StatusCode::NOT_FOUND).body,
+            default_error_body(StatusCode::NOT_FOUND).into_bytes()
+        );
+    }
+
+    #[test]
+    fn the_built_in_page_names_the_status() {
+        let body = default_error_body(StatusCode::NOT_FOUND);
+        assert!(body.starts_with("<!DOCTYPE html>\n"));
+        assert!(body.contains("<title>404 Not Found</title>"));
+        assert!(body.contains("<h1>404 Not Found</h1>"));
+        assert!(body.ends_with("</html>\n"));
+    }
+}
@@ -0,0 +1,1717 @@
+use std::{
+    any::Any,
+    cmp::Ordering as CmpOrdering,
+    collections::BinaryHeap,
+    convert::TryFrom,
+    error::Error,
+    fmt, io, iter,
+    panic::{self, AssertUnwindSafe},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicU16, AtomicU64, AtomicUsize, Ordering},
+        mpsc, Arc, Condvar, Mutex, PoisonError, RwLock,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crossbeam_deque::{Injector, Steal, Stealer};
+
+use crate::{log, log::Level};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+enum Message {
+    NewJob(Job),
+    Terminate,
+}
+
+/// How urgently a job should run. Jobs run in priority order, and in the
+/// order they were submitted within a priority.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Bulk work that can wait for everything else
+    Low,
+    /// What `submit` uses
+    Normal,
+    /// Jobs such as health checks that should jump ahead of queued work
+    High,
+}
+
+/// A job waiting in the priority queue
+struct Prioritized {
+    priority: Priority,
+    /// Submission order, so equal priorities come out first in, first out
+    seq: u64,
+    job: Job,
+}
+
+impl PartialEq for Prioritized {
+    fn eq(&self, other: &Prioritized) -> bool {
+        self.cmp(other) == CmpOrdering::Equal
+    }
+}
+
+impl Eq for Prioritized {}
+
+impl PartialOrd for Prioritized {
+    fn partial_cmp(&self, other: &Prioritized) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Prioritized {
+    /// Greatest first out of the heap: the highest priority, then the
+    /// earliest submitted
+    fn cmp(&self, other: &Prioritized) -> CmpOrdering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+/// Jobs submitted with a priority other than `Normal`
+#[derive(Default)]
+struct PriorityQueue {
+    heap: BinaryHeap<Prioritized>,
+    next_seq: u64,
+}
+
+/// Why a worker exited, as reported to the pool
+enum Exit {
+    /// It was sent `Message::Terminate`
+    Terminated(u16),
+    /// It was idle for longer than the idle timeout
+    Retired(u16),
+    /// It panicked outside of a job, and needs replacing
+    Died(u16),
+}
+
+/// Job bookkeeping and idle settings shared between the pool and its
+/// workers
+#[derive(Default)]
+struct Counters {
+    /// Jobs submitted that have not finished yet, queued or running
+    pending: AtomicUsize,
+    /// Jobs running right now
+    active: AtomicUsize,
+    /// Jobs that have run to completion, including ones that panicked
+    completed: AtomicUsize,
+    /// Worker threads that are still running
+    live: AtomicUsize,
+    /// Workers that died and have not been replaced yet
+    died: AtomicUsize,
+    /// How long a worker waits for a job before retiring, in milliseconds.
+    /// Zero means workers never retire.
+    idle_timeout_ms: AtomicU64,
+    /// Workers that stay however idle they are
+    min_workers: AtomicUsize,
+}
+
+impl Counters {
+    fn idle_timeout(&self) -> Option<Duration> {
+        match self.idle_timeout_ms.load(Ordering::SeqCst) {
+            0 => None,
+            ms => Some(Duration::from_millis(ms)),
+        }
+    }
+
+    /// Claim the right for an idle worker to exit, unless that would take
+    /// the pool below its minimum
+    fn try_retire(&self) -> bool {
+        let min = self.min_workers.load(Ordering::SeqCst);
+        self.live
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |live| {
+                if live > min {
+                    Some(live - 1)
+                } else {
+                    None
+                }
+            })
+            .is_ok()
+    }
+}
+
+/// The queues and bookkeeping shared between the pool and its workers.
+///
+/// Submitted jobs go on the global `injector`. Workers move them over to a
+/// queue of their own in batches, so they rarely contend with each other,
+/// and steal from each other's queues when both theirs and the global one
+/// are empty.
+///
+/// Jobs with a priority other than `Normal` skip all that and go on the
+/// `prioritized` heap instead: high priority ones are taken before any other
+/// queue is looked at, low priority ones only once the rest are empty.
+struct Shared {
+    injector: Injector<Message>,
+    prioritized: Mutex<PriorityQueue>,
+    /// Jobs on `prioritized`, so workers can skip its lock when it's empty
+    prioritized_len: AtomicUsize,
+    /// The other end of every running worker's queue, by worker id
+    stealers: RwLock<Vec<(u16, Stealer<Message>)>>,
+    /// Held while deciding to sleep on, or signalling, `work` and `room`
+    sleep: Mutex<()>,
+    /// Signalled when there is a message for a sleeping worker
+    work: Condvar,
+    /// Signalled when a job finishes, for a `submit` waiting on a full pool
+    room: Condvar,
+    counters: Counters,
+}
+
+impl Shared {
+    fn new() -> Shared {
+        Shared {
+            injector: Injector::new(),
+            prioritized: Mutex::new(PriorityQueue::default()),
+            prioritized_len: AtomicUsize::new(0),
+            stealers: RwLock::new(Vec::new()),
+            sleep: Mutex::new(()),
+            work: Condvar::new(),
+            room: Condvar::new(),
+            counters: Counters::default(),
+        }
+    }
+
+    /// Queue a message for the workers and wake one of them for it
+    fn push(&self, message: Message) {
+        self.injector.push(message);
+        let _sleep = self.sleep.lock().unwrap();
+        self.work.notify_one();
+    }
+
+    /// Queue a job on the priority heap and wake a worker for it
+    fn push_prioritized(&self, priority: Priority, job: Job) {
+        {
+            let mut queue = self.prioritized.lock().unwrap();
+            let seq = queue.next_seq;
+            queue.next_seq += 1;
+            queue.heap.push(Prioritized { priority, seq, job });
+            self.prioritized_len.fetch_add(1, Ordering::SeqCst);
+        }
+        let _sleep = self.sleep.lock().unwrap();
+        self.work.notify_one();
+    }
+
+    /// Take the most urgent job off the priority heap, as long as it is at
+    /// least `min` priority
+    fn pop_prioritized(&self, min: Priority) -> Option<Job> {
+        if self.prioritized_len.load(Ordering::SeqCst) == 0 {
+            return None;
+        }
+        let mut queue = self.prioritized.lock().unwrap();
+        if queue.heap.peek()?.priority < min {
+            return None;
+        }
+        let prioritized = queue.heap.pop()?;
+        self.prioritized_len.fetch_sub(1, Ordering::SeqCst);
+        Some(prioritized.job)
+    }
+
+    /// Whether any queue, global or a worker's, has messages waiting
+    fn has_messages(&self) -> bool {
+        self.prioritized_len.load(Ordering::SeqCst) > 0
+            || !self.injector.is_empty()
+            || self
+                .stealers
+                .read()
+                .unwrap()
+                .iter()
+                .any(|(_, stealer)| !stealer.is_empty())
+    }
+
+    /// Take every message off the global queue, the priority heap and,
+    /// through their stealers, the workers' queues
+    fn drain(&self) -> Vec<Message> {
+        let mut drained = Vec::new();
+        while let Some(job) = self.pop_prioritized(Priority::Low) {
+            drained.push(Message::NewJob(job));
+        }
+        let stealers = self.stealers.read().unwrap();
+        loop {
+            let steal = self.injector.steal().or_else(|| {
+                stealers
+                    .iter()
+                    .map(|(_, stealer)| stealer.steal())
+                    .collect()
+            });
+            match steal {
+                Steal::Success(message) => drained.push(message),
+                Steal::Retry => continue,
+                Steal::Empty => return drained,
+            }
+        }
+    }
+}
+
+/// A snapshot of what a pool is doing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolStats {
+    pub workers: usize,
+    /// Jobs being executed
+    pub active: usize,
+    pub completed: usize,
+    /// Jobs waiting for a worker. Approximate, as the counters it's derived
+    /// from are read separately.
+    pub queued: usize,
+}
+
+/// How often `shutdown_graceful` checks whether the queue has drained
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How long a worker without an idle timeout sleeps before looking for
+/// work again, in case it missed a wakeup or an idle timeout was set
+const IDLE_SETTINGS_POLL: Duration = Duration::from_secs(1);
+
+/// Jobs that may wait in the queue before `submit` starts turning them away
+pub const DEFAULT_QUEUE_BOUND: usize = 1024;
+
+/// What `submit` does with a job when the queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectionPolicy {
+    /// Wait for room in the queue
+    Block,
+    /// Fail with `SubmitError::Full`, dropping the job
+    Reject,
+    /// Run the job on the submitting thread before returning. This slows
+    /// the submitter down to the pool's pace, and is safe from inside a
+    /// job since nothing waits on the pool.
+    CallerRuns,
+}
+
+impl FromStr for RejectionPolicy {
+    type Err = String;
+
+    fn from_str(policy: &str) -> Result<RejectionPolicy, String> {
+        match policy {
+            "block" => Ok(RejectionPolicy::Block),
+            "reject" => Ok(RejectionPolicy::Reject),
+            "caller-runs" => Ok(RejectionPolicy::CallerRuns),
+            _ => Err(format!("unknown rejection policy {:?}", policy)),
+        }
+    }
+}
+
+/// Threads in a pool built without setting any
+pub const DEFAULT_THREADS: u16 = 4;
+
+/// What worker threads are named, before their id
+pub const DEFAULT_THREAD_NAME: &str = "ws-worker";
+
+/// Settings for a [`ThreadPool`], applied by [`build`](ThreadPoolBuilder::build).
+///
+/// Anything left unset takes the default: `DEFAULT_THREADS` threads,
+/// `DEFAULT_QUEUE_BOUND` queued jobs, `RejectionPolicy::Reject`, workers
+/// that never retire, and threads named `DEFAULT_THREAD_NAME`.
+#[derive(Debug, Clone)]
+pub struct ThreadPoolBuilder {
+    threads: u16,
+    queue_bound: usize,
+    policy: RejectionPolicy,
+    idle_timeout: Option<(Duration, u16)>,
+    thread_name: String,
+}
+
+impl Default for ThreadPoolBuilder {
+    fn default() -> Self {
+        ThreadPoolBuilder {
+            threads: DEFAULT_THREADS,
+            queue_bound: DEFAULT_QUEUE_BOUND,
+            policy: RejectionPolicy::Reject,
+            idle_timeout: None,
+            thread_name: DEFAULT_THREAD_NAME.to_string(),
+        }
+    }
+}
+
+impl ThreadPoolBuilder {
+    pub fn new() -> ThreadPoolBuilder {
+        ThreadPoolBuilder::default()
+    }
+
+    /// How many workers to start with. Zero makes `build` fail.
+    pub fn threads(mut self, threads: u16) -> ThreadPoolBuilder {
+        self.threads = threads;
+        self
+    }
+
+    /// Jobs that may be queued on top of one per worker
+    pub fn queue_bound(mut self, queue_bound: usize) -> ThreadPoolBuilder {
+        self.queue_bound = queue_bound;
+        self
+    }
+
+    /// What `submit` does when the queue is full
+    pub fn rejection_policy(mut self, policy: RejectionPolicy) -> ThreadPoolBuilder {
+        self.policy = policy;
+        self
+    }
+
+    /// Let idle workers exit, as [`ThreadPool::set_idle_timeout`] does
+    pub fn idle_timeout(mut self, timeout: Duration, min_threads: u16) -> ThreadPoolBuilder {
+        self.idle_timeout = Some((timeout, min_threads));
+        self
+    }
+
+    /// Name worker threads `{name}-{id}`, as shown by debuggers and in
+    /// panic messages
+    pub fn thread_name(mut self, name: impl Into<String>) -> ThreadPoolBuilder {
+        self.thread_name = name.into();
+        self
+    }
+
+    /// Start the pool. Fails if it would have no threads or a worker thread
+    /// could not be spawned.
+    pub fn build(self) -> Result<ThreadPool, PoolError> {
+        if self.threads == 0 {
+            return Err(PoolError::NoThreads);
+        }
+
+        let (exit_sender, exits) = mpsc::channel();
+        let mut pool = ThreadPool {
+            workers: Mutex::new(Vec::with_capacity(self.threads.into())),
+            shared: Arc::new(Shared::new()),
+            queue_bound: self.queue_bound,
+            policy: self.policy,
+            exit_sender,
+            exits: Mutex::new(exits),
+            next_id: AtomicU16::new(self.threads),
+            thread_name: self.thread_name,
+            shut_down: AtomicBool::new(false),
+        };
+        if let Some((timeout, min_threads)) = self.idle_timeout {
+            pool.set_idle_timeout(timeout, min_threads);
+        }
+        for id in 0..self.threads {
+            // Dropping the pool on failure stops the workers already spawned
+            let worker = Worker::new(
+                id,
+                &pool.thread_name,
+                Arc::clone(&pool.shared),
+                pool.exit_sender.clone(),
+            )
+            .map_err(PoolError::Spawn)?;
+            pool.workers.get_mut().unwrap().push(worker);
+        }
+
+        Ok(pool)
+    }
+}
+
+pub struct ThreadPool {
+    workers: Mutex<Vec<Worker>>,
+    shared: Arc<Shared>,
+    /// Jobs that may be queued on top of one per worker
+    queue_bound: usize,
+    /// What `submit` does when the queue is full
+    policy: RejectionPolicy,
+    /// Workers report here as they exit
+    exit_sender: mpsc::Sender<Exit>,
+    exits: Mutex<mpsc::Receiver<Exit>>,
+    next_id: AtomicU16,
+    /// Worker threads are named this followed by their id
+    thread_name: String,
+    /// Set by the first shutdown, SIGINT's or `Drop`'s, so later ones are
+    /// no-ops
+    shut_down: AtomicBool,
+}
+
+impl ThreadPool {
+    /// Create a new ThreadPool
+    ///
+    /// nthreads is the number of threads in the pool. Up to
+    /// `DEFAULT_QUEUE_BOUND` jobs may be queued, after which `submit` fails.
+    ///
+    /// Fails if nthreads is zero or a worker thread could not be spawned.
+    pub fn new(nthreads: u16) -> Result<ThreadPool, PoolError> {
+        ThreadPool::with_queue_bound(nthreads, DEFAULT_QUEUE_BOUND, RejectionPolicy::Reject)
+    }
+
+    /// Create a ThreadPool that holds at most `queue_bound` jobs beyond one
+    /// for each worker.
+    ///
+    /// What happens to jobs submitted when it is full is up to `policy`.
+    /// Fails as `new` does.
+    pub fn with_queue_bound(
+        nthreads: u16,
+        queue_bound: usize,
+        policy: RejectionPolicy,
+    ) -> Result<ThreadPool, PoolError> {
+        ThreadPool::builder()
+            .threads(nthreads)
+            .queue_bound(queue_bound)
+            .rejection_policy(policy)
+            .build()
+    }
+
+    /// Configure a pool one setting at a time
+    pub fn builder() -> ThreadPoolBuilder {
+        ThreadPoolBuilder::new()
+    }
+
+    /// Let workers that have waited `timeout` for a job exit, as long as at
+    /// least `min_threads` remain, or one if `min_threads` is zero. An idle
+    /// pool may take up to a second to notice the new setting.
+    pub fn set_idle_timeout(&self, timeout: Duration, min_threads: u16) {
+        // Zero would turn retiring off, so round up to the smallest timeout
+        let ms = u64::try_from(timeout.as_millis())
+            .unwrap_or(u64::MAX)
+            .max(1);
+        let counters = &self.shared.counters;
+        counters
+            .min_workers
+            .store(usize::from(min_threads.max(1)), Ordering::SeqCst);
+        counters.idle_timeout_ms.store(ms, Ordering::SeqCst);
+    }
+
+    /// The number of workers in the pool
+    pub fn size(&self) -> usize {
+        let mut workers = self.workers.lock().unwrap();
+        self.reap_exited(&mut workers);
+        workers.len()
+    }
+
+    fn counters(&self) -> &Counters {
+        &self.shared.counters
+    }
+
+    fn live(&self) -> usize {
+        self.counters().live.load(Ordering::SeqCst)
+    }
+
+    /// Join and forget the workers that have exited on their own
+    fn reap_exited(&self, workers: &mut Vec<Worker>) {
+        // Shutdown waits on the exits itself
+        let exits = match self.exits.try_lock() {
+            Ok(exits) => exits,
+            Err(_) => return,
+        };
+        for exit in exits.try_iter() {
+            self.forget_exited(workers, exit);
+        }
+    }
+
+    /// Remove a worker that has exited, spawning a replacement if it died
+    /// so the pool stays the same size
+    fn forget_exited(&self, workers: &mut Vec<Worker>, exit: Exit) {
+        let id = match exit {
+            Exit::Terminated(id) | Exit::Retired(id) => return remove_worker(workers, id),
+            Exit::Died(id) => id,
+        };
+        remove_worker(workers, id);
+        self.counters().died.fetch_sub(1, Ordering::SeqCst);
+        if self.shut_down.load(Ordering::SeqCst) {
+            return;
+        }
+        match self.spawn_worker() {
+            Ok(worker) => {
+                log!(
+                    Level::Warn,
+                    "replaced dead worker {} with worker {}",
+                    id,
+                    worker.id
+                );
+                workers.push(worker);
+            }
+            Err(e) => log!(Level::Error, "failed to replace dead worker {}: {}", id, e),
+        }
+    }
+
+    /// Replace any workers that have died, unless the workers are busy
+    /// being changed already
+    fn replace_dead(&self) {
+        if self.counters().died.load(Ordering::SeqCst) == 0 {
+            return;
+        }
+        if let Ok(mut workers) = self.workers.try_lock() {
+            self.reap_exited(&mut workers);
+        }
+    }
+
+    fn spawn_worker(&self) -> io::Result<Worker> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        Worker::new(
+            id,
+            &self.thread_name,
+            Arc::clone(&self.shared),
+            self.exit_sender.clone(),
+        )
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        let active = self.counters().active.load(Ordering::SeqCst);
+        let pending = self.counters().pending.load(Ordering::SeqCst);
+
+        PoolStats {
+            workers: self.size(),
+            active,
+            completed: self.counters().completed.load(Ordering::SeqCst),
+            queued: pending.saturating_sub(active),
+        }
+    }
+
+    /// Grow or shrink the pool to `new_size` workers.
+    ///
+    /// Shrinking retires workers as they become free, so this blocks until
+    /// that many workers have finished their current jobs and any jobs
+    /// queued ahead of the retirement.
+    ///
+    /// Fails if `new_size` is zero, or if a new worker thread could not be
+    /// spawned, in which case the pool keeps the workers it managed to add.
+    pub fn resize(&mut self, new_size: u16) -> Result<(), PoolError> {
+        if new_size == 0 {
+            return Err(PoolError::NoThreads);
+        }
+
+        if *self.shut_down.get_mut() {
+            log!(Level::Warn, "not resizing a pool that has been shut down");
+            return Ok(());
+        }
+        let mut workers = self.workers.lock().unwrap();
+        self.reap_exited(&mut workers);
+        let new_size = usize::from(new_size);
+
+        while workers.len() < new_size {
+            workers.push(self.spawn_worker().map_err(PoolError::Spawn)?);
+        }
+
+        let excess = workers.len() - new_size;
+        for _ in 0..excess {
+            self.shared.push(Message::Terminate);
+        }
+
+        // Whichever workers pick up the terminate messages are the ones to
+        // go, though idle ones may retire in the meantime too
+        let exits = self.exits.lock().unwrap();
+        let mut terminated = 0;
+        while terminated < excess {
+            match exits.recv().unwrap() {
+                Exit::Terminated(id) => {
+                    terminated += 1;
+                    remove_worker(&mut workers, id);
+                }
+                exit => self.forget_exited(&mut workers, exit),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Queue a job to run on the next free worker. If the queue is full the
+    /// pool's [`RejectionPolicy`] decides what happens to it; the job is
+    /// dropped if it is rejected.
+    pub fn submit<F>(&self, request: F) -> Result<(), SubmitError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.replace_dead();
+        match self.reserve()? {
+            true => self.shared.push(Message::NewJob(Box::new(request))),
+            false => self.run_on_caller(Box::new(request)),
+        }
+        Ok(())
+    }
+
+    /// Queue a job to run ahead of, or behind, jobs of other priorities.
+    /// `Priority::Normal` is the same as [`submit`](ThreadPool::submit). A
+    /// full queue is handled as for `submit`, whatever the priority.
+    pub fn submit_with_priority<F>(&self, priority: Priority, job: F) -> Result<(), SubmitError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        if priority == Priority::Normal {
+            return self.submit(job);
+        }
+        self.replace_dead();
+        match self.reserve()? {
+            true => self.shared.push_prioritized(priority, Box::new(job)),
+            false => self.run_on_caller(Box::new(job)),
+        }
+        Ok(())
+    }
+
+    /// Count a new job as pending if there is room for it, applying the
+    /// rejection policy if not. Returns `false` if the caller should run
+    /// the job itself.
+    fn reserve(&self) -> Result<bool, SubmitError> {
+        if self.try_reserve()? {
+            return Ok(true);
+        }
+        match self.policy {
+            RejectionPolicy::Block => {}
+            RejectionPolicy::Reject => return Err(SubmitError::Full),
+            RejectionPolicy::CallerRuns => return Ok(false),
+        }
+
+        let mut sleep = self.shared.sleep.lock().unwrap();
+        while !self.try_reserve()? {
+            // Shutting down doesn't signal `room`, hence the timeout
+            sleep = self
+                .shared
+                .room
+                .wait_timeout(sleep, DRAIN_POLL_INTERVAL)
+                .unwrap()
+                .0;
+        }
+        Ok(true)
+    }
+
+    /// Run a job that found the queue full on the submitting thread, which
+    /// a panic must not take down any more than it would a worker
+    fn run_on_caller(&self, job: Job) {
+        log!(
+            Level::Debug,
+            "queue full, running job on the submitting thread"
+        );
+        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(job)) {
+            log!(
+                Level::Error,
+                "job panicked on the submitting thread: {}",
+                panic_message(payload.as_ref())
+            );
+        }
+        self.counters().completed.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn try_reserve(&self) -> Result<bool, SubmitError> {
+        if self.shut_down.load(Ordering::SeqCst) {
+            return Err(SubmitError::Disconnected);
+        }
+
+        let capacity = self.queue_bound + self.live();
+        let reserved =
+            self.counters()
+                .pending
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |pending| {
+                    if pending < capacity {
+                        Some(pending + 1)
+                    } else {
+                        None
+                    }
+                });
+        Ok(reserved.is_ok())
+    }
+
+    /// Submit a job whose return value is wanted back. A panic in the job is
+    /// caught and reported through the handle.
+    pub fn submit_with_result<F, T>(&self, f: F) -> Result<JobHandle<T>, SubmitError>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (sender, receiver) = mpsc::sync_channel(1);
+        self.submit(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(f))
+                .map_err(|payload| JobError::Panicked(panic_message(payload.as_ref())));
+            // The handle may have been dropped if nobody wants the result
+            let _ = sender.send(result);
+        })?;
+
+        Ok(JobHandle { receiver })
+    }
+
+    /// Submit every job in `jobs`, returning their handles in the same
+    /// order. A job that could not be submitted gets a handle that reports
+    /// `JobError::Cancelled`.
+    pub fn submit_all<F, T>(&self, jobs: Vec<F>) -> Vec<JobHandle<T>>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        jobs.into_iter()
+            .map(|job| {
+                self.submit_with_result(job).unwrap_or_else(|_| {
+                    // The sender goes straight away, so the handle sees the
+                    // job as cancelled
+                    let (_, receiver) = mpsc::sync_channel(1);
+                    JobHandle { receiver }
+                })
+            })
+            .collect()
+    }
+
+    /// Terminate and join all workers through a shared reference, as
+    /// needed by the signal handler.
+    ///
+    /// Calling this more than once is harmless, and once it has returned
+    /// `submit` fails with `SubmitError::Disconnected`.
+    pub fn shutdown(&self) {
+        if self.shut_down.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        let mut workers = self.workers.lock().unwrap();
+        terminate_and_join(&self.shared, &mut workers, self.live());
+    }
+
+    /// Stop accepting jobs and give the ones already submitted up to
+    /// `timeout` to finish before terminating the workers.
+    ///
+    /// If the timeout passes first, jobs still queued are dropped without
+    /// running. Jobs already running can't be interrupted, so any worker
+    /// still busy with one is left to finish on its own rather than joined.
+    /// Like `shutdown`, only the first call has any effect.
+    pub fn shutdown_graceful(&self, timeout: Duration) {
+        if self.shut_down.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        let counters = self.counters();
+        let deadline = Instant::now() + timeout;
+        while counters.pending.load(Ordering::SeqCst) > 0 && Instant::now() < deadline {
+            thread::sleep(DRAIN_POLL_INTERVAL);
+        }
+
+        let mut workers = self.workers.lock().unwrap();
+
+        if counters.pending.load(Ordering::SeqCst) == 0 {
+            terminate_and_join(&self.shared, &mut workers, self.live());
+            return;
+        }
+
+        let dropped = self
+            .shared
+            .drain()
+            .into_iter()
+            .filter(|message| matches!(message, Message::NewJob(_)))
+            .count();
+        counters.pending.fetch_sub(dropped, Ordering::SeqCst);
+        log!(
+            Level::Warn,
+            "shutdown timed out, dropped {} queued jobs",
+            dropped
+        );
+
+        // Every worker that is free will pick up one of these and exit
+        for _ in 0..self.live() {
+            self.shared.injector.push(Message::Terminate);
+        }
+        {
+            let _sleep = self.shared.sleep.lock().unwrap();
+            self.shared.work.notify_all();
+        }
+
+        let exits = self.exits.lock().unwrap();
+        let deadline = Instant::now() + DRAIN_POLL_INTERVAL * 10;
+        while let Some(wait) = deadline.checked_duration_since(Instant::now()) {
+            if exits.recv_timeout(wait).is_err() {
+                break;
+            }
+        }
+
+        workers
+            .iter_mut()
+            .for_each(|worker| match worker.join_handle.take() {
+                Some(handle) if handle.is_finished() => {
+                    log!(Level::Debug, "joining worker {}", worker.id);
+                    handle.join().unwrap();
+                }
+                Some(_) => log!(Level::Warn, "abandoning busy worker {}", worker.id),
+                None => {}
+            });
+    }
+
+    /// Terminate and join all workers when the pool is exclusively owned.
+    fn shutdown_mut(&mut self) {
+        if std::mem::replace(self.shut_down.get_mut(), true) {
+            return;
+        }
+
+        let live = self.live();
+        let workers = self.workers.get_mut().unwrap();
+        terminate_and_join(&self.shared, workers, live);
+    }
+}
+
+/// Remove the worker with `id`, joining it. It must have exited already.
+fn remove_worker(workers: &mut Vec<Worker>, id: u16) {
+    if let Some(pos) = workers.iter().position(|worker| worker.id == id) {
+        let mut worker = workers.remove(pos);
+        if let Some(handle) = worker.join_handle.take() {
+            handle.join().unwrap();
+        }
+    }
+}
+
+/// Terminate and join whichever workers have not been joined already.
+/// Workers that retired have exited already, so only the `live` ones are
+/// sent a terminate message.
+fn terminate_and_join(shared: &Shared, workers: &mut [Worker], live: usize) {
+    let unjoined = workers
+        .iter()
+        .filter(|worker| worker.join_handle.is_some())
+        .count();
+    if unjoined == 0 {
+        return;
+    }
+
+    log!(Level::Debug, "sending terminate message to all workers");
+    for _ in 0..unjoined.min(live) {
+        shared.push(Message::Terminate);
+    }
+
+    log!(Level::Debug, "joining all workers on shutdown");
+    workers.iter_mut().for_each(|worker| {
+        if let Some(handle) = worker.join_handle.take() {
+            log!(Level::Debug, "joining worker {}", worker.id);
+            handle.join().unwrap();
+        }
+    });
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        self.shutdown_mut();
+    }
+}
+
+struct Worker {
+    id: u16,
+    join_handle: Option<JoinHandle<()>>,
+}
+
+impl Worker {
+    fn new(
+        id: u16,
+        name: &str,
+        shared: Arc<Shared>,
+        exit_sender: mpsc::Sender<Exit>,
+    ) -> io::Result<Worker> {
+        let local = crossbeam_deque::Worker::new_fifo();
+        shared.stealers.write().unwrap().push((id, local.stealer()));
+        shared.counters.live.fetch_add(1, Ordering::SeqCst);
+
+        let spawn_shared = Arc::clone(&shared);
+        let spawned = thread::Builder::new()
+            .name(format!("{}-{}", name, id))
+            .spawn(move || {
+                // Jobs can't panic their way out of the loop, but a bug in it,
+                // such as unwrapping a poisoned lock, still could
+                let run = AssertUnwindSafe(|| worker_loop(id, &local, &spawn_shared));
+                let exit = panic::catch_unwind(run).unwrap_or_else(|payload| {
+                    log!(
+                        Level::Error,
+                        "worker {} died: {}",
+                        id,
+                        panic_message(payload.as_ref())
+                    );
+                    let counters = &spawn_shared.counters;
+                    counters.live.fetch_sub(1, Ordering::SeqCst);
+                    counters.died.fetch_add(1, Ordering::SeqCst);
+                    Exit::Died(id)
+                });
+                leave(id, &local, &spawn_shared);
+                let _ = exit_sender.send(exit);
+            });
+        let join_handle = match spawned {
+            Ok(handle) => handle,
+            Err(e) => {
+                shared.counters.live.fetch_sub(1, Ordering::SeqCst);
+                shared
+                    .stealers
+                    .write()
+                    .unwrap()
+                    .retain(|(stealer_id, _)| *stealer_id != id);
+                return Err(e);
+            }
+        };
+
+        let join_handle = Some(join_handle);
+
+        Ok(Worker { id, join_handle })
+    }
+}
+
+fn worker_loop(id: u16, local: &crossbeam_deque::Worker<Message>, shared: &Shared) -> Exit {
+    let counters = &shared.counters;
+    loop {
+        let message = match find_message(id, local, shared) {
+            Some(message) => message,
+            None => {
+                if wait_for_work(shared) || counters.idle_timeout().is_none() {
+                    continue;
+                }
+                if counters.try_retire() {
+                    log!(Level::Debug, "retiring idle worker {}", id);
+                    return Exit::Retired(id);
+                }
+                continue;
+            }
+        };
+
+        match message {
+            Message::NewJob(job) => {
+                log!(Level::Debug, "executing job on worker {}", id);
+                counters.active.fetch_add(1, Ordering::SeqCst);
+                let _finished = JobFinished(shared);
+                // A panicking job must not take the worker down with it,
+                // or the pool would shrink with every panic
+                if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(job)) {
+                    log!(
+                        Level::Error,
+                        "job panicked on worker {}: {}",
+                        id,
+                        panic_message(payload.as_ref())
+                    );
+                }
+            }
+
+            Message::Terminate => {
+                log!(Level::Debug, "terminating worker {}", id);
+                counters.live.fetch_sub(1, Ordering::SeqCst);
+                return Exit::Terminated(id);
+            }
+        }
+    }
+}
+
+/// Counts a job as finished when dropped, so a worker that dies after
+/// starting one doesn't leave it counted as running and queued for good
+struct JobFinished<'a>(&'a Shared);
+
+impl Drop for JobFinished<'_> {
+    fn drop(&mut self) {
+        let counters = &self.0.counters;
+        counters.active.fetch_sub(1, Ordering::SeqCst);
+        counters.completed.fetch_add(1, Ordering::SeqCst);
+        counters.pending.fetch_sub(1, Ordering::SeqCst);
+
+        let _sleep = self.0.sleep.lock().unwrap_or_else(PoisonError::into_inner);
+        self.0.room.notify_one();
+    }
+}
+
+/// The next message for a worker: a high priority job, else from its own
+/// queue, else a batch from the global queue, else stolen from another
+/// worker, else a low priority job
+fn find_message(
+    id: u16,
+    local: &crossbeam_deque::Worker<Message>,
+    shared: &Shared,
+) -> Option<Message> {
+    if let Some(job) = shared.pop_prioritized(Priority::High) {
+        return Some(Message::NewJob(job));
+    }
+
+    let message = find_normal_message(id, local, shared);
+    if let Some(Message::NewJob(_)) = message {
+        return message;
+    }
+    match shared.pop_prioritized(Priority::Low) {
+        Some(job) => {
+            // Low priority jobs were queued before any terminate message was
+            // sent, so they get to run first, as jobs in the other queues do
+            if let Some(terminate) = message {
+                shared.push(terminate);
+            }
+            Some(Message::NewJob(job))
+        }
+        None => message,
+    }
+}
+
+fn find_normal_message(
+    id: u16,
+    local: &crossbeam_deque::Worker<Message>,
+    shared: &Shared,
+) -> Option<Message> {
+    if let Some(message) = local.pop() {
+        return Some(message);
+    }
+
+    let message = iter::repeat_with(|| {
+        shared.injector.steal_batch_and_pop(local).or_else(|| {
+            shared
+                .stealers
+                .read()
+                .unwrap()
+                .iter()
+                .filter(|(stealer_id, _)| *stealer_id != id)
+                .map(|(_, stealer)| stealer.steal())
+                .collect()
+        })
+    })
+    .find(|steal| !steal.is_retry())
+    .and_then(Steal::success);
+
+    // A batch may have brought more than this worker can get to soon, so
+    // let a sleeping one come and steal some
+    if !local.is_empty() {
+        let _sleep = shared.sleep.lock().unwrap();
+        shared.work.notify_one();
+    }
+
+    message
+}
+
+/// Sleep until there may be work, returning false if the idle timeout
+/// passed with none turning up
+fn wait_for_work(shared: &Shared) -> bool {
+    let sleep = shared.sleep.lock().unwrap();
+    // Checked with the lock held, as messages are pushed before the lock is
+    // taken to signal them
+    if shared.has_messages() {
+        return true;
+    }
+
+    let timeout = shared.counters.idle_timeout().unwrap_or(IDLE_SETTINGS_POLL);
+    let (_sleep, wait) = shared.work.wait_timeout(sleep, timeout).unwrap();
+    !wait.timed_out()
+}
+
+/// Hand back anything left in an exiting worker's queue and stop others
+/// stealing from it. A worker that died may have poisoned the locks, which
+/// must not stop it leaving.
+fn leave(id: u16, local: &crossbeam_deque::Worker<Message>, shared: &Shared) {
+    shared
+        .stealers
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .retain(|(stealer_id, _)| *stealer_id != id);
+
+    let mut returned = false;
+    while let Some(message) = local.pop() {
+        shared.injector.push(message);
+        returned = true;
+    }
+    if returned {
+        let _sleep = shared.sleep.lock().unwrap_or_else(PoisonError::into_inner);
+        shared.work.notify_all();
+    }
+}
+
+/// Why a pool could not be created
+#[derive(Debug)]
+pub enum PoolError {
+    /// A pool needs at least one thread
+    NoThreads,
+    /// A worker thread could not be spawned
+    Spawn(io::Error),
+}
+
+impl fmt::Display for PoolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PoolError::NoThreads => write!(f, "thread pool needs at least one thread"),
+            PoolError::Spawn(e) => write!(f, "failed to spawn worker thread: {}", e),
+        }
+    }
+}
+
+impl Error for PoolError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PoolError::NoThreads => None,
+            PoolError::Spawn(e) => Some(e),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubmitError {
+    /// The queue is at its bound
+    Full,
+    /// The workers have gone away, so the job can never run
+    Disconnected,
+}
+
+impl fmt::Display for SubmitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SubmitError::Full => write!(f, "job queue is full"),
+            SubmitError::Disconnected => write!(f, "thread pool is no longer running"),
+        }
+    }
+}
+
+impl Error for SubmitError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JobError {
+    /// The job panicked, with the panic message if it had one
+    Panicked(String),
+    /// The job was dropped without running, e.g. because the pool shut down
+    Cancelled,
+}
+
+impl fmt::Display for JobError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobError::Panicked(message) => write!(f, "job panicked: {}", message),
+            JobError::Cancelled => write!(f, "job was cancelled before it ran"),
+        }
+    }
+}
+
+impl Error for JobError {}
+
+/// The pending result of a job sent with `ThreadPool::submit_with_result`
+pub struct JobHandle<T> {
+    receiver: mpsc::Receiver<Result<T, JobError>>,
+}
+
+impl<T> JobHandle<T> {
+    /// Block until the job has finished and return its result
+    pub fn join(self) -> Result<T, JobError> {
+        self.receiver.recv().unwrap_or(Err(JobError::Cancelled))
+    }
+}
+
+/// Wait for every job in `handles` to finish, returning their results in
+/// the same order
+pub fn join_all<T>(handles: Vec<JobHandle<T>>) -> Vec<Result<T, JobError>> {
+    handles.into_iter().map(JobHandle::join).collect()
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic payload".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, sync::Barrier};
+
+    use super::*;
+
+    #[test]
+    fn resize_grows_the_pool() {
+        let mut pool = ThreadPool::new(2).unwrap();
+        pool.resize(4).unwrap();
+        assert_eq!(pool.size(), 4);
+    }
+
+    #[test]
+    fn resize_shrinks_the_pool() {
+        let mut pool = ThreadPool::new(4).unwrap();
+        pool.resize(1).unwrap();
+        assert_eq!(pool.size(), 1);
+    }
+
+    #[test]
+    fn resize_to_zero_is_an_error() {
+        let mut pool = ThreadPool::new(2).unwrap();
+        assert!(matches!(pool.resize(0), Err(PoolError::NoThreads)));
+        assert_eq!(pool.size(), 2);
+    }
+
+    #[test]
+    fn shutdown_runs_every_submitted_job() {
+        let ran = Arc::new(AtomicUsize::new(0));
+        let pool = ThreadPool::new(4).unwrap();
+        for _ in 0..100 {
+            let ran = Arc::clone(&ran);
+            pool.submit(move || {
+                ran.fetch_add(1, Ordering::SeqCst);
+            })
+            .unwrap();
+        }
+        pool.shutdown();
+        assert_eq!(ran.load(Ordering::SeqCst), 100);
+        assert_eq!(pool.submit(|| {}), Err(SubmitError::Disconnected));
+    }
+
+    #[test]
+    fn dropping_the_pool_joins_the_workers() {
+        let ran = Arc::new(AtomicUsize::new(0));
+        {
+            let pool = ThreadPool::new(2).unwrap();
+            for _ in 0..10 {
+                let ran = Arc::clone(&ran);
+                pool.submit(move || {
+                    thread::sleep(Duration::from_millis(5));
+                    ran.fetch_add(1, Ordering::SeqCst);
+                })
+                .unwrap();
+            }
+        }
+        assert_eq!(ran.load(Ordering::SeqCst), 10);
+    }
+
+    #[test]
+    fn submit_with_result_returns_the_result() {
+        let pool = ThreadPool::new(2).unwrap();
+        let handle = pool.submit_with_result(|| 6 * 7).unwrap();
+        assert_eq!(handle.join(), Ok(42));
+    }
+
+    #[test]
+    fn submit_with_result_reports_a_panic() {
+        let pool = ThreadPool::new(2).unwrap();
+        let handle = pool
+            .submit_with_result(|| -> u32 { panic!("boom") })
+            .unwrap();
+        assert_eq!(handle.join(), Err(JobError::Panicked("boom".to_string())));
+    }
+
+    #[test]
+    fn a_panicking_job_leaves_the_worker_running() {
+        let ran = Arc::new(AtomicUsize::new(0));
+        let pool = ThreadPool::new(1).unwrap();
+        pool.submit(|| panic!("job failed")).unwrap();
+        let counter = Arc::clone(&ran);
+        let handle = pool
+            .submit_with_result(move || counter.fetch_add(1, Ordering::SeqCst))
+            .unwrap();
+        assert_eq!(handle.join(), Ok(0));
+        assert_eq!(ran.load(Ordering::SeqCst), 1);
+        assert_eq!(pool.size(), 1);
+    }
+
+    #[test]
+    fn shutting_down_twice_is_harmless() {
+        let pool = ThreadPool::new(3).unwrap();
+        pool.shutdown();
+        assert!(pool
+            .workers
+            .lock()
+            .unwrap()
+            .iter()
+            .all(|worker| worker.join_handle.is_none()));
+        pool.shutdown();
+        pool.shutdown_graceful(Duration::from_secs(1));
+        // Drop shuts down a third time
+    }
+
+    #[test]
+    fn graceful_shutdown_lets_running_jobs_finish() {
+        let finished = Arc::new(AtomicBool::new(false));
+        let (started, running) = mpsc::channel();
+        let pool = ThreadPool::new(1).unwrap();
+        let done = Arc::clone(&finished);
+        pool.submit(move || {
+            started.send(()).unwrap();
+            thread::sleep(Duration::from_millis(200));
+            done.store(true, Ordering::SeqCst);
+        })
+        .unwrap();
+        running.recv().unwrap();
+
+        pool.shutdown_graceful(Duration::from_secs(5));
+        assert!(finished.load(Ordering::SeqCst));
+        assert_eq!(pool.submit(|| {}), Err(SubmitError::Disconnected));
+    }
+
+    #[test]
+    fn graceful_shutdown_drops_queued_jobs_after_the_timeout() {
+        let ran = Arc::new(AtomicUsize::new(0));
+        let pool = ThreadPool::new(1).unwrap();
+        for _ in 0..3 {
+            let ran = Arc::clone(&ran);
+            pool.submit(move || {
+                thread::sleep(Duration::from_millis(100));
+                ran.fetch_add(1, Ordering::SeqCst);
+            })
+            .unwrap();
+        }
+
+        pool.shutdown_graceful(Duration::from_millis(50));
+        // Only the job already running when the timeout passed may finish
+        thread::sleep(Duration::from_millis(200));
+        assert!(ran.load(Ordering::SeqCst) <= 1);
+    }
+
+    /// Wait up to a few seconds for `done`, which workers changing in the
+    /// background make true eventually
+    fn eventually(done: impl Fn() -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            if done() {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        done()
+    }
+
+    #[test]
+    fn idle_workers_retire_down_to_the_minimum() {
+        let pool = ThreadPool::builder()
+            .threads(4)
+            .idle_timeout(Duration::from_millis(20), 2)
+            .build()
+            .unwrap();
+        assert!(eventually(|| pool.size() == 2));
+        // And no further
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(pool.size(), 2);
+        assert_eq!(pool.submit_with_result(|| 1).unwrap().join(), Ok(1));
+    }
+
+    #[test]
+    fn many_tiny_jobs_all_complete() {
+        let ran = Arc::new(AtomicUsize::new(0));
+        let pool = ThreadPool::with_queue_bound(4, 64, RejectionPolicy::Block).unwrap();
+        for _ in 0..10_000 {
+            let ran = Arc::clone(&ran);
+            pool.submit(move || {
+                ran.fetch_add(1, Ordering::SeqCst);
+            })
+            .unwrap();
+        }
+        pool.shutdown();
+        assert_eq!(ran.load(Ordering::SeqCst), 10_000);
+    }
+
+    #[test]
+    fn busy_workers_have_their_queued_jobs_stolen() {
+        let pool = Arc::new(ThreadPool::with_queue_bound(4, 4096, RejectionPolicy::Block).unwrap());
+        let threads = Arc::new(Mutex::new(HashSet::new()));
+        let ran = Arc::new(AtomicUsize::new(0));
+
+        // Several submitters at once, with uneven jobs, so batches pulled
+        // into one worker's queue pile up behind a slow job there
+        let submitters: Vec<_> = (0..4)
+            .map(|submitter| {
+                let (pool, threads, ran) =
+                    (Arc::clone(&pool), Arc::clone(&threads), Arc::clone(&ran));
+                thread::spawn(move || {
+                    for i in 0..500 {
+                        let (threads, ran) = (Arc::clone(&threads), Arc::clone(&ran));
+                        pool.submit(move || {
+                            if i % 100 == submitter {
+                                thread::sleep(Duration::from_millis(5));
+                            }
+                            threads.lock().unwrap().insert(thread::current().id());
+                            ran.fetch_add(1, Ordering::SeqCst);
+                        })
+                        .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for submitter in submitters {
+            submitter.join().unwrap();
+        }
+
+        pool.shutdown();
+        assert_eq!(ran.load(Ordering::SeqCst), 2000);
+        assert!(threads.lock().unwrap().len() > 1);
+    }
+
+    /// Keep a pool's only worker busy until the returned sender is dropped
+    fn block_worker(pool: &ThreadPool) -> mpsc::Sender<()> {
+        let (release, blocked) = mpsc::channel::<()>();
+        let (started, running) = mpsc::channel();
+        pool.submit(move || {
+            started.send(()).unwrap();
+            let _ = blocked.recv();
+        })
+        .unwrap();
+        running.recv().unwrap();
+        release
+    }
+
+    /// A pool with one worker and room for one queued job, both taken. The
+    /// worker is stuck until the returned sender is dropped.
+    fn full_pool(policy: RejectionPolicy) -> (Arc<ThreadPool>, mpsc::Sender<()>) {
+        let pool = Arc::new(ThreadPool::with_queue_bound(1, 1, policy).unwrap());
+        let release = block_worker(&pool);
+        pool.submit(|| {}).unwrap();
+        (pool, release)
+    }
+
+    #[test]
+    fn reject_policy_fails_when_full() {
+        let (pool, release) = full_pool(RejectionPolicy::Reject);
+        assert_eq!(pool.submit(|| {}), Err(SubmitError::Full));
+        drop(release);
+    }
+
+    #[test]
+    fn caller_runs_policy_runs_the_job_on_the_submitter() {
+        let (pool, release) = full_pool(RejectionPolicy::CallerRuns);
+        let ran_on = Arc::new(Mutex::new(None));
+        let record = Arc::clone(&ran_on);
+        pool.submit(move || *record.lock().unwrap() = Some(thread::current().id()))
+            .unwrap();
+        assert_eq!(*ran_on.lock().unwrap(), Some(thread::current().id()));
+        drop(release);
+    }
+
+    #[test]
+    fn caller_runs_policy_does_not_deadlock_inside_a_job() {
+        let pool =
+            Arc::new(ThreadPool::with_queue_bound(1, 0, RejectionPolicy::CallerRuns).unwrap());
+        let inner_pool = Arc::clone(&pool);
+        let handle = pool
+            .submit_with_result(move || {
+                // The only worker is running this job, so the queue is full
+                let ran = Arc::new(AtomicBool::new(false));
+                let inner_ran = Arc::clone(&ran);
+                inner_pool
+                    .submit(move || inner_ran.store(true, Ordering::SeqCst))
+                    .unwrap();
+                ran.load(Ordering::SeqCst)
+            })
+            .unwrap();
+        assert_eq!(handle.join(), Ok(true));
+    }
+
+    #[test]
+    fn block_policy_waits_for_room() {
+        let (pool, release) = full_pool(RejectionPolicy::Block);
+        let (submitted, done) = mpsc::channel();
+        let submitter = {
+            let pool = Arc::clone(&pool);
+            thread::spawn(move || {
+                pool.submit(|| {}).unwrap();
+                submitted.send(()).unwrap();
+            })
+        };
+        assert!(done.recv_timeout(Duration::from_millis(100)).is_err());
+        drop(release);
+        done.recv_timeout(Duration::from_secs(5)).unwrap();
+        submitter.join().unwrap();
+    }
+
+    #[test]
+    fn high_priority_jobs_overtake_queued_low_priority_ones() {
+        let pool = ThreadPool::new(1).unwrap();
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let record = |label: &'static str| {
+            let order = Arc::clone(&order);
+            move || order.lock().unwrap().push(label)
+        };
+
+        let release = block_worker(&pool);
+        for label in ["low 1", "low 2", "low 3"] {
+            pool.submit_with_priority(Priority::Low, record(label))
+                .unwrap();
+        }
+        pool.submit(record("normal")).unwrap();
+        pool.submit_with_priority(Priority::High, record("high"))
+            .unwrap();
+        drop(release);
+        pool.shutdown();
+
+        assert_eq!(
+            *order.lock().unwrap(),
+            ["high", "normal", "low 1", "low 2", "low 3"]
+        );
+    }
+
+    /// A panic payload that panics again when the worker drops it, which
+    /// gets past the worker's catch of job panics and takes it down
+    struct Bomb;
+
+    impl Drop for Bomb {
+        fn drop(&mut self) {
+            panic!("worker killed");
+        }
+    }
+
+    #[test]
+    fn a_dead_worker_is_replaced() {
+        let pool = ThreadPool::new(2).unwrap();
+        pool.submit(|| panic::panic_any(Bomb)).unwrap();
+
+        // Worker ids 0 and 1 were taken at the start, so the replacement
+        // is worker 2
+        assert!(eventually(
+            || pool.size() == 2 && pool.next_id.load(Ordering::SeqCst) == 3
+        ));
+        assert_eq!(pool.counters().died.load(Ordering::SeqCst), 0);
+        // The job the worker died in is over, not still running or queued
+        assert_eq!(pool.counters().active.load(Ordering::SeqCst), 0);
+        assert_eq!(pool.counters().pending.load(Ordering::SeqCst), 0);
+        let handles = pool.submit_all(vec![|| 1, || 2]);
+        assert_eq!(join_all(handles), [Ok(1), Ok(2)]);
+    }
+
+    #[test]
+    fn workers_run_jobs_at_the_same_time() {
+        let pool = ThreadPool::new(2).unwrap();
+        let barrier = Arc::new(Barrier::new(2));
+        let (done, finished) = mpsc::channel();
+        for _ in 0..2 {
+            let (barrier, done) = (Arc::clone(&barrier), done.clone());
+            pool.submit(move || {
+                // Only returns once both jobs are running, which they can't
+                // be if a worker holds a lock while running its job
+                barrier.wait();
+                done.send(()).unwrap();
+            })
+            .unwrap();
+        }
+
+        let both = (0..2).all(|_| finished.recv_timeout(Duration::from_secs(5)).is_ok());
+        if !both {
+            // A worker stuck at the barrier could never be joined
+            std::mem::forget(pool);
+        }
+        assert!(both, "jobs did not run concurrently");
+    }
+
+    #[test]
+    fn a_full_queue_rejects_the_next_job() {
+        let ran = Arc::new(AtomicUsize::new(0));
+        let pool = ThreadPool::with_queue_bound(1, 3, RejectionPolicy::Reject).unwrap();
+        let release = block_worker(&pool);
+        for _ in 0..3 {
+            let ran = Arc::clone(&ran);
+            pool.submit(move || {
+                ran.fetch_add(1, Ordering::SeqCst);
+            })
+            .unwrap();
+        }
+        assert_eq!(pool.stats().queued, 3);
+        assert_eq!(pool.submit(|| {}), Err(SubmitError::Full));
+        assert!(matches!(
+            pool.submit_with_result(|| ()),
+            Err(SubmitError::Full)
+        ));
+
+        drop(release);
+        pool.shutdown();
+        assert_eq!(ran.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn submitting_after_shutdown_is_a_clear_error() {
+        let pool = ThreadPool::new(2).unwrap();
+        pool.shutdown();
+        let error = pool.submit(|| {}).unwrap_err();
+        assert_eq!(error, SubmitError::Disconnected);
+        assert_eq!(error.to_string(), "thread pool is no longer running");
+        assert!(matches!(
+            pool.submit_with_result(|| 1),
+            Err(SubmitError::Disconnected)
+        ));
+        assert_eq!(
+            pool.submit_with_priority(Priority::High, || {}),
+            Err(SubmitError::Disconnected)
+        );
+    }
+
+    #[test]
+    fn workers_are_named_with_their_id() {
+        let pool = ThreadPool::new(1).unwrap();
+        let name = pool
+            .submit_with_result(|| thread::current().name().map(String::from))
+            .unwrap()
+            .join();
+        assert_eq!(name, Ok(Some(format!("{}-0", DEFAULT_THREAD_NAME))));
+
+        let pool = ThreadPool::builder()
+            .threads(3)
+            .thread_name("static")
+            .build()
+            .unwrap();
+        let names: HashSet<String> = join_all(
+            (0..30)
+                .map(|_| {
+                    pool.submit_with_result(|| {
+                        thread::sleep(Duration::from_millis(1));
+                        thread::current().name().unwrap().to_string()
+                    })
+                    .unwrap()
+                })
+                .collect(),
+        )
+        .into_iter()
+        .map(Result::unwrap)
+        .collect();
+        assert!(!names.is_empty());
+        for name in names {
+            let id = name.strip_prefix("static-").unwrap();
+            assert!(id.parse::<u16>().unwrap() < 3, "{}", name);
+        }
+    }
+
+    #[test]
+    fn stats_count_every_completed_job() {
+        let pool = ThreadPool::new(3).unwrap();
+        assert_eq!(
+            pool.stats(),
+            PoolStats {
+                workers: 3,
+                active: 0,
+                completed: 0,
+                queued: 0
+            }
+        );
+        for _ in 0..20 {
+            pool.submit(|| thread::sleep(Duration::from_millis(1)))
+                .unwrap();
+        }
+        assert!(eventually(|| pool.stats().completed == 20));
+        let stats = pool.stats();
+        assert_eq!((stats.active, stats.queued), (0, 0));
+    }
+
+    #[test]
+    fn stats_count_running_and_queued_jobs() {
+        let pool = ThreadPool::new(1).unwrap();
+        let release = block_worker(&pool);
+        pool.submit(|| {}).unwrap();
+        pool.submit(|| {}).unwrap();
+        let stats = pool.stats();
+        assert_eq!((stats.active, stats.queued, stats.completed), (1, 2, 0));
+        drop(release);
+        assert!(eventually(|| pool.stats().completed == 3));
+    }
+
+    #[test]
+    fn submit_all_returns_every_result_in_order() {
+        let pool = ThreadPool::new(3).unwrap();
+        let jobs: Vec<_> = (0..10).map(|i| move || i).collect();
+        let results = join_all(pool.submit_all(jobs));
+        let indices: Vec<usize> = results.into_iter().map(Result::unwrap).collect();
+        assert_eq!(indices, (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn submit_all_after_shutdown_cancels_each_job() {
+        let pool = ThreadPool::new(1).unwrap();
+        pool.shutdown();
+        let results = join_all(pool.submit_all(vec![|| 1, || 2]));
+        assert_eq!(
+            results,
+            [Err(JobError::Cancelled), Err(JobError::Cancelled)]
+        );
+    }
+
+    #[test]
+    fn a_pool_needs_at_least_one_thread() {
+        let error = ThreadPool::new(0).err().unwrap();
+        assert!(matches!(error, PoolError::NoThreads));
+        assert_eq!(error.to_string(), "thread pool needs at least one thread");
+
+        let pool = ThreadPool::new(4).unwrap();
+        assert_eq!(pool.size(), 4);
+    }
+
+    #[test]
+    fn a_builder_pool_has_what_was_set() {
+        let pool = ThreadPool::builder()
+            .threads(3)
+            .queue_bound(7)
+            .rejection_policy(RejectionPolicy::CallerRuns)
+            .thread_name("built")
+            .build()
+            .unwrap();
+        assert_eq!(pool.size(), 3);
+        assert_eq!(pool.queue_bound, 7);
+        assert_eq!(pool.policy, RejectionPolicy::CallerRuns);
+        assert_eq!(pool.thread_name, "built");
+    }
+
+    #[test]
+    fn unset_builder_fields_take_the_defaults() {
+        let pool = ThreadPool::builder().build().unwrap();
+        assert_eq!(pool.size(), usize::from(DEFAULT_THREADS));
+        assert_eq!(pool.queue_bound, DEFAULT_QUEUE_BOUND);
+        assert_eq!(pool.policy, RejectionPolicy::Reject);
+        assert_eq!(pool.thread_name, DEFAULT_THREAD_NAME);
+
+        assert!(matches!(
+            ThreadPool::builder().threads(0).build(),
+            Err(PoolError::NoThreads)
+        ));
+    }
+}
@@ -0,0 +1,131 @@
+use std::{error::Error, fmt};
+
+use crate::{response::Response, status::StatusCode};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RangeError {
+    /// The header is not a single `bytes=start-end` range
+    Malformed(String),
+    /// The range starts past the end of the content
+    Unsatisfiable,
+}
+
+impl fmt::Display for RangeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RangeError::Malformed(header) => write!(f, "malformed range {:?}", header),
+            RangeError::Unsatisfiable => write!(f, "range not satisfiable"),
+        }
+    }
+}
+
+impl Error for RangeError {}
+
+/// Parse a `Range` header for content `total_len` bytes long into the
+/// inclusive `(first, last)` byte positions it selects.
+///
+/// Handles `bytes=start-end`, open-ended `bytes=start-` and suffix
+/// `bytes=-count` ranges. An `end` past the content is clamped to the last
+/// byte. Lists of several ranges are not supported.
+pub fn parse_range(header: &str, total_len: u64) -> Result<(u64, u64), RangeError> {
+    let malformed = || RangeError::Malformed(header.to_string());
+    let spec = header
+        .trim()
+        .strip_prefix("bytes=")
+        .filter(|spec| !spec.contains(','))
+        .ok_or_else(malformed)?;
+    let (start, end) = spec.split_once('-').ok_or_else(malformed)?;
+    let parse = |n: &str| n.trim().parse::<u64>().map_err(|_| malformed());
+
+    let (first, last) = match (start.trim(), end.trim()) {
+        ("", "") => return Err(malformed()),
+        ("", suffix) => {
+            let count = parse(suffix)?;
+            if count == 0 {
+                return Err(RangeError::Unsatisfiable);
+            }
+            (total_len.saturating_sub(count), total_len.saturating_sub(1))
+        }
+        (start, "") => (parse(start)?, total_len.saturating_sub(1)),
+        (start, end) => {
+            let (first, last) = (parse(start)?, parse(end)?);
+            if last < first {
+                return Err(malformed());
+            }
+            (first, last.min(total_len.saturating_sub(1)))
+        }
+    };
+
+    if first >= total_len {
+        return Err(RangeError::Unsatisfiable);
+    }
+    Ok((first, last))
+}
+
+/// Mark `response`, which carries bytes `first` to `last` of content
+/// `total_len` bytes long, as a `206 Partial Content`
+pub fn partial(response: Response, first: u64, last: u64, total_len: u64) -> Response {
+    let content_range = format!("bytes {}-{}/{}", first, last, total_len);
+    let mut response = response.header("Content-Range", &content_range);
+    response.status = StatusCode::PARTIAL_CONTENT;
+    response
+}
+
+/// A `416` for content `total_len` bytes long
+pub fn unsatisfiable(total_len: u64) -> Response {
+    Response::new(StatusCode::RANGE_NOT_SATISFIABLE)
+        .header("Content-Range", &format!("bytes */{}", total_len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_a_closed_range() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Ok((0, 99)));
+        assert_eq!(parse_range("bytes=900-2000", 1000), Ok((900, 999)));
+    }
+
+    #[test]
+    fn parses_open_and_suffix_ranges() {
+        assert_eq!(parse_range("bytes=500-", 1000), Ok((500, 999)));
+        assert_eq!(parse_range("bytes=-100", 1000), Ok((900, 999)));
+        assert_eq!(parse_range("bytes=-5000", 1000), Ok((0, 999)));
+    }
+
+    #[test]
+    fn rejects_out_of_bounds_and_malformed_ranges() {
+        assert_eq!(
+            parse_range("bytes=1000-", 1000),
+            Err(RangeError::Unsatisfiable)
+        );
+        assert_eq!(
+            parse_range("bytes=-0", 1000),
+            Err(RangeError::Unsatisfiable)
+        );
+        for header in [
+            "bytes=5-1",
+            "bytes=0-1,5-9",
+            "items=0-1",
+            "bytes=a-b",
+            "bytes=-",
+        ] {
+            assert_eq!(
+                parse_range(header, 1000),
+                Err(RangeError::Malformed(header.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn partial_and_unsatisfiable_responses() {
+        let response = partial(Response::new(StatusCode::OK).body("cd"), 2, 3, 10);
+        assert_eq!(response.status, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers.get("Content-Range"), Some("bytes 2-3/10"));
+
+        let response = unsatisfiable(10);
+        assert_eq!(response.status, StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(response.headers.get("Content-Range"), Some("bytes */10"));
+    }
+}
@@ -0,0 +1,149 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// How often buckets that have filled back up are dropped
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A token bucket per client IP. Each request takes a token; tokens come
+/// back at `rate` per second, up to `burst`.
+#[derive(Debug)]
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+    next_prune: Mutex<Instant>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, now: Instant, rate: f64, burst: f64) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(burst);
+        self.updated = now;
+    }
+}
+
+impl RateLimiter {
+    /// # Panics
+    ///
+    /// If `rate` is not positive or `burst` is zero
+    pub fn new(rate: f64, burst: u32) -> RateLimiter {
+        assert!(rate > 0.0, "rate limit must be positive");
+        assert!(burst > 0, "burst must allow at least one request");
+
+        RateLimiter {
+            rate,
+            burst: f64::from(burst),
+            buckets: Mutex::new(HashMap::new()),
+            next_prune: Mutex::new(Instant::now() + PRUNE_INTERVAL),
+        }
+    }
+
+    /// Take a token for a request from `ip`. When there are none left,
+    /// returns how long until there will be.
+    pub fn check(&self, ip: IpAddr) -> Result<(), Duration> {
+        self.check_at(ip, Instant::now())
+    }
+
+    /// [`RateLimiter::check`] as of `now`
+    pub fn check_at(&self, ip: IpAddr, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().unwrap();
+        self.prune(&mut buckets, now);
+
+        let bucket = buckets.entry(ip).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        bucket.refill(now, self.rate, self.burst);
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate))
+        }
+    }
+
+    /// Forget clients whose buckets would be full by now, as a fresh bucket
+    /// behaves the same
+    fn prune(&self, buckets: &mut HashMap<IpAddr, Bucket>, now: Instant) {
+        let mut next_prune = self.next_prune.lock().unwrap();
+        if now < *next_prune {
+            return;
+        }
+        *next_prune = now + PRUNE_INTERVAL;
+
+        let (rate, burst) = (self.rate, self.burst);
+        buckets.retain(|_, bucket| {
+            let mut bucket = *bucket;
+            bucket.refill(now, rate, burst);
+            bucket.tokens < burst
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    const CLIENT: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+    const OTHER: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+
+    #[test]
+    fn a_burst_is_allowed_then_throttled() {
+        let limiter = RateLimiter::new(1.0, 3);
+        let now = Instant::now();
+        for _ in 0..3 {
+            assert_eq!(limiter.check_at(CLIENT, now), Ok(()));
+        }
+        assert_eq!(limiter.check_at(CLIENT, now), Err(Duration::from_secs(1)));
+        // Other clients have buckets of their own
+        assert_eq!(limiter.check_at(OTHER, now), Ok(()));
+    }
+
+    #[test]
+    fn tokens_refill_over_time_up_to_the_burst() {
+        let limiter = RateLimiter::new(2.0, 2);
+        let now = Instant::now();
+        limiter.check_at(CLIENT, now).unwrap();
+        limiter.check_at(CLIENT, now).unwrap();
+        assert_eq!(
+            limiter.check_at(CLIENT, now),
+            Err(Duration::from_millis(500))
+        );
+
+        let later = now + Duration::from_millis(500);
+        assert_eq!(limiter.check_at(CLIENT, later), Ok(()));
+        assert!(limiter.check_at(CLIENT, later).is_err());
+
+        // However long the client is away, it gets no more than a burst
+        let much_later = later + Duration::from_secs(60);
+        assert_eq!(limiter.check_at(CLIENT, much_later), Ok(()));
+        assert_eq!(limiter.check_at(CLIENT, much_later), Ok(()));
+        assert!(limiter.check_at(CLIENT, much_later).is_err());
+    }
+
+    #[test]
+    fn full_buckets_are_pruned() {
+        let limiter = RateLimiter::new(1.0, 1);
+        let now = Instant::now();
+        limiter.check_at(CLIENT, now).unwrap();
+        assert_eq!(limiter.buckets.lock().unwrap().len(), 1);
+
+        limiter.check_at(OTHER, now + PRUNE_INTERVAL).unwrap();
+        let buckets = limiter.buckets.lock().unwrap();
+        assert!(!buckets.contains_key(&CLIENT));
+        assert!(buckets.contains_key(&OTHER));
+    }
+}
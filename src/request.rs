@@ -0,0 +1,1208 @@
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    error::Error,
+    fmt,
+    io::{self, Read},
+    net::SocketAddr,
+    str,
+    str::FromStr,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    config::{
+        DEFAULT_HEADER_TIMEOUT, DEFAULT_MAX_BODY_SIZE, DEFAULT_MAX_HEADERS,
+        DEFAULT_MAX_HEADER_SIZE, DEFAULT_TIMEOUT,
+    },
+    connection::Connection,
+    cookie::parse_cookies,
+    error::ServerError,
+    headers::{find_blank_line, find_header_end, parse_headers, Headers},
+    url::parse_query,
+};
+
+const READ_CHUNK_SIZE: usize = 1024;
+/// Longest chunk-size or trailer line accepted in a chunked body
+const MAX_CHUNK_LINE: usize = 4096;
+const CONTINUE_RESPONSE: &[u8] = b"HTTP/1.1 100 Continue\r\n\r\n";
+/// Headers whose values are left out of request dumps, as they carry
+/// credentials
+const REDACTED_HEADERS: [&str; 3] = ["Authorization", "Proxy-Authorization", "Cookie"];
+/// Header carrying the request id, both from clients and in responses
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+/// Longest `X-Request-Id` taken from a client
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(1);
+
+/// The HTTP versions responses can be sent as
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Version {
+    Http10,
+    Http11,
+}
+
+impl Version {
+    /// The version to answer a request with, given the version token it was
+    /// sent with. Clients older than 1.1 get a 1.0 response.
+    pub fn for_request(version: &str) -> Version {
+        match version {
+            "HTTP/1.0" => Version::Http10,
+            _ => Version::Http11,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Version::Http10 => "HTTP/1.0",
+            Version::Http11 => "HTTP/1.1",
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Method {
+    Get,
+    Post,
+    Put,
+    Delete,
+    Head,
+    Options,
+    Patch,
+}
+
+impl Method {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Head => "HEAD",
+            Method::Options => "OPTIONS",
+            Method::Patch => "PATCH",
+        }
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Method {
+    type Err = ParseError;
+
+    /// Methods are case-sensitive, so `get` is rejected
+    fn from_str(token: &str) -> Result<Self, Self::Err> {
+        match token {
+            "GET" => Ok(Method::Get),
+            "POST" => Ok(Method::Post),
+            "PUT" => Ok(Method::Put),
+            "DELETE" => Ok(Method::Delete),
+            "HEAD" => Ok(Method::Head),
+            "OPTIONS" => Ok(Method::Options),
+            "PATCH" => Ok(Method::Patch),
+            other => Err(ParseError::UnknownMethod(other.to_string())),
+        }
+    }
+}
+
+impl TryFrom<&str> for Method {
+    type Error = ParseError;
+
+    fn try_from(token: &str) -> Result<Self, Self::Error> {
+        token.parse()
+    }
+}
+
+/// The first line of a request, e.g. `GET /index.html HTTP/1.1`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestLine {
+    pub method: Method,
+    pub path: String,
+    pub version: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// Nothing was received before the end of the line
+    Empty,
+    /// The request line is not valid UTF-8
+    InvalidEncoding,
+    /// The request line does not have exactly a method, path and version
+    MalformedRequestLine(String),
+    /// An HTTP/1.1 request has no `Host` header, which RFC 7230 requires
+    MissingHost,
+    /// The version token is not of the form `HTTP/x.y`
+    InvalidVersion(String),
+    /// The version is well formed but not HTTP/1.0 or HTTP/1.1
+    UnsupportedVersion(String),
+    /// The request target is not a path starting with `/`, or `*` for
+    /// OPTIONS
+    InvalidTarget(String),
+    /// The method token is not one of the supported verbs
+    UnknownMethod(String),
+    /// The blank line ending the header block was never found
+    IncompleteHeaders,
+    /// A header line is not of the form `Name: value`
+    MalformedHeader(String),
+    /// The header block did not end within the configured maximum size
+    HeadersTooLarge,
+    /// There are more header lines than the configured maximum
+    TooManyHeaders,
+    /// A `%` escape is truncated, not hex, or decodes to invalid UTF-8
+    InvalidPercentEncoding(String),
+    /// `Content-Length` is not a non-negative integer
+    InvalidContentLength(String),
+    /// The declared body is larger than the configured maximum
+    BodyTooLarge,
+    /// The connection closed before the declared body was received
+    IncompleteBody,
+    /// A chunk-size line of a chunked body is not valid hex
+    InvalidChunkSize(String),
+    /// `Transfer-Encoding` does not end in `chunked`, so where the body
+    /// ends can't be told
+    InvalidTransferEncoding(String),
+    /// A multipart body does not start with its boundary
+    MissingBoundary,
+    /// A multipart body ended before its closing boundary
+    IncompleteMultipart,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Empty => write!(f, "empty request"),
+            ParseError::InvalidEncoding => write!(f, "request is not valid UTF-8"),
+            ParseError::MalformedRequestLine(line) => {
+                write!(f, "malformed request line {:?}", line)
+            }
+            ParseError::MissingHost => write!(f, "HTTP/1.1 request has no Host header"),
+            ParseError::InvalidVersion(version) => write!(f, "invalid HTTP version {:?}", version),
+            ParseError::UnsupportedVersion(version) => {
+                write!(f, "unsupported HTTP version {:?}", version)
+            }
+            ParseError::InvalidTarget(target) => write!(f, "invalid request target {:?}", target),
+            ParseError::UnknownMethod(method) => write!(f, "unknown method {:?}", method),
+            ParseError::IncompleteHeaders => write!(f, "header block is not terminated"),
+            ParseError::MalformedHeader(line) => write!(f, "malformed header line {:?}", line),
+            ParseError::HeadersTooLarge => write!(f, "request headers too large"),
+            ParseError::TooManyHeaders => write!(f, "too many request headers"),
+            ParseError::InvalidPercentEncoding(input) => {
+                write!(f, "invalid percent-encoding in {:?}", input)
+            }
+            ParseError::InvalidContentLength(value) => {
+                write!(f, "invalid Content-Length {:?}", value)
+            }
+            ParseError::BodyTooLarge => write!(f, "request body too large"),
+            ParseError::IncompleteBody => write!(f, "request body ended early"),
+            ParseError::InvalidChunkSize(line) => write!(f, "invalid chunk size {:?}", line),
+            ParseError::InvalidTransferEncoding(codings) => {
+                write!(f, "Transfer-Encoding {:?} does not end in chunked", codings)
+            }
+            ParseError::MissingBoundary => write!(f, "multipart body has no boundary"),
+            ParseError::IncompleteMultipart => write!(f, "multipart body ended early"),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+/// Parse the request line out of the start of a raw request.
+///
+/// Only the bytes up to the first line break are looked at. Tokens may be
+/// separated by any amount of whitespace.
+pub fn parse_request_line(buffer: &[u8]) -> Result<RequestLine, ParseError> {
+    let end = buffer
+        .iter()
+        .position(|&b| b == b'\n')
+        .unwrap_or(buffer.len());
+    let line = str::from_utf8(&buffer[..end])
+        .map_err(|_| ParseError::InvalidEncoding)?
+        .trim_end_matches(['\r', '\0']);
+
+    if line.trim().is_empty() {
+        return Err(ParseError::Empty);
+    }
+
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    let (method, path, version) = match tokens.as_slice() {
+        [method, path, version] => (*method, *path, *version),
+        _ => return Err(ParseError::MalformedRequestLine(line.to_string())),
+    };
+
+    if !version.starts_with("HTTP/") {
+        return Err(ParseError::InvalidVersion(version.to_string()));
+    }
+    if !matches!(version, "HTTP/1.0" | "HTTP/1.1") {
+        return Err(ParseError::UnsupportedVersion(version.to_string()));
+    }
+
+    let method = method.parse()?;
+    // Only origin-form targets are served, plus `*` for a server-wide
+    // OPTIONS (RFC 7230 section 5.3)
+    let is_server_wide = path == "*" && method == Method::Options;
+    if !path.starts_with('/') && !is_server_wide {
+        return Err(ParseError::InvalidTarget(path.to_string()));
+    }
+
+    Ok(RequestLine {
+        method,
+        path: path.to_string(),
+        version: version.to_string(),
+    })
+}
+
+/// A parsed request, as handed to handlers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    /// Identifies the request in logs; taken from `X-Request-Id` when the
+    /// client sent one
+    pub request_id: String,
+    pub method: Method,
+    /// The request target exactly as the client sent it
+    pub target: String,
+    /// The target path, without its query string
+    pub path: String,
+    pub query: HashMap<String, String>,
+    pub version: String,
+    pub headers: Headers,
+    /// Cookies from every `Cookie` header, by name
+    pub cookies: HashMap<String, String>,
+    /// Values captured by `:name` segments of the matched route
+    pub params: HashMap<String, String>,
+    pub body: Vec<u8>,
+    /// The client's address, if the connection has one
+    pub peer_addr: Option<SocketAddr>,
+}
+
+/// How large a request may be, and how long its head may take to arrive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Largest request line plus header block, in bytes
+    pub max_header_size: usize,
+    /// Most header lines
+    pub max_headers: usize,
+    /// Largest body, in bytes
+    pub max_body_size: usize,
+    /// How long the whole head may take to arrive, however many reads it
+    /// trickles in over. There is no deadline when unset.
+    pub header_timeout: Option<Duration>,
+    /// How long any one read may block, which is put back once the head has
+    /// arrived within its deadline
+    pub read_timeout: Option<Duration>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_header_size: DEFAULT_MAX_HEADER_SIZE,
+            max_headers: DEFAULT_MAX_HEADERS,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            header_timeout: Some(DEFAULT_HEADER_TIMEOUT),
+            read_timeout: Some(DEFAULT_TIMEOUT),
+        }
+    }
+}
+
+impl Request {
+    /// Whether the request sent both `Transfer-Encoding` and
+    /// `Content-Length`. Something between it and the server may have
+    /// framed it by the other one, so the connection must not be reused
+    /// afterwards (RFC 9112 section 6.3).
+    pub fn has_conflicting_framing(&self) -> bool {
+        self.headers.contains("Transfer-Encoding") && self.headers.contains("Content-Length")
+    }
+
+    /// Read a whole request from `stream`, as [`read_request_with_continue`]
+    /// does, noting that it came from `peer_addr`. This is how the server
+    /// reads each request off a connection.
+    ///
+    /// Also returns whatever was read past the end of the request, which is
+    /// the start of the next one if the client is pipelining.
+    pub fn parse<S: Connection>(
+        stream: &mut S,
+        peer_addr: Option<SocketAddr>,
+        limits: &Limits,
+    ) -> Result<(Request, Vec<u8>), ServerError> {
+        let (mut request, rest) = read_request_with_continue(stream, limits)?;
+        request.peer_addr = peer_addr;
+        Ok((request, rest))
+    }
+
+    /// The request as it was sent, for debugging: the request line, the
+    /// headers with credentials redacted, and up to `max_body` bytes of the
+    /// body
+    pub fn dump(&self, max_body: usize) -> String {
+        let mut dump = format!("{} {} {}\n", self.method, self.target, self.version);
+        for (name, value) in self.headers.iter() {
+            let redacted = REDACTED_HEADERS
+                .iter()
+                .any(|header| header.eq_ignore_ascii_case(name));
+            let value = if redacted { "[redacted]" } else { value };
+            dump.push_str(&format!("{}: {}\n", name, value));
+        }
+        if !self.body.is_empty() {
+            let shown = &self.body[..self.body.len().min(max_body)];
+            dump.push('\n');
+            dump.push_str(&String::from_utf8_lossy(shown));
+            if shown.len() < self.body.len() {
+                dump.push_str(&format!(
+                    "... ({} more bytes)",
+                    self.body.len() - shown.len()
+                ));
+            }
+        }
+        dump
+    }
+}
+
+/// Parse the request line and at most `max_headers` headers out of a raw
+/// request head
+pub fn parse_request_head(buffer: &[u8], max_headers: usize) -> Result<Request, ParseError> {
+    let line = parse_request_line(buffer)?;
+    let headers_start = buffer
+        .iter()
+        .position(|&b| b == b'\n')
+        .map(|pos| pos + 1)
+        .ok_or(ParseError::IncompleteHeaders)?;
+    let headers = parse_headers(&buffer[headers_start..], max_headers)?;
+    if line.version == "HTTP/1.1" && !headers.contains("Host") {
+        return Err(ParseError::MissingHost);
+    }
+    let (path, query) = parse_query(&line.path);
+    let request_id = match headers.get(REQUEST_ID_HEADER) {
+        Some(id) if is_valid_request_id(id) => id.to_string(),
+        _ => next_request_id(),
+    };
+    let cookies = headers
+        .get_all("Cookie")
+        .iter()
+        .flat_map(|header| parse_cookies(header))
+        .collect();
+
+    Ok(Request {
+        request_id,
+        method: line.method,
+        target: line.path,
+        path,
+        query,
+        version: line.version,
+        headers,
+        cookies,
+        params: HashMap::new(),
+        body: Vec::new(),
+        peer_addr: None,
+    })
+}
+
+/// Whether a client's `X-Request-Id` is safe to log and send back: up to
+/// `MAX_REQUEST_ID_LEN` letters, digits, dots, underscores and hyphens
+fn is_valid_request_id(id: &str) -> bool {
+    (1..=MAX_REQUEST_ID_LEN).contains(&id.len())
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'.' | b'_' | b'-'))
+}
+
+/// Generate a fresh request id, unique within this process
+pub fn next_request_id() -> String {
+    let id = NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed);
+    format!("{:x}-{:06x}", std::process::id(), id)
+}
+
+/// Whether the client wants the connection kept open after this request.
+///
+/// HTTP/1.1 connections persist unless the client sends `Connection: close`,
+/// older versions only persist if the client asks for `keep-alive`.
+pub fn wants_keep_alive(version: &str, headers: &Headers) -> bool {
+    let has_token = |token: &str| {
+        headers.get_all("Connection").iter().any(|value| {
+            value
+                .split(',')
+                .any(|t| t.trim().eq_ignore_ascii_case(token))
+        })
+    };
+
+    if version == "HTTP/1.1" {
+        !has_token("close")
+    } else {
+        has_token("keep-alive")
+    }
+}
+
+/// Read from `reader` until the blank line ending the request head arrives,
+/// however many reads that takes.
+///
+/// Everything read is returned, so the buffer may run past the end of the
+/// head into the body. If the peer closes the connection first, whatever
+/// was received is returned as-is and may be incomplete, unless nothing was,
+/// which is `ServerError::ConnectionClosed`.
+///
+/// A client that has not finished the head `limits.header_timeout` after
+/// the first read began gets `ServerError::Timeout`, so one that sends a
+/// byte at a time can't hold the connection forever. Each read is given no
+/// longer than what is left of the deadline, so neither can one that sends
+/// nothing. The stream is left with that shortened read timeout.
+pub fn read_request_head<R: Connection>(
+    reader: &mut R,
+    limits: &Limits,
+) -> Result<Vec<u8>, ServerError> {
+    let max_size = limits.max_header_size;
+    let mut buffer = Vec::with_capacity(READ_CHUNK_SIZE);
+    let mut chunk = [0; READ_CHUNK_SIZE];
+    let deadline = limits
+        .header_timeout
+        .map(|timeout| Instant::now() + timeout);
+
+    loop {
+        if let Some(deadline) = deadline {
+            let left = deadline
+                .checked_duration_since(Instant::now())
+                .filter(|left| !left.is_zero())
+                .ok_or(ServerError::Timeout)?;
+            let timeout = limits
+                .read_timeout
+                .map_or(left, |timeout| timeout.min(left));
+            reader.set_read_timeout(Some(timeout))?;
+        }
+        let read = match reader.read(&mut chunk) {
+            Ok(0) if buffer.is_empty() => return Err(ServerError::ConnectionClosed),
+            Ok(0) => return Ok(buffer),
+            Ok(read) => read,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+
+        // The terminator may straddle two reads, so look back a few bytes.
+        // A look-back starting on a line break is no empty header block, so
+        // only a blank line inside it counts.
+        let scan_from = buffer.len().saturating_sub(3);
+        buffer.extend_from_slice(&chunk[..read]);
+        let end = match scan_from {
+            0 => find_header_end(&buffer),
+            _ => find_blank_line(&buffer[scan_from..]),
+        };
+
+        match end {
+            Some(end) if scan_from + end <= max_size => return Ok(buffer),
+            Some(_) => return Err(ParseError::HeadersTooLarge.into()),
+            None if buffer.len() > max_size => return Err(ParseError::HeadersTooLarge.into()),
+            None => {}
+        }
+    }
+}
+
+/// Read a whole request: the head, then a body of `Content-Length` bytes or
+/// a chunked body if one is declared. Anything read past the end of the
+/// request is discarded.
+pub fn read_request<R: Connection>(
+    reader: &mut R,
+    limits: &Limits,
+) -> Result<Request, ServerError> {
+    read_request_inner(reader, limits, |_| Ok(())).map(|(request, _)| request)
+}
+
+/// [`read_request`] on a stream that can be written to as well, so that a
+/// client sending `Expect: 100-continue` is told to go ahead before the body
+/// is read. A body that is too large is refused without it.
+///
+/// Also returns whatever was read past the end of the request, which is the
+/// start of the next one if the client is pipelining.
+pub fn read_request_with_continue<S: Connection>(
+    stream: &mut S,
+    limits: &Limits,
+) -> Result<(Request, Vec<u8>), ServerError> {
+    read_request_inner(stream, limits, |stream| {
+        stream.write_all(CONTINUE_RESPONSE)?;
+        stream.flush()
+    })
+}
+
+/// Read a request, calling `send_continue` once the head has been accepted
+/// if the client is waiting for permission to send its body
+fn read_request_inner<R: Connection>(
+    reader: &mut R,
+    limits: &Limits,
+    send_continue: impl FnOnce(&mut R) -> io::Result<()>,
+) -> Result<(Request, Vec<u8>), ServerError> {
+    let buffer = read_request_head(reader, limits)?;
+    if limits.header_timeout.is_some() {
+        reader.set_read_timeout(limits.read_timeout)?;
+    }
+    let mut request = parse_request_head(&buffer, limits.max_headers)?;
+
+    // parse_request_head succeeded, so the terminator is in the buffer
+    let head_len = find_header_end(&buffer).unwrap_or(buffer.len());
+    let read = &buffer[head_len..];
+
+    // Transfer-Encoding wins over Content-Length if both are sent, and a
+    // request body can only be framed by it when chunked comes last
+    // (RFC 9112 section 6.3)
+    if request.headers.contains("Transfer-Encoding") {
+        if !is_chunked(&request.headers) {
+            let codings = request.headers.get_all("Transfer-Encoding").join(", ");
+            return Err(ParseError::InvalidTransferEncoding(codings).into());
+        }
+        if expects_continue(&request) {
+            send_continue(reader)?;
+        }
+        // Chunks are read exactly, so what's left of `rest` is past the body
+        let mut rest = read;
+        request.body =
+            read_chunked_body(&mut (&mut rest).chain(&mut *reader), limits.max_body_size)?;
+        return Ok((request, rest.to_vec()));
+    }
+
+    let length = match request.headers.get("Content-Length") {
+        Some(value) => value
+            .parse::<usize>()
+            .map_err(|_| ParseError::InvalidContentLength(value.to_string()))?,
+        None => 0,
+    };
+    if length > limits.max_body_size {
+        return Err(ParseError::BodyTooLarge.into());
+    }
+    // Nothing to wait for if the body already arrived with the head
+    if expects_continue(&request) && length > read.len() {
+        send_continue(reader)?;
+    }
+
+    request.body = read_body(reader, read, length)?;
+    let rest = read.get(length..).unwrap_or(&[]).to_vec();
+
+    Ok((request, rest))
+}
+
+/// Whether the client is waiting for a `100 Continue` before sending its
+/// body. HTTP/1.0 clients don't know about the interim response.
+fn expects_continue(request: &Request) -> bool {
+    request.version != "HTTP/1.0"
+        && request
+            .headers
+            .get("Expect")
+            .is_some_and(|expect| expect.eq_ignore_ascii_case("100-continue"))
+}
+
+fn is_chunked(headers: &Headers) -> bool {
+    headers
+        .get_all("Transfer-Encoding")
+        .iter()
+        .flat_map(|value| value.split(','))
+        .last()
+        .is_some_and(|coding| coding.trim().eq_ignore_ascii_case("chunked"))
+}
+
+/// Read a body of exactly `length` bytes, starting with any that were
+/// already read along with the head
+fn read_body<R: Read>(reader: &mut R, read: &[u8], length: usize) -> Result<Vec<u8>, ServerError> {
+    let mut body = read[..read.len().min(length)].to_vec();
+    let already = body.len();
+    body.resize(length, 0);
+
+    reader
+        .read_exact(&mut body[already..])
+        .map_err(body_read_error)?;
+
+    Ok(body)
+}
+
+/// Read a `Transfer-Encoding: chunked` body, up to `max_size` bytes in
+/// total. Chunk extensions and trailing headers are ignored.
+pub fn read_chunked_body(reader: &mut impl Read, max_size: usize) -> Result<Vec<u8>, ServerError> {
+    let mut body = Vec::new();
+
+    loop {
+        let line = read_chunk_line(reader)?;
+        let size_field = line.split(';').next().unwrap_or("").trim();
+        let size = usize::from_str_radix(size_field, 16)
+            .map_err(|_| ParseError::InvalidChunkSize(line.clone()))?;
+
+        if size == 0 {
+            break;
+        }
+        if body.len() + size > max_size {
+            return Err(ParseError::BodyTooLarge.into());
+        }
+
+        let start = body.len();
+        body.resize(start + size, 0);
+        reader
+            .read_exact(&mut body[start..])
+            .map_err(body_read_error)?;
+
+        if !read_chunk_line(reader)?.is_empty() {
+            return Err(ParseError::InvalidChunkSize(line).into());
+        }
+    }
+
+    // Skip the trailer section up to the blank line that ends the body
+    while !read_chunk_line(reader)?.is_empty() {}
+
+    Ok(body)
+}
+
+/// Read one CRLF (or bare LF) terminated line, a byte at a time so nothing
+/// past the line is consumed
+fn read_chunk_line(reader: &mut impl Read) -> Result<String, ServerError> {
+    let mut line = Vec::new();
+    let mut byte = [0; 1];
+
+    loop {
+        reader.read_exact(&mut byte).map_err(body_read_error)?;
+        match byte[0] {
+            b'\n' => break,
+            b => line.push(b),
+        }
+        if line.len() > MAX_CHUNK_LINE {
+            return Err(ParseError::InvalidChunkSize(String::from_utf8_lossy(&line).into()).into());
+        }
+    }
+
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    String::from_utf8(line).map_err(|_| ParseError::InvalidEncoding.into())
+}
+
+fn body_read_error(e: io::Error) -> ServerError {
+    match e.kind() {
+        io::ErrorKind::UnexpectedEof => ParseError::IncompleteBody.into(),
+        _ => e.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Write,
+        net::{TcpListener, TcpStream},
+    };
+
+    use super::*;
+    use crate::testing::Duplex;
+
+    /// A client whose request arrives in separate pieces, one per read, as
+    /// it might over several TCP segments
+    struct Segments(Vec<&'static [u8]>);
+
+    impl Read for Segments {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() {
+                return Ok(0);
+            }
+            let segment = self.0.remove(0);
+            let len = segment.len().min(buf.len());
+            buf[..len].copy_from_slice(&segment[..len]);
+            if len < segment.len() {
+                self.0.insert(0, &segment[len..]);
+            }
+            Ok(len)
+        }
+    }
+
+    impl Write for Segments {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Connection for Segments {}
+
+    fn head(request: &str) -> Result<Request, ParseError> {
+        parse_request_head(request.as_bytes(), DEFAULT_MAX_HEADERS)
+    }
+
+    #[test]
+    fn a_valid_request_id_is_kept() {
+        let request = head("GET / HTTP/1.1\r\nHost: a\r\nX-Request-Id: ab-12_3.4\r\n\r\n").unwrap();
+        assert_eq!(request.request_id, "ab-12_3.4");
+    }
+
+    #[test]
+    fn an_unsafe_request_id_is_replaced() {
+        let long = "a".repeat(MAX_REQUEST_ID_LEN + 1);
+        for id in ["abc\rInjected: yes", "has space", "", long.as_str()] {
+            let request = head(&format!(
+                "GET / HTTP/1.1\r\nHost: a\r\nX-Request-Id: {}\r\n\r\n",
+                id
+            ))
+            .unwrap();
+            assert_ne!(request.request_id, id);
+            assert!(is_valid_request_id(&request.request_id));
+        }
+        assert!(is_valid_request_id(&"a".repeat(MAX_REQUEST_ID_LEN)));
+    }
+
+    #[test]
+    fn parses_a_valid_request_line() {
+        let line = parse_request_line(b"GET /index.html HTTP/1.1\r\nHost: a\r\n").unwrap();
+        assert_eq!(
+            line,
+            RequestLine {
+                method: Method::Get,
+                path: "/index.html".to_string(),
+                version: "HTTP/1.1".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn keeps_the_query_string_in_the_path() {
+        let line = parse_request_line(b"GET /search?q=rust  HTTP/1.0\r\n").unwrap();
+        assert_eq!(line.path, "/search?q=rust");
+        assert_eq!(line.version, "HTTP/1.0");
+    }
+
+    #[test]
+    fn rejects_malformed_request_lines() {
+        assert_eq!(parse_request_line(b"\r\n"), Err(ParseError::Empty));
+        assert_eq!(
+            parse_request_line(b"GET\r\n"),
+            Err(ParseError::MalformedRequestLine("GET".to_string()))
+        );
+        assert_eq!(
+            parse_request_line(b"GET / FTP/1.1\r\n"),
+            Err(ParseError::InvalidVersion("FTP/1.1".to_string()))
+        );
+    }
+
+    #[test]
+    fn rejects_targets_that_are_not_paths() {
+        assert_eq!(
+            parse_request_line(b"GET index.html HTTP/1.1\r\n"),
+            Err(ParseError::InvalidTarget("index.html".to_string()))
+        );
+        assert_eq!(
+            parse_request_line(b"GET * HTTP/1.1\r\n"),
+            Err(ParseError::InvalidTarget("*".to_string()))
+        );
+        assert_eq!(
+            parse_request_line(b"OPTIONS * HTTP/1.1\r\n").unwrap().path,
+            "*"
+        );
+    }
+
+    #[test]
+    fn rejects_versions_other_than_1_0_and_1_1() {
+        for version in ["HTTP/2.0", "HTTP/0.9", "HTTP/1.2"] {
+            assert_eq!(
+                parse_request_line(format!("GET / {}\r\n", version).as_bytes()),
+                Err(ParseError::UnsupportedVersion(version.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn parses_a_complete_request() {
+        let mut stream = Duplex::new(
+            "POST /submit?name=a%20b&page=2 HTTP/1.1\r\n\
+             Host: example.com\r\n\
+             Cookie: session=xyz\r\n\
+             X-Request-Id: req-1\r\n\
+             Content-Length: 5\r\n\
+             \r\n\
+             helloGET /next HTTP/1.1\r\n",
+        );
+        let peer = "127.0.0.1:4000".parse().unwrap();
+        let (request, rest) = Request::parse(&mut stream, Some(peer), &Limits::default()).unwrap();
+
+        assert_eq!(request.request_id, "req-1");
+        assert_eq!(request.method, Method::Post);
+        assert_eq!(request.target, "/submit?name=a%20b&page=2");
+        assert_eq!(request.path, "/submit");
+        assert_eq!(request.query["name"], "a b");
+        assert_eq!(request.query["page"], "2");
+        assert_eq!(request.version, "HTTP/1.1");
+        assert_eq!(request.headers.get("host"), Some("example.com"));
+        assert_eq!(request.headers.len(), 4);
+        assert_eq!(request.cookies["session"], "xyz");
+        assert!(request.params.is_empty());
+        assert_eq!(request.body, b"hello");
+        assert_eq!(request.peer_addr, Some(peer));
+        assert_eq!(rest, b"GET /next HTTP/1.1\r\n");
+        assert!(stream.output.is_empty());
+    }
+
+    #[test]
+    fn a_client_that_goes_quiet_mid_head_times_out_at_the_deadline() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+        client.write_all(b"GET / HTTP/1.1\r\nHost: a\r\n").unwrap();
+
+        let limits = Limits {
+            header_timeout: Some(Duration::from_millis(200)),
+            read_timeout: Some(Duration::from_secs(30)),
+            ..Limits::default()
+        };
+        let started = Instant::now();
+        let result = read_request(&mut server, &limits);
+        assert!(matches!(result, Err(ServerError::Timeout)));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn parses_each_supported_method() {
+        let methods = [
+            ("GET", Method::Get),
+            ("POST", Method::Post),
+            ("PUT", Method::Put),
+            ("DELETE", Method::Delete),
+            ("HEAD", Method::Head),
+            ("OPTIONS", Method::Options),
+            ("PATCH", Method::Patch),
+        ];
+        for (token, method) in methods {
+            assert_eq!(token.parse(), Ok(method));
+            assert_eq!(Method::try_from(token), Ok(method));
+            assert_eq!(method.as_str(), token);
+            let line = format!("{} / HTTP/1.1\r\n", token);
+            assert_eq!(parse_request_line(line.as_bytes()).unwrap().method, method);
+        }
+    }
+
+    #[test]
+    fn unknown_methods_are_a_parse_error() {
+        for token in ["BREW", "get", "CONNECT"] {
+            assert_eq!(
+                token.parse::<Method>(),
+                Err(ParseError::UnknownMethod(token.to_string()))
+            );
+        }
+        assert_eq!(
+            parse_request_line(b"BREW /pot HTTP/1.1\r\n"),
+            Err(ParseError::UnknownMethod("BREW".to_string()))
+        );
+    }
+
+    #[test]
+    fn a_head_split_across_reads_is_read_whole() {
+        let mut reader = Segments(vec![
+            b"GET /index.html HTTP/1.1\r\nHo",
+            b"st: example.com\r\n\r",
+            b"\n",
+        ]);
+        let request = read_request(&mut reader, &Limits::default()).unwrap();
+        assert_eq!(request.path, "/index.html");
+        assert_eq!(request.headers.get("Host"), Some("example.com"));
+    }
+
+    #[test]
+    fn a_head_longer_than_one_read_chunk_is_read_whole() {
+        let long = "a".repeat(READ_CHUNK_SIZE * 3);
+        let head = format!("GET / HTTP/1.1\r\nHost: a\r\nX-Long: {}\r\n\r\n", long);
+        let mut reader = Duplex::new(&head);
+        let request = read_request(&mut reader, &Limits::default()).unwrap();
+        assert_eq!(request.headers.get("X-Long"), Some(long.as_str()));
+    }
+
+    #[test]
+    fn a_head_past_the_size_limit_is_rejected() {
+        let head = format!(
+            "GET / HTTP/1.1\r\nHost: a\r\nX-Long: {}\r\n\r\n",
+            "a".repeat(DEFAULT_MAX_HEADER_SIZE)
+        );
+        let result = read_request(&mut Duplex::new(&head), &Limits::default());
+        assert!(matches!(
+            result,
+            Err(ServerError::Parse(ParseError::HeadersTooLarge))
+        ));
+    }
+
+    #[test]
+    fn a_connection_closed_before_the_head_ends() {
+        let result = read_request(&mut Duplex::new(""), &Limits::default());
+        assert!(matches!(result, Err(ServerError::ConnectionClosed)));
+        let result = read_request(
+            &mut Duplex::new("GET / HTTP/1.1\r\nHost"),
+            &Limits::default(),
+        );
+        assert!(matches!(
+            result,
+            Err(ServerError::Parse(ParseError::IncompleteHeaders))
+        ));
+    }
+
+    fn limits(max_body_size: usize) -> Limits {
+        Limits {
+            max_body_size,
+            ..Limits::default()
+        }
+    }
+
+    #[test]
+    fn reads_a_body_of_exactly_content_length() {
+        let mut stream =
+            Duplex::new("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 11\r\n\r\nhello world");
+        let request = read_request(&mut stream, &limits(11)).unwrap();
+        assert_eq!(request.body, b"hello world");
+
+        let mut stream = Segments(vec![
+            b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 6\r\n\r\nab",
+            b"cd",
+            b"ef",
+        ]);
+        assert_eq!(
+            read_request(&mut stream, &limits(6)).unwrap().body,
+            b"abcdef"
+        );
+    }
+
+    #[test]
+    fn a_request_without_content_length_has_no_body() {
+        let mut stream = Duplex::new("GET / HTTP/1.1\r\nHost: a\r\n\r\n");
+        assert!(read_request(&mut stream, &limits(0))
+            .unwrap()
+            .body
+            .is_empty());
+    }
+
+    #[test]
+    fn a_body_over_the_limit_is_rejected_unread() {
+        let mut stream = Duplex::new("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 12\r\n\r\n");
+        let result = read_request(&mut stream, &limits(11));
+        assert!(matches!(
+            result,
+            Err(ServerError::Parse(ParseError::BodyTooLarge))
+        ));
+    }
+
+    #[test]
+    fn a_truncated_body_is_an_error() {
+        let mut stream =
+            Duplex::new("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 10\r\n\r\nshort");
+        let result = read_request(&mut stream, &limits(100));
+        assert!(matches!(
+            result,
+            Err(ServerError::Parse(ParseError::IncompleteBody))
+        ));
+    }
+
+    #[test]
+    fn an_invalid_content_length_is_an_error() {
+        for length in ["-1", "ten", "1.5"] {
+            let request = format!(
+                "POST / HTTP/1.1\r\nHost: a\r\nContent-Length: {}\r\n\r\n",
+                length
+            );
+            let result = read_request(&mut Duplex::new(&request), &limits(100));
+            match result {
+                Err(ServerError::Parse(ParseError::InvalidContentLength(value))) => {
+                    assert_eq!(value, length)
+                }
+                other => panic!("unexpected result {:?}", other),
+            }
+        }
+    }
+
+    fn chunked(body: &str) -> Duplex {
+        Duplex::new(&format!(
+            "POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n{}",
+            body
+        ))
+    }
+
+    #[test]
+    fn reads_a_two_chunk_body() {
+        let mut stream = chunked("5\r\nhello\r\n6;ext=1\r\n world\r\n0\r\nTrailer: x\r\n\r\nGET /");
+        let (request, rest) = read_request_with_continue(&mut stream, &limits(100)).unwrap();
+        assert_eq!(request.body, b"hello world");
+        assert_eq!(rest, b"GET /");
+    }
+
+    #[test]
+    fn a_chunked_body_over_the_limit_is_rejected() {
+        let mut stream = chunked("6\r\nhello \r\n5\r\nworld\r\n0\r\n\r\n");
+        let result = read_request(&mut stream, &limits(10));
+        assert!(matches!(
+            result,
+            Err(ServerError::Parse(ParseError::BodyTooLarge))
+        ));
+    }
+
+    #[test]
+    fn a_malformed_chunk_size_is_rejected() {
+        for body in [
+            "zz\r\nhello\r\n0\r\n\r\n",
+            "\r\n",
+            "5\r\nhelloX\r\n0\r\n\r\n",
+        ] {
+            let result = read_request(&mut chunked(body), &limits(100));
+            assert!(
+                matches!(
+                    result,
+                    Err(ServerError::Parse(ParseError::InvalidChunkSize(_)))
+                ),
+                "{:?}: {:?}",
+                body,
+                result
+            );
+        }
+    }
+
+    #[test]
+    fn a_transfer_encoding_not_ending_in_chunked_is_rejected() {
+        for codings in [
+            "gzip",
+            "chunked, gzip",
+            "chunked\r\nTransfer-Encoding: gzip",
+        ] {
+            let mut stream = Duplex::new(&format!(
+                "POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: {}\r\n\
+                 Content-Length: 5\r\n\r\nhello",
+                codings
+            ));
+            let result = read_request(&mut stream, &limits(100));
+            assert!(
+                matches!(
+                    result,
+                    Err(ServerError::Parse(ParseError::InvalidTransferEncoding(_)))
+                ),
+                "{:?}: {:?}",
+                codings,
+                result
+            );
+        }
+    }
+
+    #[test]
+    fn both_lengths_are_conflicting_framing() {
+        let both = head(
+            "POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\
+             Content-Length: 5\r\n\r\n",
+        )
+        .unwrap();
+        assert!(both.has_conflicting_framing());
+        let chunked = head("POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n");
+        assert!(!chunked.unwrap().has_conflicting_framing());
+    }
+
+    #[test]
+    fn a_chunked_body_cut_short_is_incomplete() {
+        let result = read_request(&mut chunked("5\r\nhel"), &limits(100));
+        assert!(matches!(
+            result,
+            Err(ServerError::Parse(ParseError::IncompleteBody))
+        ));
+    }
+
+    /// A client that sends its head, then holds its body back until it has
+    /// been told to continue, as `Expect: 100-continue` clients do
+    struct Expecting {
+        head: Vec<u8>,
+        body: &'static [u8],
+        output: Vec<u8>,
+    }
+
+    impl Read for Expecting {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if !self.head.is_empty() {
+                let head = std::mem::take(&mut self.head);
+                buf[..head.len()].copy_from_slice(&head);
+                return Ok(head.len());
+            }
+            if self.output.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "still waiting"));
+            }
+            let body = std::mem::take(&mut self.body);
+            buf[..body.len()].copy_from_slice(body);
+            Ok(body.len())
+        }
+    }
+
+    impl Write for Expecting {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Connection for Expecting {}
+
+    fn expecting(version: &str, length: usize) -> Expecting {
+        let head = format!(
+            "POST / {}\r\nHost: a\r\nExpect: 100-continue\r\nContent-Length: {}\r\n\r\n",
+            version, length
+        );
+        Expecting {
+            head: head.into_bytes(),
+            body: b"hello",
+            output: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn continue_is_sent_before_the_body_is_read() {
+        let mut stream = expecting("HTTP/1.1", 5);
+        let (request, _) = read_request_with_continue(&mut stream, &limits(100)).unwrap();
+        assert_eq!(request.body, b"hello");
+        assert_eq!(stream.output, CONTINUE_RESPONSE);
+    }
+
+    #[test]
+    fn a_body_too_large_is_refused_without_a_continue() {
+        let mut stream = expecting("HTTP/1.1", 500);
+        let result = read_request_with_continue(&mut stream, &limits(100));
+        assert!(matches!(
+            result,
+            Err(ServerError::Parse(ParseError::BodyTooLarge))
+        ));
+        assert!(stream.output.is_empty());
+    }
+
+    #[test]
+    fn http_1_0_clients_are_not_sent_a_continue() {
+        let mut stream = expecting("HTTP/1.0", 5);
+        assert!(read_request_with_continue(&mut stream, &limits(100)).is_err());
+        assert!(stream.output.is_empty());
+    }
+
+    #[test]
+    fn only_http_1_0_requests_get_a_1_0_response() {
+        assert_eq!(Version::for_request("HTTP/1.0"), Version::Http10);
+        assert_eq!(Version::for_request("HTTP/1.1"), Version::Http11);
+        assert_eq!(Version::for_request("HTTP/1.0").as_str(), "HTTP/1.0");
+    }
+
+    #[test]
+    fn a_dump_redacts_credentials_and_truncates_the_body() {
+        let mut request = head(
+            "POST /login HTTP/1.1\r\nHost: a\r\nauthorization: Basic YTpi\r\n\
+             Cookie: session=abc\r\nProxy-Authorization: Basic YTpi\r\n\r\n",
+        )
+        .unwrap();
+        request.body = b"user=a&password=b".to_vec();
+
+        let dump = request.dump(6);
+        assert!(!dump.contains("YTpi"), "{}", dump);
+        assert!(!dump.contains("session=abc"), "{}", dump);
+        assert!(dump.starts_with("POST /login HTTP/1.1\n"), "{}", dump);
+        assert!(dump.contains("Host: a\n"), "{}", dump);
+        assert!(dump.contains("authorization: [redacted]\n"), "{}", dump);
+        assert!(dump.contains("Cookie: [redacted]\n"), "{}", dump);
+        assert!(
+            dump.contains("Proxy-Authorization: [redacted]\n"),
+            "{}",
+            dump
+        );
+        assert!(dump.ends_with("\nuser=a... (11 more bytes)"), "{}", dump);
+    }
+}
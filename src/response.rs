@@ -0,0 +1,381 @@
+use std::{
+    convert::TryFrom,
+    fmt,
+    io::{self, Read, Write},
+    time::SystemTime,
+};
+
+use crate::{
+    compression::maybe_compress,
+    cookie::{format_set_cookie, CookieOptions},
+    date::format_http_date,
+    headers::Headers,
+    json::ToJson,
+    request::Version,
+    status::StatusCode,
+};
+
+/// A response to be written back to the client.
+///
+/// `Content-Length` is always derived from the body when writing, so it
+/// does not need to be set by hand. Neither does `Date`, which is the time
+/// of writing unless a handler set its own. Statuses that can't have a
+/// body, such as 204 and 304, are written with no body or `Content-Length`.
+#[derive(Debug)]
+pub struct Response {
+    /// Version for the status line. Defaults to HTTP/1.1; set it to match an
+    /// older client's request.
+    pub version: Version,
+    pub status: StatusCode,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+    /// Length of a body not held in `body`: one streamed from `stream`, or
+    /// one never loaded for a response to HEAD. Ignored if there is a body.
+    pub content_length: Option<u64>,
+    /// Source of a body too large to hold in memory, copied to the client
+    /// a chunk at a time
+    pub stream: Option<BodyStream>,
+}
+
+/// Size of the chunks a streamed body is copied in
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+/// A body read as it is written
+pub struct BodyStream(Box<dyn Read + Send>);
+
+impl fmt::Debug for BodyStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("BodyStream")
+    }
+}
+
+impl Response {
+    /// A response with no headers and an empty body
+    pub fn new(status: StatusCode) -> Response {
+        Response {
+            version: Version::Http11,
+            status,
+            headers: Headers::new(),
+            body: Vec::new(),
+            content_length: None,
+            stream: None,
+        }
+    }
+
+    /// A `200` with `value` serialized as its JSON body
+    pub fn json(value: &impl ToJson) -> Response {
+        Response::new(StatusCode::OK)
+            .header("Content-Type", "application/json; charset=utf-8")
+            .body(value.to_json().to_string())
+    }
+
+    /// A redirect to `location`, with an empty body.
+    ///
+    /// Panics if `status` is not one of the redirect codes: 301, 302, 303,
+    /// 307 or 308.
+    pub fn redirect(location: &str, status: StatusCode) -> Response {
+        assert!(
+            matches!(status.as_u16(), 301 | 302 | 303 | 307 | 308),
+            "{} is not a redirect status",
+            status
+        );
+        Response::new(status).header("Location", location)
+    }
+
+    /// Add a header, keeping any existing values for the same name
+    pub fn header(mut self, name: &str, value: &str) -> Response {
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// Set the body, replacing any streamed or advertised one
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Response {
+        self.body = body.into();
+        self.content_length = None;
+        self.stream = None;
+        self
+    }
+
+    /// Stream the body from `reader`, which must yield exactly `len` bytes,
+    /// rather than holding it in memory
+    pub fn stream(mut self, reader: impl Read + Send + 'static, len: u64) -> Response {
+        self.body = Vec::new();
+        self.content_length = Some(len);
+        self.stream = Some(BodyStream(Box::new(reader)));
+        self
+    }
+
+    /// Add each of `defaults` unless the response already has a header of
+    /// that name
+    pub fn add_default_headers(&mut self, defaults: &[(String, String)]) {
+        for (name, value) in defaults {
+            if !self.headers.contains(name) {
+                self.headers.insert(name, value);
+            }
+        }
+    }
+
+    /// The length of the body, or of the one left unloaded
+    pub fn content_len(&self) -> u64 {
+        match self.content_length {
+            Some(length) if self.body.is_empty() => length,
+            _ => self.body.len() as u64,
+        }
+    }
+
+    /// Note in `Vary` that the response depends on the request header
+    /// `name`, unless it already says so
+    pub fn vary(&mut self, name: &str) {
+        let listed = self
+            .headers
+            .get_all("Vary")
+            .iter()
+            .flat_map(|value| value.split(','))
+            .any(|listed| listed.trim().eq_ignore_ascii_case(name));
+        if !listed {
+            self.headers.insert("Vary", name);
+        }
+    }
+
+    /// Add a `Set-Cookie` header. Each call adds its own header, so several
+    /// cookies can be set on one response.
+    pub fn set_cookie(&mut self, name: &str, value: &str, options: CookieOptions) {
+        self.headers
+            .insert("Set-Cookie", &format_set_cookie(name, value, &options));
+    }
+
+    /// Gzip the body if the client accepts it and the body is at least
+    /// `min_size` bytes. Bodies that already have a `Content-Encoding` are
+    /// left alone, as are partial ones since their `Content-Range` counts
+    /// uncompressed bytes.
+    pub fn compress(mut self, accept_encoding: Option<&str>, min_size: usize) -> Response {
+        if self.body.len() < min_size
+            || self.headers.contains("Content-Encoding")
+            || self.status == StatusCode::PARTIAL_CONTENT
+        {
+            return self;
+        }
+
+        let (body, encoding) = maybe_compress(accept_encoding, self.body);
+        self.body = body;
+        // Caches must not hand a compressed body to clients that can't take it
+        self.vary("Accept-Encoding");
+        if let Some(encoding) = encoding {
+            self.headers.set("Content-Encoding", encoding);
+        }
+        self
+    }
+
+    /// Write the status line, headers and body, returning how many bytes
+    /// that took, headers included. A streamed body is used up.
+    pub fn write_to(&mut self, w: &mut impl Write) -> io::Result<usize> {
+        let head_len = self.write_head_to(w)?;
+        let body_len = match self.stream.take() {
+            _ if !self.status.allows_body() => 0,
+            Some(BodyStream(reader)) => copy_stream(reader, self.content_len(), w)?,
+            None => {
+                w.write_all(&self.body)?;
+                self.body.len() as u64
+            }
+        };
+        Ok(head_len + body_len as usize)
+    }
+
+    /// Write the status line and headers only, as for a HEAD request.
+    /// `Content-Length` still reflects the body that was left out. Returns
+    /// how many bytes were written.
+    pub fn write_head_to(&self, w: &mut impl Write) -> io::Result<usize> {
+        let mut head = format!("{} {}\r\n", self.version, self.status);
+        for (name, value) in self.headers.iter() {
+            if !name.eq_ignore_ascii_case("Content-Length") {
+                head.push_str(&format!("{}: {}\r\n", name, value));
+            }
+        }
+        if !self.headers.contains("Date") {
+            head.push_str(&format!(
+                "Date: {}\r\n",
+                format_http_date(SystemTime::now())
+            ));
+        }
+        if self.status.allows_body() {
+            head.push_str(&format!("Content-Length: {}\r\n", self.content_len()));
+        }
+        head.push_str("\r\n");
+
+        w.write_all(head.as_bytes())?;
+        Ok(head.len())
+    }
+}
+
+/// Copy `len` bytes from `reader` to `w` a chunk at a time. Running out
+/// early is an error, as the client was promised `len` bytes.
+fn copy_stream(mut reader: impl Read, len: u64, w: &mut impl Write) -> io::Result<u64> {
+    let mut chunk = vec![0; STREAM_CHUNK_SIZE];
+    let mut remaining = len;
+    while remaining > 0 {
+        let wanted = chunk
+            .len()
+            .min(usize::try_from(remaining).unwrap_or(usize::MAX));
+        let read = match reader.read(&mut chunk[..wanted]) {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "streamed body ended before its Content-Length",
+                ))
+            }
+            Ok(read) => read,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        w.write_all(&chunk[..read])?;
+        remaining -= read as u64;
+    }
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::date::parse_http_date;
+
+    const DATE: &str = "Sun, 06 Nov 1994 08:49:37 GMT";
+
+    fn written(mut response: Response) -> String {
+        let mut out = Vec::new();
+        let len = response.write_to(&mut out).unwrap();
+        assert_eq!(len, out.len());
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn writes_a_200_with_a_body() {
+        let response = Response::new(StatusCode::OK)
+            .header("Date", DATE)
+            .header("Content-Type", "text/plain")
+            .body("hello");
+        assert_eq!(
+            written(response),
+            format!(
+                "HTTP/1.1 200 OK\r\nDate: {}\r\nContent-Type: text/plain\r\n\
+                 Content-Length: 5\r\n\r\nhello",
+                DATE
+            )
+        );
+    }
+
+    #[test]
+    fn writes_a_404_with_no_body() {
+        let response = Response::new(StatusCode::NOT_FOUND).header("Date", DATE);
+        assert_eq!(
+            written(response),
+            format!(
+                "HTTP/1.1 404 Not Found\r\nDate: {}\r\nContent-Length: 0\r\n\r\n",
+                DATE
+            )
+        );
+    }
+
+    #[test]
+    fn bodiless_statuses_have_no_content_length() {
+        for status in [StatusCode::NO_CONTENT, StatusCode::NOT_MODIFIED] {
+            let response = Response::new(status)
+                .header("Date", DATE)
+                .header("Content-Length", "12")
+                .body("not sent");
+            assert_eq!(
+                written(response),
+                format!("HTTP/1.1 {}\r\nDate: {}\r\n\r\n", status, DATE)
+            );
+        }
+    }
+
+    #[test]
+    fn each_cookie_gets_its_own_header() {
+        let mut response = Response::new(StatusCode::OK).header("Date", DATE);
+        response.set_cookie("a", "1", CookieOptions::default());
+        let options = CookieOptions {
+            http_only: true,
+            ..CookieOptions::default()
+        };
+        response.set_cookie("b", "2", options);
+        assert_eq!(
+            written(response),
+            format!(
+                "HTTP/1.1 200 OK\r\nDate: {}\r\nSet-Cookie: a=1\r\n\
+                 Set-Cookie: b=2; HttpOnly\r\nContent-Length: 0\r\n\r\n",
+                DATE
+            )
+        );
+    }
+
+    #[test]
+    fn a_multibyte_body_is_counted_in_bytes() {
+        let body = "héllo, wörld ✓";
+        assert_eq!(body.chars().count(), 14);
+        let mut response = Response::new(StatusCode::OK)
+            .header("Date", DATE)
+            .body(body);
+        let head = format!(
+            "HTTP/1.1 200 OK\r\nDate: {}\r\nContent-Length: 18\r\n\r\n",
+            DATE
+        );
+
+        let mut out = Vec::new();
+        assert_eq!(response.write_to(&mut out).unwrap(), head.len() + 18);
+        assert_eq!(out, format!("{}{}", head, body).as_bytes());
+    }
+
+    #[test]
+    fn default_headers_do_not_replace_the_handlers() {
+        let defaults = [
+            ("X-Content-Type-Options".to_string(), "nosniff".to_string()),
+            ("X-Frame-Options".to_string(), "DENY".to_string()),
+        ];
+        let mut response = Response::new(StatusCode::OK).header("x-frame-options", "SAMEORIGIN");
+        response.add_default_headers(&defaults);
+        assert_eq!(
+            response.headers.get("X-Content-Type-Options"),
+            Some("nosniff")
+        );
+        assert_eq!(response.headers.get_all("X-Frame-Options"), ["SAMEORIGIN"]);
+    }
+
+    #[test]
+    fn a_response_without_a_date_is_given_the_current_one() {
+        let before = SystemTime::now();
+        let written = written(Response::new(StatusCode::OK));
+        let date = written
+            .lines()
+            .find_map(|line| line.strip_prefix("Date: "))
+            .unwrap();
+        let date = parse_http_date(date).unwrap();
+        // The format only has whole seconds
+        assert!(date + Duration::from_secs(1) >= before, "{:?}", date);
+        assert!(date <= SystemTime::now());
+    }
+
+    #[test]
+    fn a_handlers_date_is_kept() {
+        let written = written(Response::new(StatusCode::OK).header("Date", DATE));
+        assert_eq!(written.matches("Date: ").count(), 1);
+        assert!(written.contains(&format!("\r\nDate: {}\r\n", DATE)));
+    }
+
+    #[test]
+    fn a_redirect_has_its_status_and_location() {
+        for code in [301, 302, 303, 307, 308] {
+            let response = Response::redirect("/new?page=2", StatusCode(code));
+            assert_eq!(response.status, StatusCode(code));
+            assert_eq!(response.headers.get("Location"), Some("/new?page=2"));
+            assert!(response.body.is_empty());
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "is not a redirect status")]
+    fn a_redirect_needs_a_redirect_status() {
+        Response::redirect("/new", StatusCode::OK);
+    }
+}
@@ -0,0 +1,337 @@
+use std::collections::HashMap;
+
+use crate::{
+    request::{Method, Request},
+    response::Response,
+    status::StatusCode,
+};
+
+pub type Handler = Box<dyn Fn(&Request) -> Response + Send + Sync>;
+
+struct Route {
+    method: Method,
+    pattern: Pattern,
+    handler: Handler,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Static(String),
+    /// A `:name` segment, matching any single path segment
+    Param(String),
+}
+
+/// A compiled route path such as `/users/:id`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern {
+    segments: Vec<Segment>,
+}
+
+impl Pattern {
+    pub fn compile(path: &str) -> Pattern {
+        let segments = split_path(path)
+            .map(|segment| match segment.strip_prefix(':') {
+                Some(name) if !name.is_empty() => Segment::Param(name.to_string()),
+                _ => Segment::Static(segment.to_string()),
+            })
+            .collect();
+
+        Pattern { segments }
+    }
+
+    /// The captured parameters if `path` matches the pattern
+    pub fn matches(&self, path: &str) -> Option<HashMap<String, String>> {
+        let mut params = HashMap::new();
+        let mut parts = split_path(path);
+
+        for segment in &self.segments {
+            let part = parts.next()?;
+            match segment {
+                Segment::Static(expected) if expected == part => {}
+                Segment::Static(_) => return None,
+                Segment::Param(name) => {
+                    params.insert(name.clone(), part.to_string());
+                }
+            }
+        }
+
+        match parts.next() {
+            Some(_) => None,
+            None => Some(params),
+        }
+    }
+
+    /// Sort key where smaller is more specific: static segments beat
+    /// parameters, compared from the left
+    fn specificity(&self) -> Vec<u8> {
+        self.segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Static(_) => 0,
+                Segment::Param(_) => 1,
+            })
+            .collect()
+    }
+}
+
+/// Path segments, ignoring empty ones so `/a//b/` is the same as `/a/b`
+fn split_path(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|segment| !segment.is_empty())
+}
+
+/// Maps a method and path to the handler that serves it.
+///
+/// Paths may contain `:name` segments, whose values are handed to the
+/// handler in `Request::params`. When several routes match, the one with
+/// static segments furthest to the left wins.
+///
+/// A HEAD request is served by the GET handler when no HEAD handler is
+/// registered, and an OPTIONS request without a handler gets a `204`
+/// listing the path's methods in `Allow`, as does `OPTIONS *` for the whole
+/// server. Requests for a known path with a method it has no handler for
+/// get a `405` listing the methods it does have.
+pub struct Router {
+    routes: Vec<Route>,
+    fallback: Handler,
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Router::new()
+    }
+}
+
+impl Router {
+    /// An empty router, answering everything with a bare 404
+    pub fn new() -> Router {
+        Router {
+            routes: Vec::new(),
+            fallback: Box::new(|_| Response::new(StatusCode::NOT_FOUND)),
+        }
+    }
+
+    pub fn add_route(&mut self, method: Method, path: &str, handler: Handler) {
+        self.routes.push(Route {
+            method,
+            pattern: Pattern::compile(path),
+            handler,
+        });
+    }
+
+    /// Set the handler for requests that match no route
+    pub fn set_fallback(&mut self, handler: Handler) {
+        self.fallback = handler;
+    }
+
+    pub fn dispatch(&self, req: &Request) -> Response {
+        if req.method == Method::Options && req.target == "*" {
+            let routes: Vec<&Route> = self.routes.iter().collect();
+            return Response::new(StatusCode::NO_CONTENT).header("Allow", &allow_header(&routes));
+        }
+
+        let path = req.path.as_str();
+        let mut matching: Vec<(&Route, HashMap<String, String>)> = self
+            .routes
+            .iter()
+            .filter_map(|route| route.pattern.matches(path).map(|params| (route, params)))
+            .collect();
+        if matching.is_empty() {
+            return (self.fallback)(req);
+        }
+        matching.sort_by_key(|(route, _)| route.pattern.specificity());
+
+        let found = matching
+            .iter()
+            .find(|(route, _)| route.method == req.method)
+            .or_else(|| match req.method {
+                Method::Head => matching
+                    .iter()
+                    .find(|(route, _)| route.method == Method::Get),
+                _ => None,
+            });
+
+        match found {
+            Some((route, params)) if params.is_empty() => (route.handler)(req),
+            Some((route, params)) => {
+                let mut req = req.clone();
+                req.params = params.clone();
+                (route.handler)(&req)
+            }
+            None => {
+                let routes: Vec<&Route> = matching.iter().map(|(route, _)| *route).collect();
+                let status = match req.method {
+                    Method::Options => StatusCode::NO_CONTENT,
+                    _ => StatusCode::METHOD_NOT_ALLOWED,
+                };
+                Response::new(status).header("Allow", &allow_header(&routes))
+            }
+        }
+    }
+}
+
+/// The methods `routes` have handlers for, counting the HEAD and OPTIONS
+/// requests answered on their behalf
+fn allow_header(routes: &[&Route]) -> String {
+    let mut methods: Vec<Method> = Vec::new();
+    for route in routes {
+        if !methods.contains(&route.method) {
+            methods.push(route.method);
+        }
+        if route.method == Method::Get && !methods.contains(&Method::Head) {
+            methods.push(Method::Head);
+        }
+    }
+    if !methods.contains(&Method::Options) {
+        methods.push(Method::Options);
+    }
+
+    methods
+        .iter()
+        .map(Method::as_str)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::parse_request_head;
+
+    fn request(method: &str, target: &str) -> Request {
+        let head = format!("{} {} HTTP/1.1\r\nHost: a\r\n\r\n", method, target);
+        parse_request_head(head.as_bytes(), 100).unwrap()
+    }
+
+    fn text(body: &'static str) -> Handler {
+        Box::new(move |_| Response::new(StatusCode::OK).body(body))
+    }
+
+    #[test]
+    fn a_registered_route_is_invoked() {
+        let mut router = Router::new();
+        router.add_route(Method::Get, "/", text("home"));
+        router.add_route(Method::Get, "/about", text("about"));
+        router.add_route(Method::Post, "/about", text("posted"));
+
+        let response = router.dispatch(&request("GET", "/about"));
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.body, b"about");
+        assert_eq!(router.dispatch(&request("POST", "/about")).body, b"posted");
+        assert_eq!(router.dispatch(&request("GET", "/?q=1")).body, b"home");
+    }
+
+    #[test]
+    fn an_unregistered_path_is_a_404() {
+        let mut router = Router::new();
+        router.add_route(Method::Get, "/", text("home"));
+        let response = router.dispatch(&request("GET", "/missing"));
+        assert_eq!(response.status, StatusCode::NOT_FOUND);
+        assert!(response.body.is_empty());
+    }
+
+    #[test]
+    fn the_fallback_answers_unmatched_paths() {
+        let mut router = Router::new();
+        router.add_route(Method::Get, "/", text("home"));
+        router.set_fallback(text("fallback"));
+        assert_eq!(
+            router.dispatch(&request("GET", "/missing")).body,
+            b"fallback"
+        );
+        assert_eq!(router.dispatch(&request("GET", "/")).body, b"home");
+    }
+
+    fn params(pattern: &str, path: &str) -> Option<Vec<(String, String)>> {
+        let mut params: Vec<_> = Pattern::compile(pattern)
+            .matches(path)?
+            .into_iter()
+            .collect();
+        params.sort();
+        Some(params)
+    }
+
+    #[test]
+    fn a_single_param_is_captured() {
+        assert_eq!(
+            params("/users/:id", "/users/42"),
+            Some(vec![("id".to_string(), "42".to_string())])
+        );
+        assert_eq!(params("/users/:id", "/posts/42"), None);
+
+        let mut router = Router::new();
+        router.add_route(
+            Method::Get,
+            "/users/:id",
+            Box::new(|req| Response::new(StatusCode::OK).body(req.params["id"].clone())),
+        );
+        assert_eq!(router.dispatch(&request("GET", "/users/7")).body, b"7");
+    }
+
+    #[test]
+    fn several_params_are_captured() {
+        assert_eq!(
+            params("/users/:user/posts/:post", "/users/ann/posts/3/"),
+            Some(vec![
+                ("post".to_string(), "3".to_string()),
+                ("user".to_string(), "ann".to_string())
+            ])
+        );
+    }
+
+    #[test]
+    fn a_path_of_another_length_does_not_match() {
+        assert_eq!(params("/users/:id", "/users"), None);
+        assert_eq!(params("/users/:id", "/users/1/posts"), None);
+        assert_eq!(params("/", "/users"), None);
+        assert_eq!(params("/", "/"), Some(Vec::new()));
+    }
+
+    #[test]
+    fn static_segments_win_over_params() {
+        let mut router = Router::new();
+        router.add_route(Method::Get, "/users/:id", text("user"));
+        router.add_route(Method::Get, "/users/me", text("me"));
+        router.add_route(Method::Get, "/:section/me", text("section"));
+        assert_eq!(router.dispatch(&request("GET", "/users/me")).body, b"me");
+        assert_eq!(router.dispatch(&request("GET", "/users/5")).body, b"user");
+        assert_eq!(
+            router.dispatch(&request("GET", "/posts/me")).body,
+            b"section"
+        );
+    }
+
+    #[test]
+    fn options_on_a_get_route_lists_its_methods() {
+        let mut router = Router::new();
+        router.add_route(Method::Get, "/users/:id", text("user"));
+        let response = router.dispatch(&request("OPTIONS", "/users/7"));
+        assert_eq!(response.status, StatusCode::NO_CONTENT);
+        assert_eq!(response.headers.get("Allow"), Some("GET, HEAD, OPTIONS"));
+    }
+
+    #[test]
+    fn another_method_on_a_known_path_is_a_405_with_allow() {
+        let mut router = Router::new();
+        router.add_route(Method::Get, "/items", text("list"));
+        router.add_route(Method::Post, "/items", text("created"));
+        let response = router.dispatch(&request("DELETE", "/items"));
+        assert_eq!(response.status, StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(
+            response.headers.get("Allow"),
+            Some("GET, HEAD, POST, OPTIONS")
+        );
+    }
+
+    #[test]
+    fn options_star_lists_every_method_of_the_server() {
+        let mut router = Router::new();
+        router.add_route(Method::Get, "/", text("home"));
+        router.add_route(Method::Put, "/files/:name", text("stored"));
+        let response = router.dispatch(&request("OPTIONS", "*"));
+        assert_eq!(response.status, StatusCode::NO_CONTENT);
+        assert_eq!(
+            response.headers.get("Allow"),
+            Some("GET, HEAD, PUT, OPTIONS")
+        );
+    }
+}
@@ -0,0 +1,723 @@
+use std::{
+    error::Error,
+    fmt, fs,
+    io::{self, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+#[cfg(unix)]
+use std::os::unix::{fs::FileTypeExt, net::UnixListener};
+
+use crate::{
+    access_log::AccessLog,
+    auth::Authenticate,
+    compression::Compress,
+    config::ServerConfig,
+    connection::{handle_connection, ConnectionLimiter, Context},
+    error::ServerError,
+    file_cache::FileCache,
+    log,
+    log::Level,
+    metrics::Metrics,
+    middleware::MiddlewareStack,
+    pages::{error_response, file_response, internal_error, not_found},
+    pool::{PoolError, SubmitError, ThreadPool},
+    rate_limit::RateLimiter,
+    request::Method,
+    response::Response,
+    router::Router,
+    shutdown::ShutdownSignal,
+    static_files::{serve_static, serve_static_request, DocumentRoot},
+    status::StatusCode,
+    tls::{self, Protocol, TlsStream},
+    vhost::VirtualHosts,
+};
+
+/// The methods static files are served with
+const STATIC_METHODS: &str = "GET, HEAD, OPTIONS";
+
+/// How long to wait before accepting again after a resource error
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+/// How often an idle accept loop checks for a shutdown request
+const ACCEPT_POLL: Duration = Duration::from_millis(50);
+
+/// Why the server could not start
+#[derive(Debug)]
+pub enum StartError {
+    /// None of the configured addresses, or the Unix socket, could be
+    /// bound
+    Bind(io::Error),
+    /// The worker pool could not be started
+    Pool(PoolError),
+    /// The access log file could not be opened
+    AccessLog(PathBuf, io::Error),
+    /// The TLS certificate or key could not be loaded
+    Tls(io::Error),
+    /// A static directory could not be resolved
+    StaticDir(PathBuf, ServerError),
+}
+
+impl fmt::Display for StartError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StartError::Bind(e) => write!(f, "{}", e),
+            StartError::Pool(e) => write!(f, "{}", e),
+            StartError::AccessLog(path, e) => {
+                write!(f, "unable to open access log {}: {}", path.display(), e)
+            }
+            StartError::Tls(e) => write!(f, "unable to load TLS certificate: {}", e),
+            StartError::StaticDir(path, e) => {
+                write!(
+                    f,
+                    "unable to serve static files from {}: {}",
+                    path.display(),
+                    e
+                )
+            }
+        }
+    }
+}
+
+impl Error for StartError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            StartError::Bind(e) | StartError::AccessLog(_, e) | StartError::Tls(e) => Some(e),
+            StartError::Pool(e) => Some(e),
+            StartError::StaticDir(_, e) => Some(e),
+        }
+    }
+}
+
+/// A running server, as started by [`run_server`]
+pub struct ServerHandle {
+    /// Where each TCP listener ended up, with any port 0 replaced by the
+    /// port the OS picked
+    addrs: Vec<SocketAddr>,
+    pool: Arc<ThreadPool>,
+    context: Arc<Context>,
+    accept_loops: Vec<JoinHandle<()>>,
+}
+
+impl ServerHandle {
+    /// The address of the first TCP listener, or `None` when serving only on
+    /// a Unix socket
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.addrs.first().copied()
+    }
+
+    /// The addresses of all the TCP listeners
+    pub fn local_addrs(&self) -> &[SocketAddr] {
+        &self.addrs
+    }
+
+    /// Triggering this stops the accept loops, so that
+    /// [`wait`](ServerHandle::wait) stops the workers and returns. Another
+    /// thread, such as a signal watcher, can hold it while one waits.
+    pub fn shutdown_signal(&self) -> Arc<ShutdownSignal> {
+        Arc::clone(&self.context.shutdown)
+    }
+
+    /// Stop accepting connections, then stop the workers as
+    /// [`wait`](ServerHandle::wait) does
+    pub fn shutdown(self) {
+        self.context.shutdown.trigger();
+        self.wait();
+    }
+
+    /// Block until the server stops accepting, because the shutdown signal
+    /// was triggered or every listener failed. Then stop the workers on this
+    /// thread, giving queued jobs until the shutdown timeout to finish, and
+    /// remove the Unix socket.
+    pub fn wait(self) {
+        for accept_loop in self.accept_loops {
+            let _ = accept_loop.join();
+        }
+        if self.context.shutdown.is_triggered() {
+            log!(Level::Info, "Shutdown requested - exiting");
+        }
+        stop(&self.pool, &self.context.config);
+    }
+}
+
+/// Bind the listeners `config` asks for and start serving on them in the
+/// background. Port 0 has the OS pick a free port, which
+/// [`ServerHandle::local_addr`] reports.
+pub fn run_server(config: ServerConfig) -> Result<ServerHandle, StartError> {
+    #[cfg(unix)]
+    let unix_listener = match &config.unix_socket {
+        Some(path) => Some(bind_unix(path).map_err(StartError::Bind)?),
+        None => None,
+    };
+    #[cfg(unix)]
+    let listeners = if unix_listener.is_some() {
+        Vec::new()
+    } else {
+        config.bind().map_err(StartError::Bind)?
+    };
+    #[cfg(not(unix))]
+    let listeners = config.bind().map_err(StartError::Bind)?;
+    let addrs = listeners
+        .iter()
+        .map(TcpListener::local_addr)
+        .collect::<io::Result<_>>()
+        .map_err(StartError::Bind)?;
+
+    let mut pool = ThreadPool::builder()
+        .threads(config.threads)
+        .queue_bound(config.queue_bound)
+        .rejection_policy(config.rejection_policy);
+    if let Some(timeout) = config.worker_idle_timeout {
+        pool = pool.idle_timeout(timeout, config.min_workers);
+    }
+    let pool = Arc::new(pool.build().map_err(StartError::Pool)?);
+
+    let access_log = match &config.access_log {
+        Some(path) => {
+            AccessLog::to_file(path).map_err(|e| StartError::AccessLog(path.clone(), e))?
+        }
+        None => AccessLog::stdout(),
+    };
+    let tls = match (&config.tls_cert, &config.tls_key) {
+        (Some(cert), Some(key)) => Some(tls::load_config(cert, key).map_err(StartError::Tls)?),
+        _ => None,
+    };
+    let metrics = Arc::new(Metrics::new());
+    let max_connections = config.max_connections;
+    let rate_limiter = config
+        .rate_limit
+        .map(|rate| RateLimiter::new(rate, config.rate_limit_burst.max(1)));
+    let shutdown = Arc::new(ShutdownSignal::new());
+    let context = Arc::new(Context {
+        sites: build_sites(&config, &metrics, &pool, &shutdown)?,
+        config,
+        access_log,
+        metrics,
+        connections: ConnectionLimiter::new(max_connections),
+        rate_limiter,
+        shutdown,
+    });
+
+    #[cfg(unix)]
+    let unix_loop = unix_listener.map(|listener| {
+        let pool = Arc::clone(&pool);
+        let context = Arc::clone(&context);
+        thread::spawn(move || accept_unix_loop(listener, &pool, &context))
+    });
+    #[cfg(not(unix))]
+    let unix_loop: Option<JoinHandle<()>> = None;
+
+    // Each listener gets its own accept loop, all feeding the one pool
+    let accept_loops = listeners
+        .into_iter()
+        .map(|listener| {
+            let pool = Arc::clone(&pool);
+            let context = Arc::clone(&context);
+            let tls = tls.clone();
+            thread::spawn(move || accept_loop(listener, &pool, &context, tls))
+        })
+        .chain(unix_loop)
+        .collect();
+
+    Ok(ServerHandle {
+        addrs,
+        pool,
+        context,
+        accept_loops,
+    })
+}
+
+fn stop(pool: &ThreadPool, config: &ServerConfig) {
+    pool.shutdown_graceful(config.shutdown_timeout);
+    if let Some(path) = &config.unix_socket {
+        let _ = fs::remove_file(path);
+    }
+}
+
+/// Accept connections on `listener` and hand them to the pool, for as long
+/// as the listener lasts
+fn accept_loop(
+    listener: TcpListener,
+    pool: &ThreadPool,
+    context: &Arc<Context>,
+    tls: Option<Arc<rustls::ServerConfig>>,
+) {
+    if let Ok(addr) = listener.local_addr() {
+        log!(Level::Info, "Listening on {}", addr);
+    }
+    // Blocking in accept would leave the loop deaf to a shutdown request
+    if let Err(e) = listener.set_nonblocking(true) {
+        log!(Level::Error, "Unable to make listener nonblocking: {}", e);
+        return;
+    }
+
+    let accept = || accept_tcp(&listener, &context.config);
+    while let Some(mut stream) = next_stream(accept, &context.shutdown) {
+        let peer = stream.peer_addr().ok();
+        let permit = match context.connections.try_acquire() {
+            Some(permit) => permit,
+            None => {
+                // A plain-text 503 would mean nothing to a TLS client
+                if tls.is_none() {
+                    turn_away(stream, &context.config.error_pages);
+                }
+                continue;
+            }
+        };
+
+        let job_context = Arc::clone(context);
+        let job_tls = tls.clone();
+        // Kept back so the client can still be told if the job is rejected
+        let overflow = stream.try_clone();
+
+        let submitted = pool.submit(move || {
+            let _permit = permit;
+            let job_tls = match job_tls {
+                Some(tls) => match wants_tls(&stream, &job_context.config) {
+                    Ok(true) => Some(tls),
+                    Ok(false) => None,
+                    Err(e) => {
+                        log!(Level::Debug, "No first byte from client: {}", e);
+                        return;
+                    }
+                },
+                None => None,
+            };
+            let result = match job_tls {
+                Some(tls) => match accept_tls(tls, stream, &job_context.config) {
+                    Ok(mut stream) => handle_connection(&mut stream, peer, &job_context),
+                    Err(e) => {
+                        log!(Level::Warn, "TLS handshake failed: {}", e);
+                        return;
+                    }
+                },
+                None => handle_connection(&mut stream, peer, &job_context),
+            };
+            if let Err(e) = result {
+                log!(Level::Warn, "Error handling connection: {}", e);
+            }
+        });
+
+        // A plain-text 503 would mean nothing to a TLS client
+        let overflow = overflow.ok().filter(|_| tls.is_none());
+        report_rejection(submitted, overflow, &context.config.error_pages);
+    }
+}
+
+/// Accept a connection on `listener`, set up as `config` asks. Streams may
+/// inherit the listener's nonblocking mode, but are served with blocking
+/// reads and timeouts.
+fn accept_tcp(listener: &TcpListener, config: &ServerConfig) -> io::Result<TcpStream> {
+    let (stream, _) = listener.accept()?;
+    stream.set_nonblocking(false)?;
+    if config.tcp_nodelay {
+        stream.set_nodelay(true)?;
+    }
+    Ok(stream)
+}
+
+/// Accept connections on a Unix socket and hand them to the pool
+#[cfg(unix)]
+fn accept_unix_loop(listener: UnixListener, pool: &ThreadPool, context: &Arc<Context>) {
+    if let Err(e) = listener.set_nonblocking(true) {
+        log!(Level::Error, "Unable to make listener nonblocking: {}", e);
+        return;
+    }
+    // Streams may inherit nonblocking mode, but are served with blocking
+    // reads and timeouts
+    let accept = || {
+        let (stream, _) = listener.accept()?;
+        stream.set_nonblocking(false)?;
+        Ok(stream)
+    };
+    while let Some(mut stream) = next_stream(accept, &context.shutdown) {
+        let permit = match context.connections.try_acquire() {
+            Some(permit) => permit,
+            None => {
+                turn_away(stream, &context.config.error_pages);
+                continue;
+            }
+        };
+
+        let job_context = Arc::clone(context);
+        let overflow = stream.try_clone().ok();
+        let submitted = pool.submit(move || {
+            let _permit = permit;
+            if let Err(e) = handle_connection(&mut stream, None, &job_context) {
+                log!(Level::Warn, "Error handling connection: {}", e);
+            }
+        });
+        report_rejection(submitted, overflow, &context.config.error_pages);
+    }
+}
+
+/// Wait for the next connection from `accept`, a nonblocking accept.
+/// Returns `None` once `shutdown` is triggered, checking between attempts.
+fn next_connection<S>(
+    accept: impl Fn() -> io::Result<S>,
+    shutdown: &ShutdownSignal,
+) -> Option<io::Result<S>> {
+    while !shutdown.is_triggered() {
+        match accept() {
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(ACCEPT_POLL),
+            accepted => return Some(accepted),
+        }
+    }
+    None
+}
+
+/// The next connection [`next_connection`] accepts, skipping over
+/// connections that fail. Returns `None` once `shutdown` is triggered or
+/// the listener itself fails.
+fn next_stream<S>(accept: impl Fn() -> io::Result<S>, shutdown: &ShutdownSignal) -> Option<S> {
+    loop {
+        match next_connection(&accept, shutdown)? {
+            Ok(stream) => return Some(stream),
+            Err(e) if is_fatal_accept_error(&e) => {
+                log!(
+                    Level::Error,
+                    "Listener failed, no longer accepting on it: {}",
+                    e
+                );
+                return None;
+            }
+            Err(e) => {
+                log!(Level::Warn, "Failed to accept connection: {}", e);
+                accept_backoff(&e);
+            }
+        }
+    }
+}
+
+/// Whether an accept error means the listener itself is unusable, rather
+/// than one connection failing or resources running short for a while
+fn is_fatal_accept_error(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::InvalidInput | io::ErrorKind::NotConnected | io::ErrorKind::Unsupported
+    )
+}
+
+/// Pause after a failed accept, unless only that one connection was at
+/// fault. Errors like running out of file descriptors would otherwise have
+/// the loop spin until some are freed.
+fn accept_backoff(e: &io::Error) {
+    match e.kind() {
+        io::ErrorKind::ConnectionAborted
+        | io::ErrorKind::ConnectionReset
+        | io::ErrorKind::Interrupted => {}
+        _ => thread::sleep(ACCEPT_BACKOFF),
+    }
+}
+
+/// Tell the client, if it can still be reached through `overflow`, that its
+/// connection was not taken because the queue is full
+fn report_rejection(
+    submitted: Result<(), SubmitError>,
+    overflow: Option<impl Write>,
+    error_pages: &Path,
+) {
+    match (submitted, overflow) {
+        (Ok(()), _) => {}
+        (Err(SubmitError::Full), Some(mut stream)) => {
+            let _ =
+                error_response(error_pages, StatusCode::SERVICE_UNAVAILABLE).write_to(&mut stream);
+        }
+        (Err(e), _) => log!(Level::Warn, "Dropping connection: {}", e),
+    }
+}
+
+/// Answer a connection over the limit with a 503 and close it
+fn turn_away(mut stream: impl Write, error_pages: &Path) {
+    let _ = error_response(error_pages, StatusCode::SERVICE_UNAVAILABLE)
+        .header("Connection", "close")
+        .write_to(&mut stream);
+}
+
+/// Listen on the Unix socket at `path`, replacing a socket left behind by a
+/// previous run. Anything other than a socket at `path` is left alone.
+#[cfg(unix)]
+fn bind_unix(path: &Path) -> io::Result<UnixListener> {
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if metadata.file_type().is_socket() {
+            fs::remove_file(path)?;
+        }
+    }
+
+    let listener = UnixListener::bind(path).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("failed to bind {}: {}", path.display(), e),
+        )
+    })?;
+    log!(Level::Info, "Listening on {}", path.display());
+    Ok(listener)
+}
+
+/// Whether a connection to a TLS listener is speaking TLS, which it must
+/// be unless plain HTTP is allowed there too
+fn wants_tls(stream: &TcpStream, config: &ServerConfig) -> io::Result<bool> {
+    if !config.tls_allow_plain {
+        return Ok(true);
+    }
+    stream.set_read_timeout(Some(config.read_timeout))?;
+    Ok(tls::detect_protocol(stream)? == Protocol::Tls)
+}
+
+/// Complete the TLS handshake on a fresh connection, within the usual
+/// timeouts
+fn accept_tls(
+    tls: Arc<rustls::ServerConfig>,
+    stream: TcpStream,
+    config: &ServerConfig,
+) -> io::Result<TlsStream> {
+    stream.set_read_timeout(Some(config.read_timeout))?;
+    stream.set_write_timeout(Some(config.write_timeout))?;
+    tls::accept(tls, stream)
+}
+
+/// The default site serving `static_dir`, plus one for each virtual host
+fn build_sites(
+    config: &ServerConfig,
+    metrics: &Arc<Metrics>,
+    pool: &Arc<ThreadPool>,
+    shutdown: &Arc<ShutdownSignal>,
+) -> Result<VirtualHosts, StartError> {
+    let build = |static_dir| build_app(config, static_dir, metrics, pool, shutdown);
+    let static_dir = config
+        .static_dir
+        .as_deref()
+        .map(document_root)
+        .transpose()?;
+    let mut sites = VirtualHosts::new(build(static_dir));
+    for (host, root) in &config.virtual_hosts {
+        sites.add(host, build(Some(document_root(root)?)));
+    }
+    Ok(sites)
+}
+
+fn document_root(dir: &Path) -> Result<DocumentRoot, StartError> {
+    DocumentRoot::new(dir).map_err(|e| StartError::StaticDir(dir.to_path_buf(), e))
+}
+
+/// The router wrapped in the middleware `config` asks for. CORS goes
+/// outermost so preflights are answered before auth, and compression
+/// innermost so only the router's own responses are compressed.
+fn build_app(
+    config: &ServerConfig,
+    static_dir: Option<DocumentRoot>,
+    metrics: &Arc<Metrics>,
+    pool: &Arc<ThreadPool>,
+    shutdown: &Arc<ShutdownSignal>,
+) -> MiddlewareStack {
+    let router = build_router(config, static_dir, metrics, pool, shutdown);
+    let mut app = MiddlewareStack::new(router);
+    if let Some(cors) = &config.cors {
+        app.push(cors.clone());
+    }
+    if let Some(auth) = &config.basic_auth {
+        app.push(Authenticate {
+            auth: auth.clone(),
+            error_pages: config.error_pages.clone(),
+        });
+    }
+    app.push(Compress {
+        min_size: config.compression_min_size,
+    });
+    app
+}
+
+fn build_router(
+    config: &ServerConfig,
+    static_dir: Option<DocumentRoot>,
+    metrics: &Arc<Metrics>,
+    pool: &Arc<ThreadPool>,
+    shutdown: &Arc<ShutdownSignal>,
+) -> Router {
+    let mut router = Router::new();
+
+    // A static directory serves its own index page
+    if static_dir.is_none() {
+        let pages = config.error_pages.clone();
+        router.add_route(
+            Method::Get,
+            "/",
+            Box::new(
+                move |_| match file_response(StatusCode::OK, Path::new("hello.html")) {
+                    Ok(response) => response,
+                    Err(ServerError::NotFound(_)) => not_found(&pages),
+                    Err(e) => internal_error(&pages, e),
+                },
+            ),
+        );
+    }
+
+    // Liveness check for orchestrators. Never touches the disk, so it works
+    // whatever the static directory holds.
+    router.add_route(
+        Method::Get,
+        "/healthz",
+        Box::new(|_| {
+            Response::new(StatusCode::OK)
+                .header("Content-Type", "text/plain; charset=utf-8")
+                .body("ok")
+        }),
+    );
+
+    let (metrics, pool) = (Arc::clone(metrics), Arc::clone(pool));
+    router.add_route(
+        Method::Get,
+        "/metrics",
+        Box::new(move |_| {
+            Response::new(StatusCode::OK)
+                .header("Content-Type", "text/plain; version=0.0.4; charset=utf-8")
+                .body(metrics.render(&pool.stats()))
+        }),
+    );
+
+    if config.admin_shutdown {
+        let shutdown = Arc::clone(shutdown);
+        router.add_route(
+            Method::Post,
+            "/admin/shutdown",
+            Box::new(move |_| {
+                shutdown.trigger();
+                Response::new(StatusCode::ACCEPTED)
+                    .header("Connection", "close")
+                    .header("Content-Type", "text/plain; charset=utf-8")
+                    .body("shutting down")
+            }),
+        );
+    }
+
+    let list_directories = config.directory_listing;
+    let spa_fallback = config.spa_fallback;
+    let redirect_directories = config.redirect_directories;
+    let cache = FileCache::new(config.file_cache_size);
+    let pages = config.error_pages.clone();
+    router.set_fallback(Box::new(move |req| match &static_dir {
+        Some(root) if req.method == Method::Get || req.method == Method::Head => {
+            match serve_static_request(root, req, list_directories, redirect_directories, &cache) {
+                Ok(response) => response,
+                Err(ServerError::Forbidden(_)) => error_response(&pages, StatusCode::FORBIDDEN),
+                Err(ServerError::NotFound(_)) if spa_fallback && !has_extension(&req.path) => {
+                    serve_static(root, "/").unwrap_or_else(|_| not_found(&pages))
+                }
+                Err(ServerError::NotFound(_)) => not_found(&pages),
+                Err(ServerError::Parse(_)) => error_response(&pages, StatusCode::BAD_REQUEST),
+                Err(e) => internal_error(&pages, e),
+            }
+        }
+        // Static files can only be fetched, so anything else done to one
+        // that exists is answered as a route without the method would be
+        Some(root) if root.resolve(&req.path).is_ok() => match req.method {
+            Method::Options => {
+                Response::new(StatusCode::NO_CONTENT).header("Allow", STATIC_METHODS)
+            }
+            _ => error_response(&pages, StatusCode::METHOD_NOT_ALLOWED)
+                .header("Allow", STATIC_METHODS),
+        },
+        _ => not_found(&pages),
+    }));
+
+    router
+}
+
+/// Whether the last segment of a URL path has a file extension, as asset
+/// paths do and client-side routes generally don't
+fn has_extension(path: &str) -> bool {
+    let segment = path.rsplit('/').next().unwrap_or_default();
+    Path::new(segment).extension().is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, sync::mpsc};
+
+    use super::*;
+
+    #[test]
+    fn next_connection_returns_once_shutdown_is_triggered() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let shutdown = Arc::new(ShutdownSignal::new());
+        let (returned, result) = mpsc::channel();
+
+        let waiting = Arc::clone(&shutdown);
+        thread::spawn(move || {
+            let accepted = next_connection(|| listener.accept(), &waiting);
+            returned.send(accepted.is_none()).unwrap();
+        });
+        assert!(result.recv_timeout(ACCEPT_POLL * 4).is_err());
+
+        shutdown.trigger();
+        assert_eq!(result.recv_timeout(Duration::from_secs(5)), Ok(true));
+    }
+
+    #[test]
+    fn a_connection_the_full_queue_rejects_gets_a_503() {
+        let mut client = Vec::new();
+        report_rejection(Err(SubmitError::Full), Some(&mut client), Path::new("."));
+        let response = String::from_utf8(client).unwrap();
+        assert!(
+            response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"),
+            "{}",
+            response
+        );
+
+        let mut client = Vec::new();
+        report_rejection(Ok(()), Some(&mut client), Path::new("."));
+        assert!(client.is_empty());
+    }
+
+    #[test]
+    fn failed_accepts_are_skipped_until_the_listener_fails() {
+        let incoming = RefCell::new(
+            vec![
+                Err(io::ErrorKind::ConnectionReset.into()),
+                Ok(1),
+                Err(io::ErrorKind::ConnectionAborted.into()),
+                Err(io::ErrorKind::WouldBlock.into()),
+                Ok(2),
+                Err(io::ErrorKind::InvalidInput.into()),
+                Ok(3),
+            ]
+            .into_iter(),
+        );
+        let accept = || incoming.borrow_mut().next().unwrap();
+        let shutdown = ShutdownSignal::new();
+
+        assert_eq!(next_stream(accept, &shutdown), Some(1));
+        assert_eq!(next_stream(accept, &shutdown), Some(2));
+        // A listener that has failed is given up on
+        assert_eq!(next_stream(accept, &shutdown), None);
+        assert_eq!(incoming.borrow_mut().next().map(Result::ok), Some(Some(3)));
+    }
+
+    #[test]
+    fn only_the_last_segment_is_checked_for_an_extension() {
+        assert!(has_extension("/assets/app.js"));
+        assert!(has_extension("/favicon.ico"));
+        assert!(!has_extension("/users/42"));
+        assert!(!has_extension("/v1.2/users"));
+        assert!(!has_extension("/"));
+    }
+
+    #[test]
+    fn accepted_streams_get_nodelay_when_configured() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        for tcp_nodelay in [true, false] {
+            let config = ServerConfig {
+                tcp_nodelay,
+                ..ServerConfig::default()
+            };
+            let _client = TcpStream::connect(addr).unwrap();
+            let stream = accept_tcp(&listener, &config).unwrap();
+            assert_eq!(stream.nodelay().unwrap(), tcp_nodelay);
+        }
+    }
+}
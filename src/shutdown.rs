@@ -0,0 +1,46 @@
+use std::{
+    sync::{Condvar, Mutex},
+    time::Duration,
+};
+
+/// A request for the server to stop, which any thread can make and any
+/// number of threads can wait on. Handlers that hold one can shut the
+/// server down, e.g. from an admin route.
+#[derive(Debug, Default)]
+pub struct ShutdownSignal {
+    triggered: Mutex<bool>,
+    changed: Condvar,
+}
+
+impl ShutdownSignal {
+    pub fn new() -> ShutdownSignal {
+        ShutdownSignal::default()
+    }
+
+    /// Ask the server to stop. Only the first call has any effect.
+    pub fn trigger(&self) {
+        *self.triggered.lock().unwrap() = true;
+        self.changed.notify_all();
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.triggered.lock().unwrap()
+    }
+
+    /// Block until the signal is triggered
+    pub fn wait(&self) {
+        let triggered = self.triggered.lock().unwrap();
+        let _triggered = self.changed.wait_while(triggered, |t| !*t).unwrap();
+    }
+
+    /// Block until the signal is triggered or `timeout` passes, returning
+    /// whether it was triggered
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let triggered = self.triggered.lock().unwrap();
+        let (triggered, _) = self
+            .changed
+            .wait_timeout_while(triggered, timeout, |t| !*t)
+            .unwrap();
+        *triggered
+    }
+}
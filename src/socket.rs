@@ -0,0 +1,129 @@
+use std::{
+    io,
+    net::{SocketAddr, TcpListener},
+};
+
+/// Connections the kernel queues for a listener before they are accepted,
+/// the same as std uses
+#[cfg(unix)]
+const BACKLOG: libc::c_int = 128;
+
+/// Bind a TCP listener on `addr`. With `reuse_address`, `SO_REUSEADDR` is
+/// set before binding, so a restarted server can bind its port straight
+/// away instead of waiting out the old connections left in `TIME_WAIT`.
+#[cfg(unix)]
+pub fn bind_tcp(addr: SocketAddr, reuse_address: bool) -> io::Result<TcpListener> {
+    use std::{mem, os::unix::io::AsRawFd, os::unix::io::FromRawFd};
+
+    let domain = match addr {
+        SocketAddr::V4(_) => libc::AF_INET,
+        SocketAddr::V6(_) => libc::AF_INET6,
+    };
+    let fd = cvt(unsafe { libc::socket(domain, libc::SOCK_STREAM, 0) })?;
+    // Owning the descriptor straight away closes it on any error below
+    let listener = unsafe { TcpListener::from_raw_fd(fd) };
+    cvt(unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) })?;
+
+    let reuse = libc::c_int::from(reuse_address);
+    cvt(unsafe {
+        libc::setsockopt(
+            listener.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_REUSEADDR,
+            &reuse as *const libc::c_int as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    })?;
+
+    match addr {
+        SocketAddr::V4(addr) => {
+            let mut sin: libc::sockaddr_in = unsafe { mem::zeroed() };
+            sin.sin_family = libc::AF_INET as libc::sa_family_t;
+            sin.sin_port = addr.port().to_be();
+            sin.sin_addr.s_addr = u32::from_ne_bytes(addr.ip().octets());
+            cvt(unsafe {
+                libc::bind(
+                    fd,
+                    &sin as *const libc::sockaddr_in as *const libc::sockaddr,
+                    mem::size_of_val(&sin) as libc::socklen_t,
+                )
+            })?;
+        }
+        SocketAddr::V6(addr) => {
+            let mut sin6: libc::sockaddr_in6 = unsafe { mem::zeroed() };
+            sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sin6.sin6_port = addr.port().to_be();
+            sin6.sin6_addr.s6_addr = addr.ip().octets();
+            sin6.sin6_flowinfo = addr.flowinfo();
+            sin6.sin6_scope_id = addr.scope_id();
+            cvt(unsafe {
+                libc::bind(
+                    fd,
+                    &sin6 as *const libc::sockaddr_in6 as *const libc::sockaddr,
+                    mem::size_of_val(&sin6) as libc::socklen_t,
+                )
+            })?;
+        }
+    }
+
+    cvt(unsafe { libc::listen(fd, BACKLOG) })?;
+    Ok(listener)
+}
+
+/// Bind a TCP listener on `addr`. `SO_REUSEADDR` lets other sockets take
+/// over a bound port on Windows, rather than just skip `TIME_WAIT`, so
+/// `reuse_address` is ignored there.
+#[cfg(not(unix))]
+pub fn bind_tcp(addr: SocketAddr, _reuse_address: bool) -> io::Result<TcpListener> {
+    TcpListener::bind(addr)
+}
+
+#[cfg(unix)]
+fn cvt(ret: libc::c_int) -> io::Result<libc::c_int> {
+    if ret == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::{mem, net::TcpStream, os::unix::io::AsRawFd};
+
+    use super::*;
+
+    fn reuse_address(listener: &TcpListener) -> bool {
+        let mut value: libc::c_int = 0;
+        let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+        cvt(unsafe {
+            libc::getsockopt(
+                listener.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_REUSEADDR,
+                &mut value as *mut libc::c_int as *mut libc::c_void,
+                &mut len,
+            )
+        })
+        .unwrap();
+        value != 0
+    }
+
+    #[test]
+    fn reuse_address_is_set_only_when_asked() {
+        for reuse in [true, false] {
+            let listener = bind_tcp("127.0.0.1:0".parse().unwrap(), reuse).unwrap();
+            assert_eq!(reuse_address(&listener), reuse);
+        }
+    }
+
+    #[test]
+    fn a_bound_listener_accepts_on_either_family() {
+        for addr in ["127.0.0.1:0", "[::1]:0"] {
+            let listener = bind_tcp(addr.parse().unwrap(), true).unwrap();
+            let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+            let (_, peer) = listener.accept().unwrap();
+            assert_eq!(peer, client.local_addr().unwrap());
+        }
+    }
+}
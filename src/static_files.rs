@@ -0,0 +1,965 @@
+use std::{
+    fs,
+    io::{self, Read, Seek, SeekFrom},
+    path::{Component, Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    compression::accepts_gzip,
+    date::{format_http_date, parse_http_date},
+    error::ServerError,
+    file_cache::FileCache,
+    range::{parse_range, partial, unsatisfiable},
+    request::{Method, Request},
+    response::Response,
+    status::StatusCode,
+    url::{percent_decode, percent_encode, EncodeSet},
+};
+
+const INDEX_FILE: &str = "index.html";
+/// Files at least this large are streamed from disk rather than read into
+/// memory whole
+const STREAM_THRESHOLD: u64 = 1024 * 1024;
+
+/// Serve the file under `root` named by the URL `path`, or the directory's
+/// `index.html` if it names a directory.
+///
+/// Fails with `NotFound` when there is nothing to serve, `Forbidden` when
+/// the path is rejected by [`DocumentRoot::resolve`] or names a directory without
+/// an index, and `Parse` when it is not validly percent-encoded.
+pub fn serve_static(root: &DocumentRoot, path: &str) -> Result<Response, ServerError> {
+    let file = match resolve(root, path)? {
+        Resolved::File(file) | Resolved::Index(file) => file,
+        Resolved::Directory(_) => return Err(ServerError::Forbidden(path.to_string())),
+    };
+    let metadata = fs::metadata(&file)?;
+    file_response(&file, &metadata, None)
+}
+
+/// Serve `request` from `root` as [`serve_static`] does, honouring its
+/// conditional and `Range` headers. A directory without an index is listed
+/// when `list_directories` is set. File contents come from `cache` while
+/// they are unchanged on disk.
+///
+/// A directory path without a trailing slash is redirected to one that has
+/// it when `redirect_directories` is set, so relative links in its index
+/// resolve inside the directory. Listings are always redirected, since
+/// their links are all relative.
+///
+/// A client that accepts gzip is sent `file.gz` instead of `file` when it
+/// exists, already compressed.
+pub fn serve_static_request(
+    root: &DocumentRoot,
+    request: &Request,
+    list_directories: bool,
+    redirect_directories: bool,
+    cache: &FileCache,
+) -> Result<Response, ServerError> {
+    let file = match resolve(root, &request.path)? {
+        Resolved::File(file) => file,
+        Resolved::Index(_) if redirect_directories && !request.path.ends_with('/') => {
+            return slash_redirect(request)
+        }
+        Resolved::Index(index) => index,
+        Resolved::Directory(dir) if list_directories => return directory_response(&dir, request),
+        Resolved::Directory(_) => return Err(ServerError::Forbidden(request.path.clone())),
+    };
+    let gzipped = precompressed(root, &file)?;
+    let accepts_gzip = request
+        .headers
+        .get("Accept-Encoding")
+        .is_some_and(accepts_gzip);
+    let served = match &gzipped {
+        Some(gzipped) if accepts_gzip => gzipped,
+        _ => &file,
+    };
+    let metadata = fs::metadata(served)?;
+    let etag = etag_for(&metadata);
+
+    // If-Modified-Since is only consulted without If-None-Match, as the
+    // ETag is the more precise validator
+    let not_modified = match request.headers.get("If-None-Match") {
+        Some(tags) => etag_matches(tags, &etag),
+        None => request
+            .headers
+            .get("If-Modified-Since")
+            .and_then(parse_http_date)
+            .is_some_and(|since| !modified_since(&metadata, since)),
+    };
+    if not_modified {
+        let mut response = Response::new(StatusCode::NOT_MODIFIED).header("ETag", &etag);
+        if let Ok(modified) = metadata.modified() {
+            response = response.header("Last-Modified", &format_http_date(modified));
+        }
+        return Ok(response);
+    }
+
+    // Only a HEAD for the whole file can go without reading it, a range
+    // needs the bytes to check against
+    let range = request.headers.get("Range");
+    let mut response = if request.method == Method::Head && range.is_none() {
+        file_head_response(served, &metadata)
+    } else if metadata.len() >= STREAM_THRESHOLD {
+        streamed_response(served, &metadata, range)?
+    } else if let Some(range) = range {
+        ranged_response(served, &metadata, range)?
+    } else {
+        file_response(served, &metadata, Some(cache))?
+    };
+
+    if served != &file && response.status != StatusCode::RANGE_NOT_SATISFIABLE {
+        response.headers.set("Content-Encoding", "gzip");
+        response
+            .headers
+            .set("Content-Type", &content_type_for(&file.to_string_lossy()));
+    }
+    if gzipped.is_some() {
+        // Which file is sent depends on Accept-Encoding
+        response.vary("Accept-Encoding");
+    }
+    Ok(response)
+}
+
+/// The precompressed `.gz` copy of `file`, if there is one under `root`
+fn precompressed(root: &DocumentRoot, file: &Path) -> Result<Option<PathBuf>, ServerError> {
+    let mut name = file.as_os_str().to_owned();
+    name.push(".gz");
+
+    // The copy may be a symlink out of the root, like any other file
+    match canonicalize(Path::new(&name)) {
+        Ok(gzipped) if gzipped.starts_with(root.path()) && gzipped.is_file() => Ok(Some(gzipped)),
+        Ok(_) | Err(ServerError::NotFound(_)) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// An entity tag for a file, derived from its size and modification time so
+/// it changes whenever the file is rewritten
+pub fn etag_for(metadata: &fs::Metadata) -> String {
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    format!("\"{:x}-{:x}\"", metadata.len(), modified.as_nanos())
+}
+
+/// Whether the file changed after `since`. HTTP dates only have whole
+/// seconds, so the modification time is truncated to match.
+pub fn modified_since(metadata: &fs::Metadata, since: SystemTime) -> bool {
+    let seconds = |time: SystemTime| {
+        time.duration_since(UNIX_EPOCH)
+            .map_or(0, |since_epoch| since_epoch.as_secs())
+    };
+    match metadata.modified() {
+        Ok(modified) => seconds(modified) > seconds(since),
+        // Without a modification time the file has to be sent
+        Err(_) => true,
+    }
+}
+
+/// Whether an `If-None-Match` list names `etag`. Weak tags match too, since
+/// the comparison for `If-None-Match` is the weak one.
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match
+        .split(',')
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+/// A listing of `dir`, or a redirect to the request's path with a trailing
+/// slash so the listing's relative links resolve inside the directory
+fn directory_response(dir: &Path, request: &Request) -> Result<Response, ServerError> {
+    if !request.path.ends_with('/') {
+        return slash_redirect(request);
+    }
+
+    let listing = render_directory_listing(dir)?;
+    Ok(Response::new(StatusCode::OK)
+        .header("Content-Type", "text/html; charset=utf-8")
+        .body(listing))
+}
+
+/// A `301` to the request's path with a trailing slash, keeping its query
+fn slash_redirect(request: &Request) -> Result<Response, ServerError> {
+    // Re-encoded, as clients needn't have escaped everything they sent
+    let mut location = percent_encode(&percent_decode(&request.path)?, EncodeSet::Path);
+    location.push('/');
+    if let Some((_, query)) = request.target.split_once('?') {
+        location.push('?');
+        location.push_str(query);
+    }
+    Ok(Response::redirect(&location, StatusCode::MOVED_PERMANENTLY))
+}
+
+/// An HTML page linking to each entry of `dir`, with its size and
+/// modification time. Directories come first, then files, each sorted by
+/// name. Links are relative, so the page has to be served from a URL ending
+/// in `/`.
+pub fn render_directory_listing(dir: &Path) -> Result<String, io::Error> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        entries.push((!metadata.is_dir(), name, metadata));
+    }
+    entries.sort_by(|a, b| (a.0, &a.1).cmp(&(b.0, &b.1)));
+
+    let title = html_escape(&dir.file_name().unwrap_or_default().to_string_lossy());
+    let mut rows = String::new();
+    for (is_file, name, metadata) in &entries {
+        let (href, label, size) = if *is_file {
+            (
+                percent_encode(name, EncodeSet::Component),
+                html_escape(name),
+                metadata.len().to_string(),
+            )
+        } else {
+            (
+                format!("{}/", percent_encode(name, EncodeSet::Component)),
+                format!("{}/", html_escape(name)),
+                "-".to_string(),
+            )
+        };
+        let modified = metadata
+            .modified()
+            .map_or_else(|_| "-".to_string(), format_http_date);
+        rows.push_str(&format!(
+            "      <tr><td><a href=\"{}\">{}</a></td><td>{}</td><td>{}</td></tr>\n",
+            href, label, size, modified
+        ));
+    }
+
+    Ok(format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n  <head>\n    <meta charset=\"utf-8\">\n    \
+         <title>Index of {title}</title>\n  </head>\n  <body>\n    <h1>Index of {title}</h1>\n    \
+         <table>\n      <tr><th>Name</th><th>Size</th><th>Modified</th></tr>\n{rows}    \
+         </table>\n  </body>\n</html>\n",
+        title = title,
+        rows = rows
+    ))
+}
+
+fn html_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn file_response(
+    file: &Path,
+    metadata: &fs::Metadata,
+    cache: Option<&FileCache>,
+) -> Result<Response, ServerError> {
+    let contents = match cache {
+        Some(cache) => cache.read(file, metadata)?,
+        None => fs::read(file)?,
+    };
+    Ok(file_head_response(file, metadata).body(contents))
+}
+
+/// A response that streams `file` from disk, or the part of it selected by
+/// `range`
+fn streamed_response(
+    file: &Path,
+    metadata: &fs::Metadata,
+    range: Option<&str>,
+) -> Result<Response, ServerError> {
+    let total_len = metadata.len();
+    let (first, last) = match range.map(|range| parse_range(range, total_len)) {
+        None => (0, total_len.saturating_sub(1)),
+        Some(Ok(selected)) => selected,
+        Some(Err(_)) => return Ok(unsatisfiable(total_len)),
+    };
+
+    let mut reader = fs::File::open(file)?;
+    reader.seek(SeekFrom::Start(first))?;
+    let len = last - first + 1;
+    let response = file_head_response(file, metadata).stream(reader.take(len), len);
+    if range.is_some() {
+        return Ok(partial(response, first, last, total_len));
+    }
+    Ok(response)
+}
+
+/// A response with just the part of `file` selected by `range`, read
+/// without loading the rest
+fn ranged_response(
+    file: &Path,
+    metadata: &fs::Metadata,
+    range: &str,
+) -> Result<Response, ServerError> {
+    let total_len = metadata.len();
+    let (first, last) = match parse_range(range, total_len) {
+        Ok(selected) => selected,
+        Err(_) => return Ok(unsatisfiable(total_len)),
+    };
+
+    let body = read_file_range(file, first, last - first + 1)?;
+    Ok(partial(
+        file_head_response(file, metadata).body(body),
+        first,
+        last,
+        total_len,
+    ))
+}
+
+/// Read `len` bytes of the file at `path` from offset `start`, leaving the
+/// rest of it unread. Fewer bytes come back if the file ends first.
+pub fn read_file_range(path: &Path, start: u64, len: u64) -> io::Result<Vec<u8>> {
+    let mut file = fs::File::open(path)?;
+    file.seek(SeekFrom::Start(start))?;
+    // The length comes from a client, so don't trust it with the allocation
+    let mut contents = Vec::with_capacity(len.min(STREAM_THRESHOLD) as usize);
+    file.take(len).read_to_end(&mut contents)?;
+    Ok(contents)
+}
+
+/// The headers [`file_response`] would send, with the length taken from
+/// `metadata` rather than by reading the file
+fn file_head_response(file: &Path, metadata: &fs::Metadata) -> Response {
+    let mut response = Response::new(StatusCode::OK)
+        .header("Content-Type", &content_type_for(&file.to_string_lossy()))
+        .header("Accept-Ranges", "bytes")
+        .header("ETag", &etag_for(metadata));
+    if let Ok(modified) = metadata.modified() {
+        response = response.header("Last-Modified", &format_http_date(modified));
+    }
+    response.content_length = Some(metadata.len());
+    response
+}
+
+/// The `Content-Type` to serve a file with: its MIME type, with a UTF-8
+/// charset for text types so clients don't have to guess the encoding.
+/// Binary types get no charset.
+pub fn content_type_for(path: &str) -> String {
+    let mime = mime_for_extension(path);
+    if mime.starts_with("text/") || mime == "application/json" {
+        format!("{}; charset=utf-8", mime)
+    } else {
+        mime.to_string()
+    }
+}
+
+/// The MIME type for a file based on its extension, compared
+/// case-insensitively
+pub fn mime_for_extension(path: &str) -> &'static str {
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_ascii_lowercase);
+
+    match extension.as_deref() {
+        Some("html") | Some("htm") => "text/html",
+        Some("css") => "text/css",
+        Some("js") | Some("mjs") => "text/javascript",
+        Some("json") => "application/json",
+        Some("txt") => "text/plain",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("svg") => "image/svg+xml",
+        Some("ico") => "image/x-icon",
+        Some("wasm") => "application/wasm",
+        _ => "application/octet-stream",
+    }
+}
+
+/// A static root, canonicalized once so that every path served from it is
+/// checked against the same absolute path. Canonicalizing the root again
+/// for each request would race with it being moved or swapped for a
+/// symlink.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DocumentRoot {
+    root: PathBuf,
+}
+
+impl DocumentRoot {
+    /// Fails with `NotFound` if `root` doesn't exist
+    pub fn new(root: &Path) -> Result<DocumentRoot, ServerError> {
+        Ok(DocumentRoot {
+            root: canonicalize(root)?,
+        })
+    }
+
+    /// The canonical path of the root
+    pub fn path(&self) -> &Path {
+        &self.root
+    }
+
+    /// Map the URL `request_path` to a file under the root.
+    ///
+    /// The path is percent-decoded first, so encoded `..` segments are
+    /// caught too. Paths with null bytes, `..` components or that are still
+    /// absolute once the one leading `/` is removed, such as `//etc/passwd`,
+    /// are `Forbidden`, as is anything whose canonical form (following
+    /// symlinks) is not under the root.
+    pub fn resolve(&self, request_path: &str) -> Result<PathBuf, ServerError> {
+        let decoded = percent_decode(request_path)?;
+        let relative = Path::new(decoded.strip_prefix('/').unwrap_or(&decoded));
+        let forbidden = || ServerError::Forbidden(request_path.to_string());
+
+        if decoded.contains('\0') || relative.has_root() {
+            return Err(forbidden());
+        }
+        if relative
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir))
+        {
+            return Err(forbidden());
+        }
+
+        let path = canonicalize(&self.root.join(relative))?;
+        if path.starts_with(&self.root) {
+            Ok(path)
+        } else {
+            Err(forbidden())
+        }
+    }
+}
+
+/// Map the URL `request_path` to a file under `root`, as
+/// [`DocumentRoot::resolve`] does
+pub fn sanitize_path(root: &Path, request_path: &str) -> Result<PathBuf, ServerError> {
+    DocumentRoot::new(root)?.resolve(request_path)
+}
+
+/// What a URL path under the static root names
+enum Resolved {
+    File(PathBuf),
+    /// The index file of a directory
+    Index(PathBuf),
+    /// A directory with no index
+    Directory(PathBuf),
+}
+
+fn resolve(root: &DocumentRoot, path: &str) -> Result<Resolved, ServerError> {
+    let file = root.resolve(path)?;
+    if !file.is_dir() {
+        return Ok(Resolved::File(file));
+    }
+
+    // The index file itself may be a symlink out of the root
+    let index = match canonicalize(&file.join(INDEX_FILE)) {
+        Ok(index) => index,
+        Err(ServerError::NotFound(_)) => return Ok(Resolved::Directory(file)),
+        Err(e) => return Err(e),
+    };
+    if index.starts_with(root.path()) {
+        Ok(Resolved::Index(index))
+    } else {
+        Err(ServerError::Forbidden(path.to_string()))
+    }
+}
+
+fn canonicalize(path: &Path) -> Result<PathBuf, ServerError> {
+    path.canonicalize().map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => ServerError::NotFound(path.to_path_buf()),
+        _ => e.into(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{
+        request::{parse_request_head, ParseError},
+        testing::TempDir,
+    };
+
+    fn site() -> (TempDir, DocumentRoot) {
+        let dir = TempDir::new();
+        dir.file("index.html", "<h1>home</h1>");
+        dir.file("style.css", "body {}");
+        dir.file("docs/index.html", "<h1>docs</h1>");
+        dir.dir("empty");
+        let root = DocumentRoot::new(dir.path()).unwrap();
+        (dir, root)
+    }
+
+    fn body(response: &Response) -> String {
+        String::from_utf8(response.body.clone()).unwrap()
+    }
+
+    #[test]
+    fn serves_a_file_under_the_root() {
+        let (_dir, root) = site();
+        let response = serve_static(&root, "/style.css").unwrap();
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(body(&response), "body {}");
+        assert_eq!(
+            response.headers.get("Content-Type"),
+            Some("text/css; charset=utf-8")
+        );
+    }
+
+    #[test]
+    fn serves_the_index_of_a_directory() {
+        let (_dir, root) = site();
+        assert_eq!(body(&serve_static(&root, "/").unwrap()), "<h1>home</h1>");
+        assert_eq!(
+            body(&serve_static(&root, "/docs/").unwrap()),
+            "<h1>docs</h1>"
+        );
+        assert!(matches!(
+            serve_static(&root, "/empty/"),
+            Err(ServerError::Forbidden(_))
+        ));
+    }
+
+    #[test]
+    fn a_missing_file_is_not_found() {
+        let (_dir, root) = site();
+        assert!(matches!(
+            serve_static(&root, "/missing.html"),
+            Err(ServerError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn a_path_out_of_the_root_is_forbidden() {
+        let (_dir, root) = site();
+        for path in ["/../../etc/passwd", "/docs/../../etc/passwd", "/.."] {
+            assert!(
+                matches!(serve_static(&root, path), Err(ServerError::Forbidden(_))),
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn mime_types_follow_the_extension() {
+        let types = [
+            ("index.html", "text/html"),
+            ("a/b/page.HTM", "text/html"),
+            ("app.js", "text/javascript"),
+            ("data.json", "application/json"),
+            ("logo.png", "image/png"),
+            ("photo.JPEG", "image/jpeg"),
+            ("icon.svg", "image/svg+xml"),
+            ("module.wasm", "application/wasm"),
+        ];
+        for (path, mime) in types {
+            assert_eq!(mime_for_extension(path), mime, "{}", path);
+        }
+    }
+
+    #[test]
+    fn unknown_extensions_are_octet_streams() {
+        for path in ["archive.xyz", "Makefile", "dir.d/file", ".bashrc"] {
+            assert_eq!(
+                mime_for_extension(path),
+                "application/octet-stream",
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn static_paths_are_percent_decoded() {
+        let (dir, root) = site();
+        dir.file("my file.txt", "spaced");
+        assert_eq!(
+            body(&serve_static(&root, "/my%20file.txt").unwrap()),
+            "spaced"
+        );
+        assert!(matches!(
+            serve_static(&root, "/bad%ZZ.txt"),
+            Err(ServerError::Parse(ParseError::InvalidPercentEncoding(_)))
+        ));
+    }
+
+    fn is_forbidden(result: Result<PathBuf, ServerError>) -> bool {
+        matches!(result, Err(ServerError::Forbidden(_)))
+    }
+
+    #[test]
+    fn sanitize_path_resolves_a_nested_path() {
+        let (dir, _root) = site();
+        let file = dir.file("a/b/c.txt", "deep");
+        let resolved = sanitize_path(dir.path(), "/a/b/./c.txt").unwrap();
+        assert_eq!(resolved, file.canonicalize().unwrap());
+    }
+
+    #[test]
+    fn sanitize_path_rejects_parent_components() {
+        let (dir, _root) = site();
+        assert!(is_forbidden(sanitize_path(dir.path(), "/../src/main.rs")));
+        // Even one that would end up back inside the root
+        assert!(is_forbidden(sanitize_path(
+            dir.path(),
+            "/docs/../index.html"
+        )));
+    }
+
+    #[test]
+    fn sanitize_path_rejects_encoded_parent_components() {
+        let (dir, _root) = site();
+        for path in [
+            "/%2e%2e/etc/passwd",
+            "/%2E%2E%2Fetc%2Fpasswd",
+            "/docs/..%2f..%2f",
+        ] {
+            assert!(is_forbidden(sanitize_path(dir.path(), path)), "{}", path);
+        }
+    }
+
+    #[test]
+    fn sanitize_path_rejects_null_bytes() {
+        let (dir, _root) = site();
+        assert!(is_forbidden(sanitize_path(
+            dir.path(),
+            "/index.html%00.png"
+        )));
+    }
+
+    #[test]
+    fn sanitize_path_rejects_absolute_paths() {
+        let (dir, _root) = site();
+        for path in ["//etc/passwd", "/%2Fetc%2Fpasswd", "///index.html"] {
+            assert!(is_forbidden(sanitize_path(dir.path(), path)), "{}", path);
+        }
+    }
+
+    /// `GET path` with the extra `headers`, one `Name: value` per line
+    fn request(method: &str, path: &str, headers: &str) -> Request {
+        let head = format!("{} {} HTTP/1.1\r\nHost: a\r\n{}\r\n", method, path, headers);
+        parse_request_head(head.as_bytes(), 100).unwrap()
+    }
+
+    fn get(root: &DocumentRoot, path: &str, headers: &str) -> Response {
+        let request = request("GET", path, headers);
+        serve_static_request(root, &request, false, true, &FileCache::new(0)).unwrap()
+    }
+
+    #[test]
+    fn a_matching_etag_is_a_304() {
+        let (_dir, root) = site();
+        let etag = get(&root, "/style.css", "")
+            .headers
+            .get("ETag")
+            .unwrap()
+            .to_string();
+        assert!(etag.starts_with('"') && etag.ends_with('"'), "{}", etag);
+
+        for if_none_match in [
+            etag.clone(),
+            format!("W/{}", etag),
+            format!("\"x\", {}", etag),
+            "*".to_string(),
+        ] {
+            let response = get(
+                &root,
+                "/style.css",
+                &format!("If-None-Match: {}\r\n", if_none_match),
+            );
+            assert_eq!(
+                response.status,
+                StatusCode::NOT_MODIFIED,
+                "{}",
+                if_none_match
+            );
+            assert!(response.body.is_empty());
+            assert_eq!(response.headers.get("ETag"), Some(etag.as_str()));
+        }
+    }
+
+    #[test]
+    fn a_mismatched_etag_gets_the_body() {
+        let (_dir, root) = site();
+        let response = get(&root, "/style.css", "If-None-Match: \"stale\"\r\n");
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(body(&response), "body {}");
+    }
+
+    #[test]
+    fn the_etag_changes_with_the_file() {
+        let (dir, root) = site();
+        let before = get(&root, "/style.css", "")
+            .headers
+            .get("ETag")
+            .map(String::from);
+        dir.file("style.css", "body { margin: 0 }");
+        let after = get(&root, "/style.css", "")
+            .headers
+            .get("ETag")
+            .map(String::from);
+        assert_ne!(before, after);
+    }
+
+    #[test]
+    fn if_modified_since_is_a_304_unless_the_file_is_newer() {
+        let (dir, root) = site();
+        let modified = UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        fs::File::options()
+            .write(true)
+            .open(dir.path().join("style.css"))
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        let since = |secs: u64| {
+            let date = format_http_date(UNIX_EPOCH + Duration::from_secs(secs));
+            get(
+                &root,
+                "/style.css",
+                &format!("If-Modified-Since: {}\r\n", date),
+            )
+        };
+
+        let response = get(&root, "/style.css", "");
+        assert_eq!(
+            response.headers.get("Last-Modified"),
+            Some(format_http_date(modified).as_str())
+        );
+        assert_eq!(since(1_000_000_000).status, StatusCode::NOT_MODIFIED);
+        assert_eq!(since(1_000_000_001).status, StatusCode::NOT_MODIFIED);
+        let older = since(999_999_999);
+        assert_eq!(older.status, StatusCode::OK);
+        assert_eq!(body(&older), "body {}");
+        // A date that doesn't parse is ignored
+        let response = get(&root, "/style.css", "If-Modified-Since: yesterday\r\n");
+        assert_eq!(response.status, StatusCode::OK);
+    }
+
+    #[test]
+    fn a_listing_puts_directories_first_with_encoded_links() {
+        let dir = TempDir::new();
+        dir.file("b file.txt", "12345");
+        dir.file("a&b.txt", "");
+        dir.dir("z dir");
+        let listing = render_directory_listing(dir.path()).unwrap();
+
+        let links: Vec<&str> = listing
+            .split("<a href=\"")
+            .skip(1)
+            .map(|rest| rest.split('"').next().unwrap())
+            .collect();
+        assert_eq!(links, ["z%20dir/", "a%26b.txt", "b%20file.txt"]);
+        assert!(
+            listing.contains(">a&amp;b.txt</a></td><td>0</td>"),
+            "{}",
+            listing
+        );
+        assert!(
+            listing.contains(">b file.txt</a></td><td>5</td>"),
+            "{}",
+            listing
+        );
+        assert!(
+            listing.contains(">z dir/</a></td><td>-</td>"),
+            "{}",
+            listing
+        );
+    }
+
+    #[test]
+    fn a_directory_without_an_index_is_listed_only_when_enabled() {
+        let (_dir, root) = site();
+        let cache = FileCache::new(0);
+        let request = request("GET", "/empty/", "");
+        assert!(matches!(
+            serve_static_request(&root, &request, false, true, &cache),
+            Err(ServerError::Forbidden(_))
+        ));
+
+        let response = serve_static_request(&root, &request, true, true, &cache).unwrap();
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(
+            response.headers.get("Content-Type"),
+            Some("text/html; charset=utf-8")
+        );
+        assert!(body(&response).contains("<title>Index of empty</title>"));
+    }
+
+    #[test]
+    fn a_precompressed_copy_is_served_to_gzip_clients() {
+        let (dir, root) = site();
+        dir.file("style.css.gz", "pretend gzip");
+        let response = get(&root, "/style.css", "Accept-Encoding: gzip, br\r\n");
+        assert_eq!(body(&response), "pretend gzip");
+        assert_eq!(response.headers.get("Content-Encoding"), Some("gzip"));
+        assert_eq!(
+            response.headers.get("Content-Type"),
+            Some("text/css; charset=utf-8")
+        );
+        assert_eq!(response.headers.get("Vary"), Some("Accept-Encoding"));
+    }
+
+    #[test]
+    fn without_a_precompressed_copy_the_file_is_served() {
+        let (_dir, root) = site();
+        let response = get(&root, "/style.css", "Accept-Encoding: gzip\r\n");
+        assert_eq!(body(&response), "body {}");
+        assert_eq!(response.headers.get("Content-Encoding"), None);
+        assert_eq!(response.headers.get("Vary"), None);
+    }
+
+    #[test]
+    fn a_client_without_gzip_ignores_the_precompressed_copy() {
+        let (dir, root) = site();
+        dir.file("style.css.gz", "pretend gzip");
+        for headers in [
+            "",
+            "Accept-Encoding: br\r\n",
+            "Accept-Encoding: gzip;q=0\r\n",
+        ] {
+            let response = get(&root, "/style.css", headers);
+            assert_eq!(body(&response), "body {}", "{:?}", headers);
+            assert_eq!(response.headers.get("Content-Encoding"), None);
+            assert_eq!(response.headers.get("Vary"), Some("Accept-Encoding"));
+        }
+    }
+
+    #[test]
+    fn a_head_request_takes_its_length_from_metadata() {
+        let (dir, root) = site();
+        dir.file("big.txt", "x".repeat(5000));
+        let cache = FileCache::new(1 << 20);
+        let request = request("HEAD", "/big.txt", "");
+        let response = serve_static_request(&root, &request, false, true, &cache).unwrap();
+
+        assert_eq!(response.status, StatusCode::OK);
+        assert!(response.body.is_empty());
+        assert_eq!(response.content_len(), 5000);
+        // Going through the cache would have read the file
+        assert_eq!((cache.hits(), cache.misses()), (0, 0));
+
+        let mut head = Vec::new();
+        response.write_head_to(&mut head).unwrap();
+        let head = String::from_utf8(head).unwrap();
+        assert!(head.contains("\r\nContent-Length: 5000\r\n"), "{}", head);
+        assert!(head.ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn a_large_file_is_streamed_rather_than_loaded() {
+        let (dir, root) = site();
+        let contents: Vec<u8> = (0..3 * STREAM_THRESHOLD).map(|i| (i % 251) as u8).collect();
+        let path = dir.path().join("large.bin");
+        fs::write(&path, &contents).unwrap();
+
+        let mut response = get(&root, "/large.bin", "");
+        assert!(response.body.is_empty());
+        assert_eq!(response.content_len(), contents.len() as u64);
+
+        let mut out = Vec::new();
+        let written = response.write_to(&mut out).unwrap();
+        assert_eq!(written, out.len());
+        let (head, body) = out.split_at(out.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4);
+        assert!(String::from_utf8_lossy(head)
+            .contains(&format!("\r\nContent-Length: {}\r\n", contents.len())));
+        assert!(body == contents.as_slice());
+    }
+
+    #[test]
+    fn a_binary_file_is_served_byte_for_byte() {
+        let (dir, root) = site();
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR\xff\xfe\xc0\x80";
+        fs::write(dir.path().join("logo.png"), png).unwrap();
+
+        let response = get(&root, "/logo.png", "");
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.body, png);
+        assert_eq!(response.content_len(), png.len() as u64);
+        assert_eq!(response.headers.get("Content-Type"), Some("image/png"));
+
+        let page = get(&root, "/index.html", "");
+        assert_eq!(body(&page), "<h1>home</h1>");
+        assert_eq!(
+            page.headers.get("Content-Type"),
+            Some("text/html; charset=utf-8")
+        );
+    }
+
+    #[test]
+    fn a_middle_range_of_a_file_is_read_alone() {
+        let dir = TempDir::new();
+        let file = dir.file("digits.txt", "0123456789");
+        assert_eq!(read_file_range(&file, 3, 4).unwrap(), b"3456");
+        // A range running past the end stops there
+        assert_eq!(read_file_range(&file, 8, 100).unwrap(), b"89");
+        assert!(read_file_range(&file, 20, 1).unwrap().is_empty());
+        assert!(read_file_range(&dir.path().join("missing"), 0, 1).is_err());
+    }
+
+    #[test]
+    fn a_directory_without_its_slash_is_redirected() {
+        let (dir, root) = site();
+        dir.file("my docs/index.html", "mine");
+        let response = get(&root, "/docs", "");
+        assert_eq!(response.status, StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(response.headers.get("Location"), Some("/docs/"));
+
+        // The query is kept, and the path is sent back escaped
+        let response = get(&root, "/my%20docs?x=1", "");
+        assert_eq!(response.headers.get("Location"), Some("/my%20docs/?x=1"));
+
+        let request = request("GET", "/docs", "");
+        let response =
+            serve_static_request(&root, &request, false, false, &FileCache::new(0)).unwrap();
+        assert_eq!(body(&response), "<h1>docs</h1>");
+    }
+
+    #[test]
+    fn a_document_root_resolves_paths_under_it() {
+        let (dir, root) = site();
+        assert_eq!(root.path(), dir.path().canonicalize().unwrap());
+        assert_eq!(
+            root.resolve("/docs/index.html").unwrap(),
+            root.path().join("docs/index.html")
+        );
+        assert!(is_forbidden(root.resolve("/docs/../../outside")));
+        assert!(matches!(
+            DocumentRoot::new(&dir.path().join("missing")),
+            Err(ServerError::NotFound(_))
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_symlink_out_of_the_root_is_forbidden() {
+        use std::os::unix::fs::symlink;
+
+        let outside = TempDir::new();
+        let secret = outside.file("secret.txt", "keep out");
+        let (dir, root) = site();
+        symlink(&secret, dir.path().join("escape.txt")).unwrap();
+        symlink(outside.path(), dir.path().join("escape")).unwrap();
+        symlink(dir.path().join("style.css"), dir.path().join("alias.css")).unwrap();
+
+        assert!(is_forbidden(root.resolve("/escape.txt")));
+        assert!(is_forbidden(root.resolve("/escape/secret.txt")));
+        // Links that stay inside the root are followed
+        assert_eq!(
+            root.resolve("/alias.css").unwrap(),
+            root.path().join("style.css")
+        );
+    }
+
+    #[test]
+    fn only_text_types_get_a_charset() {
+        assert_eq!(content_type_for("index.html"), "text/html; charset=utf-8");
+        assert_eq!(
+            content_type_for("data.json"),
+            "application/json; charset=utf-8"
+        );
+        for binary in ["logo.png", "photo.jpg", "app.wasm", "archive.bin"] {
+            assert!(!content_type_for(binary).contains("charset"), "{}", binary);
+        }
+    }
+}
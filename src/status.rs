@@ -0,0 +1,178 @@
+use std::fmt;
+
+/// An HTTP status code.
+///
+/// Any numeric code can be represented; the ones this server knows about
+/// have named constants and their canonical reason phrases.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct StatusCode(pub u16);
+
+impl StatusCode {
+    pub const CONTINUE: StatusCode = StatusCode(100);
+    pub const OK: StatusCode = StatusCode(200);
+    pub const CREATED: StatusCode = StatusCode(201);
+    pub const ACCEPTED: StatusCode = StatusCode(202);
+    pub const NO_CONTENT: StatusCode = StatusCode(204);
+    pub const PARTIAL_CONTENT: StatusCode = StatusCode(206);
+    pub const MOVED_PERMANENTLY: StatusCode = StatusCode(301);
+    pub const FOUND: StatusCode = StatusCode(302);
+    pub const SEE_OTHER: StatusCode = StatusCode(303);
+    pub const NOT_MODIFIED: StatusCode = StatusCode(304);
+    pub const TEMPORARY_REDIRECT: StatusCode = StatusCode(307);
+    pub const PERMANENT_REDIRECT: StatusCode = StatusCode(308);
+    pub const BAD_REQUEST: StatusCode = StatusCode(400);
+    pub const UNAUTHORIZED: StatusCode = StatusCode(401);
+    pub const FORBIDDEN: StatusCode = StatusCode(403);
+    pub const NOT_FOUND: StatusCode = StatusCode(404);
+    pub const METHOD_NOT_ALLOWED: StatusCode = StatusCode(405);
+    pub const REQUEST_TIMEOUT: StatusCode = StatusCode(408);
+    pub const LENGTH_REQUIRED: StatusCode = StatusCode(411);
+    pub const PAYLOAD_TOO_LARGE: StatusCode = StatusCode(413);
+    pub const URI_TOO_LONG: StatusCode = StatusCode(414);
+    pub const UNSUPPORTED_MEDIA_TYPE: StatusCode = StatusCode(415);
+    pub const RANGE_NOT_SATISFIABLE: StatusCode = StatusCode(416);
+    pub const EXPECTATION_FAILED: StatusCode = StatusCode(417);
+    pub const TOO_MANY_REQUESTS: StatusCode = StatusCode(429);
+    pub const REQUEST_HEADER_FIELDS_TOO_LARGE: StatusCode = StatusCode(431);
+    pub const INTERNAL_SERVER_ERROR: StatusCode = StatusCode(500);
+    pub const NOT_IMPLEMENTED: StatusCode = StatusCode(501);
+    pub const BAD_GATEWAY: StatusCode = StatusCode(502);
+    pub const SERVICE_UNAVAILABLE: StatusCode = StatusCode(503);
+    pub const GATEWAY_TIMEOUT: StatusCode = StatusCode(504);
+    pub const HTTP_VERSION_NOT_SUPPORTED: StatusCode = StatusCode(505);
+
+    pub fn as_u16(&self) -> u16 {
+        self.0
+    }
+
+    /// The canonical reason phrase, or a generic one for the code's class
+    /// if the code isn't known
+    pub fn reason_phrase(&self) -> &'static str {
+        self.canonical_reason().unwrap_or(match self.0 {
+            100..=199 => "Informational",
+            200..=299 => "Success",
+            300..=399 => "Redirection",
+            400..=499 => "Client Error",
+            500..=599 => "Server Error",
+            _ => "Unknown",
+        })
+    }
+
+    fn canonical_reason(&self) -> Option<&'static str> {
+        let reason = match self.0 {
+            100 => "Continue",
+            200 => "OK",
+            201 => "Created",
+            202 => "Accepted",
+            204 => "No Content",
+            206 => "Partial Content",
+            301 => "Moved Permanently",
+            302 => "Found",
+            303 => "See Other",
+            304 => "Not Modified",
+            307 => "Temporary Redirect",
+            308 => "Permanent Redirect",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            403 => "Forbidden",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            408 => "Request Timeout",
+            411 => "Length Required",
+            413 => "Payload Too Large",
+            414 => "URI Too Long",
+            415 => "Unsupported Media Type",
+            416 => "Range Not Satisfiable",
+            417 => "Expectation Failed",
+            429 => "Too Many Requests",
+            431 => "Request Header Fields Too Large",
+            500 => "Internal Server Error",
+            501 => "Not Implemented",
+            502 => "Bad Gateway",
+            503 => "Service Unavailable",
+            504 => "Gateway Timeout",
+            505 => "HTTP Version Not Supported",
+            _ => return None,
+        };
+        Some(reason)
+    }
+
+    /// Whether a response with this status may have a body. Those to 1xx,
+    /// 204 and 304 never do, so they carry no `Content-Length` either (RFC
+    /// 7230 section 3.3).
+    pub fn allows_body(&self) -> bool {
+        !matches!(self.0, 100..=199 | 204 | 304)
+    }
+
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.0)
+    }
+
+    pub fn is_redirection(&self) -> bool {
+        (300..400).contains(&self.0)
+    }
+
+    pub fn is_client_error(&self) -> bool {
+        (400..500).contains(&self.0)
+    }
+
+    pub fn is_server_error(&self) -> bool {
+        (500..600).contains(&self.0)
+    }
+}
+
+impl fmt::Display for StatusCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.0, self.reason_phrase())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_codes_have_their_canonical_reason() {
+        let reasons = [
+            (StatusCode::OK, "OK"),
+            (StatusCode::NOT_FOUND, "Not Found"),
+            (StatusCode::BAD_REQUEST, "Bad Request"),
+            (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error"),
+            (StatusCode::SERVICE_UNAVAILABLE, "Service Unavailable"),
+            (StatusCode(413), "Payload Too Large"),
+        ];
+        for (status, reason) in reasons {
+            assert_eq!(status.reason_phrase(), reason);
+        }
+    }
+
+    #[test]
+    fn unknown_codes_get_a_generic_reason() {
+        let reasons = [
+            (StatusCode(199), "Informational"),
+            (StatusCode(299), "Success"),
+            (StatusCode(399), "Redirection"),
+            (StatusCode(418), "Client Error"),
+            (StatusCode(599), "Server Error"),
+            (StatusCode(999), "Unknown"),
+        ];
+        for (status, reason) in reasons {
+            assert_eq!(status.reason_phrase(), reason);
+        }
+    }
+
+    #[test]
+    fn displays_as_a_status_line_would() {
+        assert_eq!(StatusCode::NOT_FOUND.to_string(), "404 Not Found");
+        assert_eq!(StatusCode(418).to_string(), "418 Client Error");
+    }
+
+    #[test]
+    fn codes_know_their_class() {
+        assert!(StatusCode::NO_CONTENT.is_success());
+        assert!(StatusCode::FOUND.is_redirection());
+        assert!(StatusCode::NOT_FOUND.is_client_error());
+        assert!(StatusCode::BAD_GATEWAY.is_server_error());
+        assert!(!StatusCode::OK.is_client_error());
+    }
+}
@@ -0,0 +1,96 @@
+//! Helpers shared by the unit tests
+
+use std::{
+    env, fs,
+    io::{self, Cursor, Read, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::connection::Connection;
+
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+/// A fresh directory under the system temp directory, removed with
+/// everything in it when dropped
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new() -> TempDir {
+        let id = NEXT_DIR.fetch_add(1, Ordering::Relaxed);
+        let path = env::temp_dir().join(format!("webserver-test-{}-{}", process::id(), id));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Write `contents` to `name` under the directory, creating any
+    /// directories on the way, and return its path
+    pub fn file(&self, name: &str, contents: impl AsRef<[u8]>) -> PathBuf {
+        let path = self.path.join(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).unwrap();
+        }
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    /// Create the directory `name` under this one, and return its path
+    pub fn dir(&self, name: &str) -> PathBuf {
+        let path = self.path.join(name);
+        fs::create_dir_all(&path).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// A client connection in memory: reads come from `input`, and writes
+/// collect in `output`
+pub struct Duplex {
+    pub input: Cursor<Vec<u8>>,
+    pub output: Vec<u8>,
+}
+
+impl Duplex {
+    pub fn new(input: &str) -> Duplex {
+        Duplex {
+            input: Cursor::new(input.as_bytes().to_vec()),
+            output: Vec::new(),
+        }
+    }
+
+    /// What was written, which tests expect to be text
+    pub fn output(&self) -> String {
+        String::from_utf8(self.output.clone()).unwrap()
+    }
+}
+
+impl Read for Duplex {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl Write for Duplex {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Connection for Duplex {}
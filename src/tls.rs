@@ -0,0 +1,126 @@
+use std::{io, net::TcpStream, path::Path, sync::Arc};
+
+use rustls::{
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    ServerConfig, ServerConnection, StreamOwned,
+};
+
+/// A TCP stream carrying TLS
+pub type TlsStream = StreamOwned<ServerConnection, TcpStream>;
+
+/// The content type byte starting a TLS handshake record
+const HANDSHAKE_RECORD: u8 = 0x16;
+
+/// What a client opened a connection to speak
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Tls,
+    Plain,
+}
+
+impl Protocol {
+    /// Tell the protocols apart by the first byte a client sent. TLS
+    /// clients open with a handshake record, and no HTTP method starts with
+    /// that byte.
+    pub fn from_first_byte(byte: u8) -> Protocol {
+        if byte == HANDSHAKE_RECORD {
+            Protocol::Tls
+        } else {
+            Protocol::Plain
+        }
+    }
+}
+
+/// Wait for the first byte on `stream` and work out the protocol from it,
+/// leaving the byte to be read again. A client that closes without sending
+/// anything counts as plain.
+pub fn detect_protocol(stream: &TcpStream) -> io::Result<Protocol> {
+    let mut byte = [0; 1];
+    loop {
+        return match stream.peek(&mut byte) {
+            Ok(0) => Ok(Protocol::Plain),
+            Ok(_) => Ok(Protocol::from_first_byte(byte[0])),
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => Err(e),
+        };
+    }
+}
+
+/// Load a PEM certificate chain and private key into a rustls config
+pub fn load_config(cert_path: &Path, key_path: &Path) -> io::Result<Arc<ServerConfig>> {
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| invalid_data(cert_path, e))?;
+    let key = PrivateKeyDer::from_pem_file(key_path).map_err(|e| invalid_data(key_path, e))?;
+
+    let config =
+        ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .and_then(|builder| builder.with_no_client_auth().with_single_cert(certs, key))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    Ok(Arc::new(config))
+}
+
+/// Wrap `stream` in TLS and complete the handshake, so a client that can't
+/// negotiate is found out before any request is read
+pub fn accept(config: Arc<ServerConfig>, mut stream: TcpStream) -> io::Result<TlsStream> {
+    let mut conn = ServerConnection::new(config).map_err(io::Error::other)?;
+    while conn.is_handshaking() {
+        conn.complete_io(&mut stream)?;
+    }
+
+    Ok(StreamOwned::new(conn, stream))
+}
+
+fn invalid_data(path: &Path, e: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("failed to load {}: {}", path.display(), e),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    #[test]
+    fn a_handshake_byte_is_tls_and_a_method_is_plain() {
+        assert_eq!(Protocol::from_first_byte(0x16), Protocol::Tls);
+        for method in ["GET", "POST", "PUT", "DELETE", "HEAD", "OPTIONS", "PATCH"] {
+            assert_eq!(
+                Protocol::from_first_byte(method.as_bytes()[0]),
+                Protocol::Plain,
+                "{}",
+                method
+            );
+        }
+    }
+
+    /// What `detect_protocol` makes of `sent`, and whether the bytes could
+    /// all still be read afterwards
+    fn detect(sent: &[u8]) -> (Protocol, bool) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+        client.write_all(sent).unwrap();
+        drop(client);
+
+        let protocol = detect_protocol(&server).unwrap();
+        let mut received = Vec::new();
+        server.read_to_end(&mut received).unwrap();
+        (protocol, received == sent)
+    }
+
+    #[test]
+    fn peeking_leaves_the_first_byte_to_be_read() {
+        assert_eq!(detect(b"\x16\x03\x01\x00\x05"), (Protocol::Tls, true));
+        assert_eq!(detect(b"GET / HTTP/1.1\r\n\r\n"), (Protocol::Plain, true));
+        assert_eq!(detect(b""), (Protocol::Plain, true));
+    }
+}
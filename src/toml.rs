@@ -0,0 +1,469 @@
+use std::{error::Error, fmt, str};
+
+/// Deepest nesting of arrays `parse` accepts
+const MAX_DEPTH: usize = 32;
+
+/// A TOML value, of the kinds a config file needs. Dates and inline tables
+/// are not supported.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    /// What kind of value this is, for error messages
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Value::String(_) => "a string",
+            Value::Integer(_) => "an integer",
+            Value::Float(_) => "a float",
+            Value::Boolean(_) => "a boolean",
+            Value::Array(_) => "an array",
+        }
+    }
+}
+
+/// One `key = value` line of a document
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// The key, prefixed with its table's name and a dot, as in
+    /// `server.port`
+    pub key: String,
+    pub value: Value,
+    /// Where the key is, counting from 1
+    pub line: usize,
+}
+
+/// Why a document could not be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TomlError {
+    /// Where the problem is, counting from 1
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for TomlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for TomlError {}
+
+/// Parse a TOML document into its entries, in the order they were written.
+/// A key defined twice is an error.
+pub fn parse(input: &str) -> Result<Vec<Entry>, TomlError> {
+    let mut parser = Parser {
+        bytes: input.as_bytes(),
+        pos: 0,
+        line: 1,
+    };
+    let mut table = String::new();
+    let mut entries: Vec<Entry> = Vec::new();
+
+    loop {
+        parser.skip_blank_lines();
+        match parser.peek() {
+            None => return Ok(entries),
+            Some(b'[') => {
+                parser.pos += 1;
+                parser.skip_spaces();
+                table = parser.name()?;
+                parser.skip_spaces();
+                parser.expect(b']')?;
+            }
+            Some(_) => {
+                let line = parser.line;
+                let name = parser.key()?;
+                let key = if table.is_empty() {
+                    name
+                } else {
+                    format!("{}.{}", table, name)
+                };
+                if entries.iter().any(|entry| entry.key == key) {
+                    return Err(parser.error(&format!("{} is defined twice", key)));
+                }
+                parser.skip_spaces();
+                parser.expect(b'=')?;
+                parser.skip_spaces();
+                let value = parser.value(0)?;
+                entries.push(Entry { key, value, line });
+            }
+        }
+        parser.end_of_line()?;
+    }
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    line: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> TomlError {
+        TomlError {
+            line: self.line,
+            message: message.to_string(),
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).copied()
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), TomlError> {
+        if self.peek() != Some(byte) {
+            return Err(self.error(&format!("expected {:?}", byte as char)));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn skip_spaces(&mut self) {
+        while let Some(b' ' | b'\t') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn skip_comment(&mut self) {
+        if self.peek() == Some(b'#') {
+            while !matches!(self.peek(), None | Some(b'\n')) {
+                self.pos += 1;
+            }
+        }
+    }
+
+    /// Skip whitespace, newlines and comments
+    fn skip_blank_lines(&mut self) {
+        loop {
+            self.skip_spaces();
+            self.skip_comment();
+            match self.peek() {
+                Some(b'\n') => self.line += 1,
+                Some(b'\r') => {}
+                _ => return,
+            }
+            self.pos += 1;
+        }
+    }
+
+    /// Only a comment may follow a table header or entry on its line
+    fn end_of_line(&mut self) -> Result<(), TomlError> {
+        self.skip_spaces();
+        self.skip_comment();
+        match self.peek() {
+            None | Some(b'\n') | Some(b'\r') => Ok(()),
+            Some(_) => Err(self.error("expected the end of the line")),
+        }
+    }
+
+    fn key(&mut self) -> Result<String, TomlError> {
+        match self.peek() {
+            Some(b'"') => self.basic_string(),
+            Some(b'\'') => self.literal_string(),
+            _ => self.bare_key(),
+        }
+    }
+
+    fn bare_key(&mut self) -> Result<String, TomlError> {
+        let start = self.pos;
+        while let Some(b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'_' | b'-') = self.peek() {
+            self.pos += 1;
+        }
+        if self.pos == start {
+            return Err(self.error("expected a key"));
+        }
+        Ok(String::from_utf8_lossy(&self.bytes[start..self.pos]).into_owned())
+    }
+
+    /// A table name, as dotted keys
+    fn name(&mut self) -> Result<String, TomlError> {
+        let mut name = self.key()?;
+        loop {
+            self.skip_spaces();
+            if self.peek() != Some(b'.') {
+                return Ok(name);
+            }
+            self.pos += 1;
+            self.skip_spaces();
+            name.push('.');
+            name.push_str(&self.key()?);
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value, TomlError> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("arrays are nested too deeply"));
+        }
+        match self.peek() {
+            Some(b'"') => self.basic_string().map(Value::String),
+            Some(b'\'') => self.literal_string().map(Value::String),
+            Some(b'[') => self.array(depth),
+            Some(b't' | b'f') => self.boolean(),
+            Some(b'+' | b'-' | b'0'..=b'9') => self.number(),
+            Some(b'{') => Err(self.error("inline tables are not supported")),
+            _ => Err(self.error("expected a value")),
+        }
+    }
+
+    fn array(&mut self, depth: usize) -> Result<Value, TomlError> {
+        self.expect(b'[')?;
+        let mut items = Vec::new();
+        loop {
+            self.skip_blank_lines();
+            if self.peek() == Some(b']') {
+                self.pos += 1;
+                return Ok(Value::Array(items));
+            }
+            items.push(self.value(depth + 1)?);
+            self.skip_blank_lines();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {}
+                _ => return Err(self.error("expected ',' or ']' in an array")),
+            }
+        }
+    }
+
+    fn boolean(&mut self) -> Result<Value, TomlError> {
+        for (word, value) in [("true", true), ("false", false)] {
+            if self.bytes[self.pos..].starts_with(word.as_bytes()) {
+                self.pos += word.len();
+                return Ok(Value::Boolean(value));
+            }
+        }
+        Err(self.error("expected a value"))
+    }
+
+    fn number(&mut self) -> Result<Value, TomlError> {
+        let start = self.pos;
+        while let Some(b'+' | b'-' | b'.' | b'_' | b'e' | b'E' | b'0'..=b'9') = self.peek() {
+            self.pos += 1;
+        }
+        let text = str::from_utf8(&self.bytes[start..self.pos])
+            .unwrap_or_default()
+            .replace('_', "");
+        let invalid = || self.error(&format!("invalid number {:?}", text));
+
+        if text.contains(['.', 'e', 'E']) {
+            text.parse().map(Value::Float).map_err(|_| invalid())
+        } else {
+            text.parse().map(Value::Integer).map_err(|_| invalid())
+        }
+    }
+
+    /// A `'...'` string, taken as written
+    fn literal_string(&mut self) -> Result<String, TomlError> {
+        self.expect(b'\'')?;
+        let start = self.pos;
+        loop {
+            match self.peek() {
+                Some(b'\'') => break,
+                None | Some(b'\n') => return Err(self.error("unterminated string")),
+                Some(_) => self.pos += 1,
+            }
+        }
+        let value = String::from_utf8_lossy(&self.bytes[start..self.pos]).into_owned();
+        self.pos += 1;
+        Ok(value)
+    }
+
+    /// A `"..."` string, with its escapes decoded
+    fn basic_string(&mut self) -> Result<String, TomlError> {
+        self.expect(b'"')?;
+        let mut value = Vec::new();
+        loop {
+            let byte = match self.peek() {
+                None | Some(b'\n') => return Err(self.error("unterminated string")),
+                Some(byte) => byte,
+            };
+            self.pos += 1;
+            match byte {
+                b'"' => break,
+                b'\\' => {
+                    let escaped = match self.peek() {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'n') => '\n',
+                        Some(b't') => '\t',
+                        Some(b'r') => '\r',
+                        Some(b'u') => self.unicode_escape()?,
+                        _ => return Err(self.error("invalid escape in a string")),
+                    };
+                    self.pos += 1;
+                    let mut buf = [0; 4];
+                    value.extend_from_slice(escaped.encode_utf8(&mut buf).as_bytes());
+                }
+                byte => value.push(byte),
+            }
+        }
+        String::from_utf8(value).map_err(|_| self.error("string is not valid UTF-8"))
+    }
+
+    /// The character of a `\uXXXX` escape, leaving `pos` on its last digit
+    fn unicode_escape(&mut self) -> Result<char, TomlError> {
+        let digits = self
+            .bytes
+            .get(self.pos + 1..self.pos + 5)
+            .and_then(|digits| str::from_utf8(digits).ok())
+            .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+            .and_then(char::from_u32)
+            .ok_or_else(|| self.error("invalid \\u escape in a string"))?;
+        self.pos += 4;
+        Ok(digits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(input: &str) -> Vec<(String, Value)> {
+        parse(input)
+            .unwrap()
+            .into_iter()
+            .map(|entry| (entry.key, entry.value))
+            .collect()
+    }
+
+    fn value(input: &str) -> Value {
+        values(&format!("key = {}", input)).remove(0).1
+    }
+
+    fn error(input: &str) -> TomlError {
+        parse(input).unwrap_err()
+    }
+
+    #[test]
+    fn basic_and_literal_strings_are_parsed() {
+        assert_eq!(value("\"hello\""), Value::String("hello".to_string()));
+        assert_eq!(
+            value("'C:\\www\\n'"),
+            Value::String("C:\\www\\n".to_string())
+        );
+        assert_eq!(value("\"\""), Value::String(String::new()));
+        assert_eq!(value("\"héllo\""), Value::String("héllo".to_string()));
+    }
+
+    #[test]
+    fn escapes_in_basic_strings_are_decoded() {
+        assert_eq!(
+            value(r#""a\"b\\c\nd\te\rf""#),
+            Value::String("a\"b\\c\nd\te\rf".to_string())
+        );
+        assert_eq!(value(r#""\u00e9\u2603""#), Value::String("é☃".to_string()));
+    }
+
+    #[test]
+    fn numbers_booleans_and_arrays_are_parsed() {
+        assert_eq!(value("8080"), Value::Integer(8080));
+        assert_eq!(value("-1_000"), Value::Integer(-1000));
+        assert_eq!(value("1.5"), Value::Float(1.5));
+        assert_eq!(value("2e3"), Value::Float(2000.0));
+        assert_eq!(value("true"), Value::Boolean(true));
+        assert_eq!(value("false"), Value::Boolean(false));
+        assert_eq!(value("[]"), Value::Array(Vec::new()));
+        assert_eq!(
+            value("[\n  \"a\", # first\n  [1, 2],\n]"),
+            Value::Array(vec![
+                Value::String("a".to_string()),
+                Value::Array(vec![Value::Integer(1), Value::Integer(2)]),
+            ])
+        );
+    }
+
+    #[test]
+    fn keys_are_prefixed_with_their_table() {
+        let input = "top = 1\n[server]\nport = 2\n[ tls . \"client auth\" ]\n'on' = true\n";
+        assert_eq!(
+            values(input),
+            vec![
+                ("top".to_string(), Value::Integer(1)),
+                ("server.port".to_string(), Value::Integer(2)),
+                ("tls.client auth.on".to_string(), Value::Boolean(true)),
+            ]
+        );
+    }
+
+    #[test]
+    fn comments_and_blank_lines_are_skipped() {
+        let input = "# a config\r\n\r\n[server] # the server\r\n\tport = 1 # the port\r\n# done";
+        assert_eq!(
+            values(input),
+            vec![("server.port".to_string(), Value::Integer(1))]
+        );
+        assert_eq!(
+            value("\"# not a comment\""),
+            Value::String("# not a comment".to_string())
+        );
+        assert!(values("# nothing but a comment\n").is_empty());
+    }
+
+    #[test]
+    fn entries_record_their_line() {
+        let entries = parse("\n# comment\na = 1\n\n[t]\nb = [\n1,\n]\nc = 2\n").unwrap();
+        let lines: Vec<usize> = entries.iter().map(|entry| entry.line).collect();
+        assert_eq!(lines, [3, 6, 9]);
+    }
+
+    #[test]
+    fn malformed_documents_are_errors_with_a_line() {
+        let cases = [
+            ("a = \"open\nb = 1", 1, "unterminated string"),
+            ("\na = 'open", 2, "unterminated string"),
+            ("a = \"\\q\"", 1, "invalid escape in a string"),
+            ("a = \"\\u12\"", 1, "invalid \\u escape in a string"),
+            ("a = \"\\ud800\"", 1, "invalid \\u escape in a string"),
+            ("a = 1\na = 2", 2, "a is defined twice"),
+            ("[t]\na = 1\n[t]\na = 2", 4, "t.a is defined twice"),
+            ("a 1", 1, "expected '='"),
+            ("= 1", 1, "expected a key"),
+            ("a =", 1, "expected a value"),
+            ("a = yes", 1, "expected a value"),
+            ("a = { b = 1 }", 1, "inline tables are not supported"),
+            ("a = 1 2", 1, "expected the end of the line"),
+            ("a = 1.2.3", 1, "invalid number \"1.2.3\""),
+            (
+                "a = 99999999999999999999",
+                1,
+                "invalid number \"99999999999999999999\"",
+            ),
+            ("a = [1 2]", 1, "expected ',' or ']' in an array"),
+            ("[server", 1, "expected ']'"),
+            ("[server] port = 1", 1, "expected the end of the line"),
+        ];
+        for (input, line, message) in cases {
+            assert_eq!(
+                error(input),
+                TomlError {
+                    line,
+                    message: message.to_string()
+                },
+                "{:?}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn deeply_nested_arrays_are_an_error() {
+        let input = format!(
+            "a = {}{}",
+            "[".repeat(MAX_DEPTH + 2),
+            "]".repeat(MAX_DEPTH + 2)
+        );
+        assert_eq!(error(&input).message, "arrays are nested too deeply");
+        let input = format!("a = {}{}", "[".repeat(MAX_DEPTH), "]".repeat(MAX_DEPTH));
+        assert!(parse(&input).is_ok());
+    }
+
+    #[test]
+    fn errors_display_their_line() {
+        assert_eq!(error("\n\na b").to_string(), "line 3: expected '='");
+    }
+}
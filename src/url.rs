@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+
+use crate::request::ParseError;
+
+/// Split a request target into its path and decoded query parameters.
+///
+/// Both `+` and `%20` decode to a space. If a key is repeated, the last
+/// value wins.
+pub fn parse_query(path: &str) -> (String, HashMap<String, String>) {
+    let (path, query) = match path.find('?') {
+        Some(pos) => (&path[..pos], &path[pos + 1..]),
+        None => (path, ""),
+    };
+
+    let params = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.find('=') {
+            Some(pos) => (
+                decode_component(&pair[..pos]),
+                decode_component(&pair[pos + 1..]),
+            ),
+            None => (decode_component(pair), String::new()),
+        })
+        .collect();
+
+    (path.to_string(), params)
+}
+
+/// Decode a query string key or value, keeping the raw text if it is not
+/// validly encoded
+fn decode_component(input: &str) -> String {
+    let input = input.replace('+', " ");
+    percent_decode(&input).unwrap_or(input)
+}
+
+/// Decode `%XX` escapes, failing on a truncated or non-hex escape or if the
+/// decoded bytes are not UTF-8
+pub fn percent_decode(input: &str) -> Result<String, ParseError> {
+    let invalid = || ParseError::InvalidPercentEncoding(input.to_string());
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let byte = match bytes.get(i + 1..i + 3) {
+                Some([high, low]) => hex_pair(*high, *low).ok_or_else(invalid)?,
+                _ => return Err(invalid()),
+            };
+            decoded.push(byte);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8(decoded).map_err(|_| invalid())
+}
+
+/// Which characters [`percent_encode`] leaves as they are
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncodeSet {
+    /// Just the unreserved characters, for a single path segment or query
+    /// component
+    Component,
+    /// The unreserved characters and `/`, for a whole path
+    Path,
+}
+
+/// Encode `input` as `%XX` escapes, byte by byte, except for the characters
+/// in `set`. [`percent_decode`] gives back the original.
+pub fn percent_encode(input: &str, set: EncodeSet) -> String {
+    let mut encoded = String::with_capacity(input.len());
+    for &byte in input.as_bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            b'/' if set == EncodeSet::Path => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+fn hex_pair(high: u8, low: u8) -> Option<u8> {
+    let digit = |b: u8| (b as char).to_digit(16);
+    Some((digit(high)? * 16 + digit(low)?) as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(target: &str) -> HashMap<String, String> {
+        parse_query(target).1
+    }
+
+    #[test]
+    fn a_target_without_a_query_has_no_params() {
+        assert_eq!(
+            parse_query("/search"),
+            ("/search".to_string(), HashMap::new())
+        );
+        assert!(query("/search?").is_empty());
+    }
+
+    #[test]
+    fn a_single_pair_is_parsed() {
+        let (path, params) = parse_query("/search?q=rust");
+        assert_eq!(path, "/search");
+        assert_eq!(params.len(), 1);
+        assert_eq!(params["q"], "rust");
+    }
+
+    #[test]
+    fn several_pairs_are_parsed_and_the_last_repeat_wins() {
+        let params = query("/?a=1&b=&flag&&a=2");
+        assert_eq!(params.len(), 3);
+        assert_eq!(params["a"], "2");
+        assert_eq!(params["b"], "");
+        assert_eq!(params["flag"], "");
+    }
+
+    #[test]
+    fn percent_encoded_values_are_decoded() {
+        let params = query("/?q=hello%20world&name=a+b&caf%C3%A9=%E2%9C%93&eq=%3D");
+        assert_eq!(params["q"], "hello world");
+        assert_eq!(params["name"], "a b");
+        assert_eq!(params["café"], "✓");
+        assert_eq!(params["eq"], "=");
+    }
+
+    #[test]
+    fn badly_encoded_values_are_kept_as_sent() {
+        assert_eq!(query("/?q=100%")["q"], "100%");
+        assert_eq!(query("/?q=%zz+x")["q"], "%zz x");
+    }
+
+    #[test]
+    fn valid_escapes_are_decoded() {
+        assert_eq!(percent_decode("/a%20b/c%2Fd"), Ok("/a b/c/d".to_string()));
+        assert_eq!(percent_decode("%e2%9c%93"), Ok("✓".to_string()));
+        assert_eq!(percent_decode("plain+text"), Ok("plain+text".to_string()));
+    }
+
+    #[test]
+    fn a_trailing_percent_is_invalid() {
+        for input in ["100%", "%", "%4"] {
+            assert_eq!(
+                percent_decode(input),
+                Err(ParseError::InvalidPercentEncoding(input.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn a_non_hex_pair_is_invalid() {
+        for input in ["%ZZ", "a%G1", "%%20"] {
+            assert_eq!(
+                percent_decode(input),
+                Err(ParseError::InvalidPercentEncoding(input.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn escapes_that_are_not_utf8_are_invalid() {
+        assert_eq!(
+            percent_decode("%FF%FE"),
+            Err(ParseError::InvalidPercentEncoding("%FF%FE".to_string()))
+        );
+    }
+
+    #[test]
+    fn reserved_and_non_ascii_characters_are_encoded() {
+        assert_eq!(
+            percent_encode("my file #1?.txt", EncodeSet::Component),
+            "my%20file%20%231%3F.txt"
+        );
+        assert_eq!(percent_encode("café", EncodeSet::Component), "caf%C3%A9");
+        assert_eq!(
+            percent_encode("a-b_c.d~e", EncodeSet::Component),
+            "a-b_c.d~e"
+        );
+    }
+
+    #[test]
+    fn only_a_path_keeps_its_slashes() {
+        assert_eq!(percent_encode("/a b/c", EncodeSet::Path), "/a%20b/c");
+        assert_eq!(
+            percent_encode("/a b/c", EncodeSet::Component),
+            "%2Fa%20b%2Fc"
+        );
+    }
+
+    #[test]
+    fn encoding_round_trips_through_decoding() {
+        for input in ["plain", "sp ace", "#?&=+%", "naïve/日本語", "%20 literal"] {
+            for set in [EncodeSet::Component, EncodeSet::Path] {
+                let encoded = percent_encode(input, set);
+                assert!(encoded.is_ascii());
+                assert_eq!(percent_decode(&encoded).unwrap(), input, "{}", encoded);
+            }
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+
+use crate::{middleware::MiddlewareStack, request::Request, response::Response};
+
+/// Several sites served from one process, told apart by the request's
+/// `Host` header
+pub struct VirtualHosts {
+    /// Serves requests for any host without a site of its own
+    default: MiddlewareStack,
+    /// Keyed by lowercased host name, without a port
+    hosts: HashMap<String, MiddlewareStack>,
+}
+
+impl VirtualHosts {
+    pub fn new(default: MiddlewareStack) -> VirtualHosts {
+        VirtualHosts {
+            default,
+            hosts: HashMap::new(),
+        }
+    }
+
+    /// Serve requests for `host` with `app`, replacing any site already
+    /// added for it
+    pub fn add(&mut self, host: &str, app: MiddlewareStack) {
+        self.hosts.insert(host_name(host), app);
+    }
+
+    /// The app for requests to `host`, as sent in a `Host` header
+    pub fn get(&self, host: Option<&str>) -> &MiddlewareStack {
+        host.and_then(|host| self.hosts.get(&host_name(host)))
+            .unwrap_or(&self.default)
+    }
+
+    /// Pass `request` to the app for the host it was sent to
+    pub fn dispatch(&self, request: Request) -> Response {
+        self.get(request.headers.get("Host")).dispatch(request)
+    }
+}
+
+/// `host` lowercased, without its port or a trailing dot, so that
+/// `Example.com.:8080` finds the site for `example.com`
+fn host_name(host: &str) -> String {
+    let host = host.trim();
+    let name = if host.starts_with('[') {
+        // An IPv6 literal, whose colons aren't a port
+        host.find(']').map_or(host, |end| &host[..=end])
+    } else {
+        host.rsplit_once(':').map_or(host, |(name, _)| name)
+    };
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        request::{parse_request_head, Method},
+        router::Router,
+        status::StatusCode,
+    };
+
+    fn site(name: &'static str) -> MiddlewareStack {
+        let mut router = Router::new();
+        router.add_route(
+            Method::Get,
+            "/",
+            Box::new(move |_| Response::new(StatusCode::OK).body(name)),
+        );
+        MiddlewareStack::new(router)
+    }
+
+    fn hosts() -> VirtualHosts {
+        let mut hosts = VirtualHosts::new(site("default"));
+        hosts.add("example.com", site("example"));
+        hosts.add("Blog.Example.org", site("blog"));
+        hosts
+    }
+
+    fn served_by(hosts: &VirtualHosts, host: &str) -> String {
+        let head = format!("GET / HTTP/1.1\r\nHost: {}\r\n\r\n", host);
+        let request = parse_request_head(head.as_bytes(), 100).unwrap();
+        String::from_utf8(hosts.dispatch(request).body).unwrap()
+    }
+
+    #[test]
+    fn each_host_gets_its_own_site() {
+        let hosts = hosts();
+        assert_eq!(served_by(&hosts, "example.com"), "example");
+        assert_eq!(served_by(&hosts, "blog.example.org"), "blog");
+        assert_eq!(served_by(&hosts, "other.example"), "default");
+    }
+
+    #[test]
+    fn host_names_ignore_case_ports_and_a_trailing_dot() {
+        let hosts = hosts();
+        assert_eq!(served_by(&hosts, "EXAMPLE.com.:8080"), "example");
+        assert_eq!(host_name("[::1]:8080"), "[::1]");
+        assert_eq!(host_name(" localhost "), "localhost");
+    }
+}
@@ -0,0 +1,522 @@
+use std::{
+    env, fs,
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream},
+    path::PathBuf,
+    process,
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use webserver::{
+    config::ServerConfig,
+    server::{run_server, ServerHandle},
+};
+
+/// Start a server on a port the OS picks, with the rest of `config`
+fn start(config: ServerConfig) -> (ServerHandle, SocketAddr) {
+    let config = ServerConfig {
+        bind_addrs: vec!["127.0.0.1".to_string()],
+        port: 0,
+        ..config
+    };
+    let server = run_server(config).expect("server should start");
+    let addr = server.local_addr().expect("server should listen on TCP");
+    (server, addr)
+}
+
+/// Send `request` on a connection of its own and read the response until
+/// the server closes it
+fn send(addr: SocketAddr, request: &str) -> String {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+fn get(addr: SocketAddr, path: &str) -> String {
+    send(
+        addr,
+        &format!(
+            "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            path
+        ),
+    )
+}
+
+/// An empty directory of its own for a test to serve files from
+fn static_dir(test: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("webserver-{}-{}", test, process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn serves_the_index_page_then_shuts_down() {
+    let (server, addr) = start(ServerConfig::default());
+    assert_ne!(addr.port(), 0);
+
+    let response = get(addr, "/");
+    assert!(
+        response.starts_with("HTTP/1.1 200 OK\r\n"),
+        "unexpected response: {}",
+        response
+    );
+
+    // Returns only once the accept loop and the workers have stopped
+    server.shutdown();
+    assert!(TcpStream::connect(addr).is_err());
+}
+
+#[test]
+fn a_missing_file_is_a_404_and_the_worker_carries_on() {
+    let dir = static_dir("missing-file");
+    fs::write(dir.join("present.txt"), "here").unwrap();
+    let (server, addr) = start(ServerConfig {
+        threads: 1,
+        static_dir: Some(dir.clone()),
+        ..ServerConfig::default()
+    });
+
+    for _ in 0..3 {
+        let response = get(addr, "/absent.txt");
+        assert!(
+            response.starts_with("HTTP/1.1 404 Not Found\r\n"),
+            "{}",
+            response
+        );
+    }
+    let response = get(addr, "/present.txt");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(response.ends_with("\r\n\r\nhere"), "{}", response);
+
+    server.shutdown();
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn a_static_directory_serves_its_own_index_page() {
+    let dir = static_dir("own-index");
+    fs::write(dir.join("index.html"), "<h1>mine</h1>").unwrap();
+    let (server, addr) = start(ServerConfig {
+        static_dir: Some(dir.clone()),
+        ..ServerConfig::default()
+    });
+
+    let response = get(addr, "/");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(response.ends_with("\r\n\r\n<h1>mine</h1>"), "{}", response);
+
+    server.shutdown();
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn other_methods_on_a_static_file_are_not_allowed() {
+    let dir = static_dir("static-methods");
+    fs::write(dir.join("index.html"), "<h1>home</h1>").unwrap();
+    fs::write(dir.join("notes.txt"), "notes").unwrap();
+    let (server, addr) = start(ServerConfig {
+        static_dir: Some(dir.clone()),
+        ..ServerConfig::default()
+    });
+
+    for path in ["/notes.txt", "/"] {
+        let response = send(
+            addr,
+            &format!(
+                "POST {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\n\
+                 Connection: close\r\n\r\n",
+                path
+            ),
+        );
+        assert!(
+            response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"),
+            "{}",
+            response
+        );
+        assert!(
+            response.contains("\r\nAllow: GET, HEAD, OPTIONS\r\n"),
+            "{}",
+            response
+        );
+    }
+
+    let response = send(
+        addr,
+        "DELETE /missing.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+    );
+    assert!(
+        response.starts_with("HTTP/1.1 404 Not Found\r\n"),
+        "{}",
+        response
+    );
+
+    server.shutdown();
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn options_on_a_static_file_lists_its_methods() {
+    let dir = static_dir("static-options");
+    fs::write(dir.join("notes.txt"), "notes").unwrap();
+    let (server, addr) = start(ServerConfig {
+        static_dir: Some(dir.clone()),
+        ..ServerConfig::default()
+    });
+
+    let options = |path: &str| {
+        send(
+            addr,
+            &format!(
+                "OPTIONS {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+                path
+            ),
+        )
+    };
+    let response = options("/notes.txt");
+    assert!(
+        response.starts_with("HTTP/1.1 204 No Content\r\n"),
+        "{}",
+        response
+    );
+    assert!(
+        response.contains("\r\nAllow: GET, HEAD, OPTIONS\r\n"),
+        "{}",
+        response
+    );
+    assert!(response.ends_with("\r\n\r\n"), "{}", response);
+
+    let response = options("/missing.txt");
+    assert!(
+        response.starts_with("HTTP/1.1 404 Not Found\r\n"),
+        "{}",
+        response
+    );
+
+    server.shutdown();
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn listens_on_ipv6_loopback_alongside_ipv4() {
+    let server = run_server(ServerConfig {
+        bind_addrs: vec!["127.0.0.1".to_string(), "::1".to_string()],
+        port: 0,
+        ..ServerConfig::default()
+    })
+    .expect("server should start");
+    let v6 = *server
+        .local_addrs()
+        .iter()
+        .find(|addr| addr.is_ipv6())
+        .expect("should listen on ::1");
+
+    let response = get(v6, "/healthz");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    server.shutdown();
+}
+
+#[test]
+fn an_address_that_fails_to_bind_is_skipped() {
+    let server = run_server(ServerConfig {
+        // Not an address of this host
+        bind_addrs: vec!["192.0.2.1".to_string(), "127.0.0.1".to_string()],
+        port: 0,
+        ..ServerConfig::default()
+    })
+    .expect("server should start on the address that binds");
+    assert_eq!(server.local_addrs().len(), 1);
+
+    let response = get(server.local_addr().unwrap(), "/healthz");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    server.shutdown();
+}
+
+#[cfg(unix)]
+#[test]
+fn serves_over_a_unix_socket_and_removes_it_on_shutdown() {
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    let dir = static_dir("unix-socket");
+    let path = dir.join("server.sock");
+    // A stale socket from an earlier run is replaced
+    drop(UnixListener::bind(&path).unwrap());
+    let server = run_server(ServerConfig {
+        unix_socket: Some(path.clone()),
+        ..ServerConfig::default()
+    })
+    .expect("server should start");
+    assert!(server.local_addr().is_none());
+
+    let mut stream = UnixStream::connect(&path).unwrap();
+    stream
+        .write_all(b"GET /healthz HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+
+    server.shutdown();
+    assert!(!path.exists());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn healthz_answers_even_when_the_static_directory_is_gone() {
+    let dir = static_dir("healthz");
+    let (server, addr) = start(ServerConfig {
+        static_dir: Some(dir.clone()),
+        ..ServerConfig::default()
+    });
+    fs::remove_dir_all(&dir).unwrap();
+
+    let response = get(addr, "/healthz");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(
+        response.contains("\r\nContent-Type: text/plain; charset=utf-8\r\n"),
+        "{}",
+        response
+    );
+    assert!(response.ends_with("\r\n\r\nok"), "{}", response);
+    server.shutdown();
+}
+
+/// The value of the unlabelled sample `name` in a Prometheus scrape,
+/// after checking that every line of it is well formed
+fn sample(scrape: &str, name: &str) -> f64 {
+    let mut value = None;
+    for line in scrape.lines() {
+        if line.starts_with("# HELP ") || line.starts_with("# TYPE ") {
+            continue;
+        }
+        let (series, number) = line.rsplit_once(' ').expect(line);
+        let number: f64 = number.parse().expect(line);
+        let metric = match series.split_once('{') {
+            Some((metric, labels)) => {
+                assert!(labels.ends_with('}'), "{}", line);
+                metric
+            }
+            None => series,
+        };
+        assert!(
+            metric
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_'),
+            "{}",
+            line
+        );
+        if series == name {
+            value = Some(number);
+        }
+    }
+    value.unwrap_or_else(|| panic!("no {} in {}", name, scrape))
+}
+
+#[test]
+fn metrics_count_the_requests_served() {
+    let (server, addr) = start(ServerConfig::default());
+    get(addr, "/healthz");
+    get(addr, "/missing");
+
+    let response = get(addr, "/metrics");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    let (_, scrape) = response.split_once("\r\n\r\n").unwrap();
+    // A request is counted once it has been answered, so not the scrape
+    assert_eq!(sample(scrape, "webserver_requests_total"), 2.0);
+    assert_eq!(
+        sample(scrape, "webserver_responses_total{class=\"2xx\"}"),
+        1.0
+    );
+    assert_eq!(
+        sample(scrape, "webserver_responses_total{class=\"4xx\"}"),
+        1.0
+    );
+
+    let response = get(addr, "/metrics");
+    let (_, scrape) = response.split_once("\r\n\r\n").unwrap();
+    assert_eq!(sample(scrape, "webserver_requests_total"), 3.0);
+    server.shutdown();
+}
+
+#[test]
+fn connections_over_the_limit_get_a_503() {
+    let (server, addr) = start(ServerConfig {
+        max_connections: 1,
+        ..ServerConfig::default()
+    });
+    // Holds the only place while it waits to send a request
+    let held = TcpStream::connect(addr).unwrap();
+
+    // Answered straight away, without the server reading a request
+    let mut response = String::new();
+    TcpStream::connect(addr)
+        .unwrap()
+        .read_to_string(&mut response)
+        .unwrap();
+    assert!(
+        response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"),
+        "{}",
+        response
+    );
+
+    // The place is given back once the held connection closes
+    drop(held);
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        // Turned away clients can see a reset, as their request goes unread
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let mut response = String::new();
+        let _ = stream
+            .write_all(b"GET /healthz HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .and_then(|_| stream.read_to_string(&mut response));
+        if response.starts_with("HTTP/1.1 200 OK\r\n") {
+            break;
+        }
+        assert!(Instant::now() < deadline, "{}", response);
+        thread::sleep(Duration::from_millis(10));
+    }
+    server.shutdown();
+}
+
+#[test]
+fn a_directory_without_an_index_is_a_403_unless_listing_is_on() {
+    let dir = static_dir("listing");
+    fs::create_dir(dir.join("files")).unwrap();
+    fs::write(dir.join("files/notes.txt"), "notes").unwrap();
+
+    let (server, addr) = start(ServerConfig {
+        static_dir: Some(dir.clone()),
+        ..ServerConfig::default()
+    });
+    let response = get(addr, "/files/");
+    assert!(
+        response.starts_with("HTTP/1.1 403 Forbidden\r\n"),
+        "{}",
+        response
+    );
+    server.shutdown();
+
+    let (server, addr) = start(ServerConfig {
+        static_dir: Some(dir.clone()),
+        directory_listing: true,
+        ..ServerConfig::default()
+    });
+    let response = get(addr, "/files/");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(response.contains("<a href=\"notes.txt\">"), "{}", response);
+    server.shutdown();
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn spa_routes_fall_back_to_the_index_but_assets_do_not() {
+    let dir = static_dir("spa");
+    fs::write(dir.join("index.html"), "<div id=app></div>").unwrap();
+    let (server, addr) = start(ServerConfig {
+        static_dir: Some(dir.clone()),
+        spa_fallback: true,
+        ..ServerConfig::default()
+    });
+
+    let response = get(addr, "/users/42/settings");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(
+        response.ends_with("\r\n\r\n<div id=app></div>"),
+        "{}",
+        response
+    );
+
+    let response = get(addr, "/assets/app.js");
+    assert!(
+        response.starts_with("HTTP/1.1 404 Not Found\r\n"),
+        "{}",
+        response
+    );
+    server.shutdown();
+    fs::remove_dir_all(dir).unwrap();
+}
+
+/// Wait on `server` from another thread, failing if it hasn't stopped
+/// within a few seconds
+fn wait_stopped(server: ServerHandle) {
+    let (stopped, waited) = mpsc::channel();
+    thread::spawn(move || {
+        server.wait();
+        stopped.send(()).unwrap();
+    });
+    waited
+        .recv_timeout(Duration::from_secs(5))
+        .expect("server should stop");
+}
+
+#[test]
+fn triggering_the_shutdown_signal_stops_the_server() {
+    let (server, addr) = start(ServerConfig::default());
+    let signal = server.shutdown_signal();
+    thread::spawn(move || {
+        thread::sleep(Duration::from_millis(50));
+        signal.trigger();
+    });
+
+    wait_stopped(server);
+    assert!(TcpStream::connect(addr).is_err());
+}
+
+#[test]
+fn a_handler_can_shut_the_server_down() {
+    let (server, addr) = start(ServerConfig {
+        admin_shutdown: true,
+        ..ServerConfig::default()
+    });
+    let response = send(
+        addr,
+        "POST /admin/shutdown HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\n\r\n",
+    );
+    assert!(
+        response.starts_with("HTTP/1.1 202 Accepted\r\n"),
+        "{}",
+        response
+    );
+
+    wait_stopped(server);
+    assert!(TcpStream::connect(addr).is_err());
+}
+
+#[test]
+fn virtual_hosts_serve_their_own_roots() {
+    let (one, two) = (static_dir("vhost-one"), static_dir("vhost-two"));
+    fs::write(one.join("index.html"), "site one").unwrap();
+    fs::write(two.join("index.html"), "site two").unwrap();
+    let (server, addr) = start(ServerConfig {
+        virtual_hosts: vec![
+            ("one.test".to_string(), one.clone()),
+            ("two.test".to_string(), two.clone()),
+        ],
+        ..ServerConfig::default()
+    });
+
+    for (host, body) in [("one.test", "site one"), ("Two.Test:7878", "site two")] {
+        let response = send(
+            addr,
+            &format!(
+                "GET / HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+                host
+            ),
+        );
+        assert!(response.ends_with(body), "{}: {}", host, response);
+    }
+    let response = send(addr, "GET / HTTP/1.1\r\nConnection: close\r\n\r\n");
+    assert!(
+        response.starts_with("HTTP/1.1 400 Bad Request\r\n"),
+        "{}",
+        response
+    );
+
+    server.shutdown();
+    fs::remove_dir_all(one).unwrap();
+    fs::remove_dir_all(two).unwrap();
+}
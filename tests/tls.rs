@@ -0,0 +1,107 @@
+use std::{
+    convert::TryFrom,
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream},
+    path::PathBuf,
+    sync::Arc,
+};
+
+use rustls::{
+    pki_types::{pem::PemObject, CertificateDer, ServerName},
+    ClientConfig, ClientConnection, RootCertStore, StreamOwned,
+};
+use webserver::{
+    config::ServerConfig,
+    server::{run_server, ServerHandle},
+};
+
+/// A file in `tests/fixtures`: `cert.pem` covers `localhost` and `127.0.0.1`
+/// and is signed by the CA in `ca.pem`; both are valid for a century.
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+fn start_tls(allow_plain: bool) -> (ServerHandle, SocketAddr) {
+    let config = ServerConfig {
+        bind_addrs: vec!["127.0.0.1".to_string()],
+        port: 0,
+        tls_cert: Some(fixture("cert.pem")),
+        tls_key: Some(fixture("key.pem")),
+        tls_allow_plain: allow_plain,
+        ..ServerConfig::default()
+    };
+    let server = run_server(config).expect("server should start");
+    let addr = server.local_addr().unwrap();
+    (server, addr)
+}
+
+/// A client trusting only the test CA
+fn client_config() -> Arc<ClientConfig> {
+    let mut roots = RootCertStore::empty();
+    let ca = CertificateDer::from_pem_file(fixture("ca.pem")).unwrap();
+    roots.add(ca).unwrap();
+    let config =
+        ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+    Arc::new(config)
+}
+
+fn https_get(addr: SocketAddr, path: &str) -> String {
+    let name = ServerName::try_from("localhost").unwrap();
+    let conn = ClientConnection::new(client_config(), name).unwrap();
+    let mut stream = StreamOwned::new(conn, TcpStream::connect(addr).unwrap());
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        path
+    );
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn a_tls_client_completes_a_round_trip() {
+    let (server, addr) = start_tls(false);
+    let response = https_get(addr, "/healthz");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(response.ends_with("\r\n\r\nok"), "{}", response);
+    server.shutdown();
+}
+
+#[test]
+fn a_failed_handshake_only_closes_that_connection() {
+    let (server, addr) = start_tls(false);
+    let mut plain = TcpStream::connect(addr).unwrap();
+    plain
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let mut response = Vec::new();
+    let _ = plain.read_to_end(&mut response);
+    assert!(!response.starts_with(b"HTTP/"));
+
+    let response = https_get(addr, "/healthz");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    server.shutdown();
+}
+
+#[test]
+fn plain_http_is_served_alongside_tls_when_allowed() {
+    let (server, addr) = start_tls(true);
+    let mut plain = TcpStream::connect(addr).unwrap();
+    plain
+        .write_all(b"GET /healthz HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    plain.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+
+    let response = https_get(addr, "/healthz");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    server.shutdown();
+}